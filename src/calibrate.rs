@@ -0,0 +1,40 @@
+use log::info;
+
+use crate::{error, utils::{self, ProgramArgs}};
+
+const OVERHEAD_CALIBRATION_SAMPLES: usize = 100_000;
+
+
+#[derive(Debug, Clone)]
+pub struct CpuCalibration {
+    pub cpu: u32,
+    /// Offset aligning the clock with realtime as measured on this cpu.
+    pub offset: i64,
+    /// Median cost of reading the clock on this cpu.
+    pub clock_overhead: i64,
+}
+
+
+/// Measures the clock of `program_args` on every requested cpu without running the sampler itself.
+pub fn calibrate(program_args: &ProgramArgs) -> error::Result<Vec<CpuCalibration>> {
+    crossbeam::scope(|s| {
+        let handles: Vec<_> = program_args.cpus.iter()
+            .map(|&cpu| s.spawn(move |_| {
+                info!("Calibrating {} on cpu: {}", program_args.clock.source().name(), cpu);
+                utils::affinitize_to_cpu(cpu)?;
+                let mut clock = program_args.clock;
+                clock.align_with_realtime();
+
+                Ok(CpuCalibration {
+                    cpu,
+                    offset: clock.offset(),
+                    clock_overhead: utils::measure_clock_overhead(&clock, OVERHEAD_CALIBRATION_SAMPLES),
+                })
+            }))
+            .collect();
+
+        handles.into_iter()
+            .map(|handle| handle.join().expect("Calibration thread panicked"))
+            .collect()
+    }).unwrap()
+}
@@ -0,0 +1,117 @@
+use std::{fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{cpulist, jitter::Field, probe::IntervalProbe};
+
+
+/// Directory of the cgroup the process belongs to in the hierarchy of one controller (cgroup v1), or in the unified
+/// hierarchy (cgroup v2) when no v1 hierarchy has the controller. v1 and v2 name their files differently.
+#[derive(Debug, Clone)]
+pub struct ControllerDir {
+    pub dir: PathBuf,
+    pub unified: bool,
+}
+
+impl ControllerDir {
+    pub fn find(controller: &str) -> Option<ControllerDir> {
+        let memberships = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let mounts: Vec<(&str, &str, &str, &str)> = mountinfo.lines()
+            .filter_map(|line| {
+                let (mount, filesystem) = line.split_once(" - ")?;
+                let mut mount = mount.split_whitespace().skip(3);
+                let mut filesystem = filesystem.split_whitespace();
+                Some((mount.next()?, mount.next()?, filesystem.next()?, filesystem.nth(1)?))
+            })
+            .collect();
+        let memberships: Vec<(&str, &str)> = memberships.lines()
+            .filter_map(|line| line.split_once(':').and_then(|(_, rest)| rest.split_once(':')))
+            .collect();
+
+        let legacy = memberships.iter()
+            .find(|(controllers, _)| controllers.split(',').any(|name| name == controller))
+            .and_then(|&(_, path)| {
+                let &(root, mount_point, _, _) = mounts.iter().find(|&&(_, _, filesystem, options)| filesystem == "cgroup" && options.split(',').any(|option| option == controller))?;
+                Some(ControllerDir { dir: join_cgroup_path(mount_point, root, path), unified: false })
+            });
+
+        legacy.or_else(|| {
+            let &(_, path) = memberships.iter().find(|(controllers, _)| controllers.is_empty())?;
+            let &(root, mount_point, _, _) = mounts.iter().find(|&&(_, _, filesystem, _)| filesystem == "cgroup2")?;
+            let dir = join_cgroup_path(mount_point, root, path);
+            let enabled = fs::read_to_string(dir.join("cgroup.controllers")).ok()?.split_whitespace().any(|name| name == controller);
+            if enabled { Some(ControllerDir { dir, unified: true }) } else { None }
+        })
+    }
+
+    fn read(&self, file: &str) -> io::Result<String> {
+        fs::read_to_string(self.dir.join(file)).map(|content| content.trim().to_string())
+    }
+}
+
+
+/// Cpus the cpuset of the process allows, None when the cpuset controller is not in use.
+pub fn allowed_cpus() -> Option<Vec<u32>> {
+    let cpuset = ControllerDir::find("cpuset")?;
+    let files: &[&str] = if cpuset.unified { &["cpuset.cpus.effective"] } else { &["cpuset.effective_cpus", "cpuset.cpus"] };
+    files.iter().find_map(|file| cpulist::read_cpu_list(&cpuset.dir.join(file).to_string_lossy()).ok())
+}
+
+
+/// CFS bandwidth limit of the process as (quota, period) in microseconds, None when unlimited.
+pub fn cpu_quota() -> Option<(i64, i64)> {
+    let cpu = ControllerDir::find("cpu")?;
+    let (quota, period) = if cpu.unified {
+        let max = cpu.read("cpu.max").ok()?;
+        let (quota, period) = max.split_once(' ')?;
+        (quota.parse().ok()?, period.parse().ok()?)
+    } else {
+        (cpu.read("cpu.cfs_quota_us").ok()?.parse().ok()?, cpu.read("cpu.cfs_period_us").ok()?.parse().ok()?)
+    };
+
+    // "max" (v2) fails to parse, -1 (v1) is negative
+    if quota > 0 { Some((quota, period)) } else { None }
+}
+
+
+/// Publishes how many times and for how long the cgroup of the process got throttled by its CFS quota during every interval,
+/// as `cgroup_nr_throttled` and `cgroup_throttled_us` fields taken from cpu.stat. Covers every thread of the cgroup, not only the sampled cpu.
+pub struct CgroupThrottlingProbe {
+    cpu: ControllerDir,
+    fields: [Arc<str>; 2],
+    previous: (i64, i64),
+}
+
+impl CgroupThrottlingProbe {
+    pub fn new() -> io::Result<CgroupThrottlingProbe> {
+        let cpu = ControllerDir::find("cpu").ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cpu controller not in use"))?;
+        let mut probe = CgroupThrottlingProbe { cpu, fields: [Arc::from("cgroup_nr_throttled"), Arc::from("cgroup_throttled_us")], previous: (0, 0) };
+        probe.previous = probe.read()?;
+        Ok(probe)
+    }
+
+    fn read(&self) -> io::Result<(i64, i64)> {
+        let stat = self.cpu.read("cpu.stat")?;
+        let value = |key: &str| stat.lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(name, _)| *name == key)
+            .and_then(|(_, value)| value.parse::<i64>().ok());
+        let throttled_us = if self.cpu.unified { value("throttled_usec") } else { value("throttled_time").map(|nanos| nanos / 1_000) };
+        Ok((value("nr_throttled").unwrap_or(0), throttled_us.unwrap_or(0)))
+    }
+}
+
+impl IntervalProbe for CgroupThrottlingProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        let current = self.read().unwrap_or(self.previous);
+        fields.push(Field { name: self.fields[0].clone(), value: current.0 - self.previous.0 });
+        fields.push(Field { name: self.fields[1].clone(), value: current.1 - self.previous.1 });
+        self.previous = current;
+    }
+}
+
+
+// the path in /proc/self/cgroup is relative to the root of the hierarchy, which in a container may be below the root of the mount
+fn join_cgroup_path(mount_point: &str, root: &str, path: &str) -> PathBuf {
+    let relative = if root == "/" { path } else { path.strip_prefix(root).unwrap_or(path) };
+    Path::new(mount_point).join(relative.trim_start_matches('/'))
+}
@@ -0,0 +1,167 @@
+use std::fs;
+
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+
+
+/// Outcome of a single audit check.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub ok: bool,
+    pub subject: String,
+    pub detail: String,
+}
+
+impl Finding {
+    fn new(ok: bool, subject: impl Into<String>, detail: impl Into<String>) -> Finding {
+        Finding { ok, subject: subject.into(), detail: detail.into() }
+    }
+}
+
+
+/// Inspects the kernel command line, sysfs and cpufreq for configuration that is likely to show up as jitter on the given cpus.
+pub fn audit(cpus: &[u32]) -> Vec<Finding> {
+    let cmdline = fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    let mut findings = Vec::new();
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    findings.push(if crate::utils::tsc_is_invariant() {
+        Finding::new(true, "tsc", "invariant")
+    } else {
+        Finding::new(false, "tsc", "not invariant, rdtsc based time sources are unreliable")
+    });
+
+    if let Some(hypervisor) = crate::virt::hypervisor() {
+        findings.push(Finding::new(false, "hypervisor", format!("{}, latencies include preemption by the host (see --steal-time)", hypervisor)));
+    }
+
+    if let Some((quota, period)) = crate::cgroup::cpu_quota() {
+        findings.push(Finding::new(false, "cgroup cpu quota", format!("{}us every {}us, sampler threads get throttled", quota, period)));
+    }
+
+    if let Some(finding) = audit_turbo() {
+        findings.push(finding);
+    }
+
+    let allowed = crate::cgroup::allowed_cpus();
+    for &cpu in cpus {
+        let subject = format!("cpu{}", cpu);
+        if allowed.as_ref().is_some_and(|allowed| !allowed.contains(&cpu)) {
+            findings.push(Finding::new(false, format!("{} cpuset", subject), "not in the cpuset of the process, cannot be sampled"));
+        }
+        // cpu0 usually cannot be taken offline and has no online attribute
+        let online = read_sysfs(&format!("cpu{}/online", cpu)).map_or_else(|| fs::metadata(format!("{}/cpu{}", CPU_SYSFS, cpu)).is_ok(), |online| online == "1");
+        if !online {
+            findings.push(Finding::new(false, subject, "offline or not present"));
+            continue;
+        }
+
+        for (parameter, purpose) in [("isolcpus", "kept away from the scheduler"), ("nohz_full", "running tickless"), ("rcu_nocbs", "offloading rcu callbacks")] {
+            let isolated = kernel_parameter(&cmdline, parameter).is_some_and(|cpu_list| kernel_cpu_list(cpu_list).contains(&cpu));
+            findings.push(Finding::new(isolated, format!("{} {}", subject, parameter), if isolated { purpose.to_string() } else { format!("not in {}=, not {}", parameter, purpose) }));
+        }
+
+        findings.push(audit_cstates(&cmdline, cpu));
+
+        let siblings: Vec<u32> = read_sysfs(&format!("cpu{}/topology/thread_siblings_list", cpu))
+            .map(|siblings| kernel_cpu_list(&siblings).into_iter().filter(|&sibling| sibling != cpu).collect())
+            .unwrap_or_default();
+        findings.push(if siblings.is_empty() {
+            Finding::new(true, format!("{} smt", subject), "no active hyperthread sibling")
+        } else {
+            Finding::new(false, format!("{} smt", subject), format!("shares its core with cpu {:?}", siblings))
+        });
+
+        findings.push(match crate::interrupts::irqs_routed_to(&[cpu]) {
+            Ok(irqs) if irqs.is_empty() => Finding::new(true, format!("{} irqs", subject), "no interrupt routed here"),
+            Ok(irqs) => Finding::new(false, format!("{} irqs", subject), format!("{} interrupts routed here: {}", irqs.len(), irqs.iter()
+                .map(|routed_irq| if routed_irq.actions.is_empty() { routed_irq.irq.to_string() } else { format!("{} ({})", routed_irq.irq, routed_irq.actions.join(",")) })
+                .collect::<Vec<_>>()
+                .join(", "))),
+            Err(err) => Finding::new(false, format!("{} irqs", subject), format!("unable to read interrupt affinity: {}", err)),
+        });
+
+        if let Some(governor) = read_sysfs(&format!("cpu{}/cpufreq/scaling_governor", cpu)) {
+            findings.push(Finding::new(governor == "performance", format!("{} governor", subject), governor));
+        }
+    }
+
+    findings
+}
+
+
+fn audit_cstates(cmdline: &str, cpu: u32) -> Finding {
+    let subject = format!("cpu{} c-states", cpu);
+    let limit = |parameter| kernel_parameter(cmdline, parameter).and_then(|value| value.parse::<u32>().ok());
+    if kernel_parameter(cmdline, "idle") == Some("poll") {
+        return Finding::new(true, subject, "idle=poll");
+    }
+    if let Some(max_cstate) = limit("intel_idle.max_cstate").or_else(|| limit("processor.max_cstate")).filter(|&max_cstate| max_cstate <= 1) {
+        return Finding::new(true, subject, format!("limited to C{} on the kernel command line", max_cstate));
+    }
+
+    let mut deep_states = Vec::new();
+    for state in 1.. {
+        let state_dir = format!("cpu{}/cpuidle/state{}", cpu, state);
+        let name = match read_sysfs(&format!("{}/name", state_dir)) {
+            Some(name) => name,
+            None => break,
+        };
+        let enabled = read_sysfs(&format!("{}/disable", state_dir)).is_none_or(|disabled| disabled == "0");
+        let latency = read_sysfs(&format!("{}/latency", state_dir)).unwrap_or_default();
+        if enabled {
+            deep_states.push(format!("{} ({}us exit latency)", name, latency));
+        }
+    }
+
+    if deep_states.is_empty() {
+        Finding::new(true, subject, "no idle states beyond polling enabled")
+    } else {
+        Finding::new(false, subject, format!("enabled: {}", deep_states.join(", ")))
+    }
+}
+
+
+fn audit_turbo() -> Option<Finding> {
+    if let Some(no_turbo) = read_sysfs("intel_pstate/no_turbo") {
+        let disabled = no_turbo == "1";
+        return Some(Finding::new(disabled, "turbo", if disabled { "disabled" } else { "enabled, core frequency varies with load and temperature" }));
+    }
+
+    read_sysfs("cpufreq/boost").map(|boost| {
+        let disabled = boost == "0";
+        Finding::new(disabled, "turbo", if disabled { "disabled" } else { "enabled, core frequency varies with load and temperature" })
+    })
+}
+
+
+fn read_sysfs(path: &str) -> Option<String> {
+    fs::read_to_string(format!("{}/{}", CPU_SYSFS, path)).ok().map(|value| value.trim().to_string())
+}
+
+
+/// Value of the last `name=value` occurrence on the kernel command line.
+fn kernel_parameter<'a>(cmdline: &'a str, name: &str) -> Option<&'a str> {
+    cmdline.trim().split(' ')
+        .filter_map(|parameter| parameter.split_once('='))
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .next_back()
+}
+
+
+/// Cpus of a kernel cpu list such as `isolcpus=managed_irq,domain,2-5,8`, ignoring any flags.
+fn kernel_cpu_list(cpu_list: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for element in cpu_list.split(',') {
+        match element.split_once('-') {
+            Some((begin, end)) => {
+                if let (Ok(begin), Ok(end)) = (begin.parse::<u32>(), end.parse::<u32>()) {
+                    cpus.extend(begin..=end);
+                }
+            },
+            None => cpus.extend(element.parse::<u32>().ok()),
+        }
+    }
+
+    cpus
+}
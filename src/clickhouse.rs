@@ -0,0 +1,124 @@
+use std::{io, time::Duration};
+
+use isahc::{HttpClient, Request, prelude::*};
+use log::info;
+use serde_json::json;
+
+use crate::{jitter::Jitter, sink::Sink, utils::{ClickhouseFormat, ProgramArgs}};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// Bulk-inserts samples through the ClickHouse HTTP interface, one row per interval or event:
+/// `ts Int64 (ns since the epoch), host String, cpu UInt32, measurement String, latency Int64 (ns), fields Map(String, Int64)`.
+pub struct ClickhouseSink {
+    client: HttpClient,
+    url: String,
+    table: String,
+    format: ClickhouseFormat,
+    credentials: Option<(String, String)>,
+    local_hostname: String,
+}
+
+impl ClickhouseSink {
+    pub fn create(url: &str, program_args: &ProgramArgs) -> io::Result<ClickhouseSink> {
+        let sink = ClickhouseSink {
+            client: HttpClient::builder().timeout(REQUEST_TIMEOUT).build()?,
+            url: url.trim_end_matches('/').to_string(),
+            table: program_args.clickhouse_table.clone(),
+            format: program_args.clickhouse_format,
+            credentials: program_args.clickhouse_user.as_ref().map(|user| {
+                (user.clone(), program_args.clickhouse_password.as_ref().map(|password| password.0.clone()).unwrap_or_default())
+            }),
+            local_hostname: program_args.local_hostname.clone(),
+        };
+        if program_args.clickhouse_create_table {
+            info!("Creating table {} unless it exists", sink.table);
+            sink.execute(&format!("CREATE TABLE IF NOT EXISTS {} (ts Int64, time DateTime64(9) MATERIALIZED fromUnixTimestamp64Nano(ts), \
+                host LowCardinality(String), cpu UInt32, measurement LowCardinality(String), latency Int64, fields Map(String, Int64)) \
+                ENGINE = MergeTree ORDER BY (host, cpu, ts)", sink.table), Vec::default())?;
+        }
+
+        Ok(sink)
+    }
+
+    fn insert(&self, cpu: u32, measurement: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let (format, rows) = match self.format {
+            ClickhouseFormat::JsonEachRow => ("JSONEachRow", self.json_rows(cpu, measurement, data_points)),
+            ClickhouseFormat::RowBinary => ("RowBinary", self.binary_rows(cpu, measurement, data_points)),
+        };
+
+        self.execute(&format!("INSERT INTO {} (ts, host, cpu, measurement, latency, fields) FORMAT {}", self.table, format), rows)
+    }
+
+    fn json_rows(&self, cpu: u32, measurement: &str, data_points: &[Jitter]) -> Vec<u8> {
+        let mut rows = String::default();
+        for data_point in data_points {
+            let fields: serde_json::Map<String, serde_json::Value> = data_point.fields.iter().map(|field| (field.name.to_string(), json!(field.value))).collect();
+            rows.push_str(&json!({ "ts": data_point.ts, "host": self.local_hostname, "cpu": cpu, "measurement": measurement, "latency": data_point.latency, "fields": fields }).to_string());
+            rows.push('\n');
+        }
+
+        rows.into_bytes()
+    }
+
+    fn binary_rows(&self, cpu: u32, measurement: &str, data_points: &[Jitter]) -> Vec<u8> {
+        let mut rows = Vec::with_capacity(data_points.len() * 64);
+        for data_point in data_points {
+            rows.extend(data_point.ts.to_le_bytes());
+            write_string(&mut rows, &self.local_hostname);
+            rows.extend(cpu.to_le_bytes());
+            write_string(&mut rows, measurement);
+            rows.extend(data_point.latency.to_le_bytes());
+            write_varint(&mut rows, data_point.fields.len());
+            for field in &data_point.fields {
+                write_string(&mut rows, &field.name);
+                rows.extend(field.value.to_le_bytes());
+            }
+        }
+
+        rows
+    }
+
+    fn execute(&self, query: &str, body: Vec<u8>) -> io::Result<()> {
+        let url = format!("{}/?{}", self.url, form_urlencoded::Serializer::new(String::new()).append_pair("query", query).finish());
+        let mut request = Request::post(url.as_str());
+        if let Some((user, password)) = &self.credentials {
+            request = request.header("X-ClickHouse-User", user.as_str()).header("X-ClickHouse-Key", password.as_str());
+        }
+
+        let request = request.body(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut response = self.client.send(request)?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ClickHouse responded with {}: {}", response.status(), response.text().unwrap_or_default().trim())))
+        }
+    }
+}
+
+
+/// RowBinary strings are prefixed with their length as an unsigned LEB128 varint.
+fn write_string(rows: &mut Vec<u8>, value: &str) {
+    write_varint(rows, value.len());
+    rows.extend(value.as_bytes());
+}
+
+
+fn write_varint(rows: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        rows.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    rows.push(value as u8);
+}
+
+impl Sink for ClickhouseSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.insert(cpu, "jitter", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.insert(cpu, measurement, events)
+    }
+}
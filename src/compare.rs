@@ -0,0 +1,202 @@
+use std::{collections::BTreeMap, fs, io::{self, Write}};
+
+use crate::{influx, jitter::Jitter, sampler::CpuJitter, sqlite, summary::{self, Summary}, utils::ProgramArgs};
+
+/// Percentiles a regression fails the comparison on; a single worst latency is too noisy to gate on and only gets reported.
+const GATED: [&str; 3] = ["p50", "p99", "p99.99"];
+
+
+/// Where the results of one of the compared runs come from.
+#[derive(Debug, Clone)]
+pub enum RunSource {
+    /// A summary printed with `--summary-format json`, or a csv or jsonl results file.
+    File(String),
+    /// A run recorded by the sqlite output, given as `sqlite:<path>#<run id>`.
+    Sqlite { path: String, run_id: String },
+    /// A run published to Influx, given as `influx:<run id>`.
+    Influx { run_id: String },
+}
+
+impl RunSource {
+    pub fn parse(spec: &str) -> Result<RunSource, String> {
+        if let Some(run_id) = spec.strip_prefix("influx:") {
+            return Ok(RunSource::Influx { run_id: run_id.to_string() });
+        }
+        if let Some(database) = spec.strip_prefix("sqlite:") {
+            let (path, run_id) = database.rsplit_once('#').ok_or_else(|| format!("expected sqlite:<path>#<run id>, got: {}", spec))?;
+            return Ok(RunSource::Sqlite { path: path.to_string(), run_id: run_id.to_string() });
+        }
+
+        Ok(RunSource::File(spec.to_string()))
+    }
+
+    /// Per-cpu summaries of the run. Only json summaries of runs with histograms cover every latency,
+    /// every other source only has the worst latency of each interval.
+    pub fn load(&self, program_args: &ProgramArgs) -> io::Result<Vec<Summary>> {
+        let samples = match self {
+            RunSource::File(path) => {
+                let content = fs::read_to_string(path)?;
+                match content.trim_start().chars().next() {
+                    Some('[') => return parse_summary_json(&content),
+                    Some('{') => parse_jsonl(&content)?,
+                    _ => parse_csv(&content)?,
+                }
+            },
+            RunSource::Sqlite { path, run_id } => sqlite::load_run(path, run_id)?,
+            RunSource::Influx { run_id } => influx::query_run(program_args, run_id)?,
+        };
+
+        let mut by_cpu: BTreeMap<u32, Vec<Jitter>> = BTreeMap::new();
+        for (cpu, sample) in samples {
+            by_cpu.entry(cpu).or_default().push(sample);
+        }
+        Ok(by_cpu.into_iter()
+            .filter_map(|(cpu, samples)| summary::summarize(&CpuJitter { cpu, samples, outliers: Vec::new(), top_latencies: Vec::new(), discontinuities: Vec::new(), round_trips: Vec::new(), histogram_buckets: Vec::new(), histogram: None }))
+            .collect())
+    }
+}
+
+
+/// How the candidate run of one cpu fared against the baseline; a cpu missing from either run fails.
+#[derive(Debug, Clone)]
+pub struct CpuComparison {
+    pub cpu: u32,
+    pub baseline: Option<Summary>,
+    pub candidate: Option<Summary>,
+    /// Gated percentiles that got worse by more than allowed, with the change in percent.
+    pub regressions: Vec<(&'static str, f64)>,
+}
+
+impl CpuComparison {
+    pub fn passed(&self) -> bool {
+        self.baseline.is_some() && self.candidate.is_some() && self.regressions.is_empty()
+    }
+}
+
+
+pub fn compare(baseline: &[Summary], candidate: &[Summary], max_regression_percent: f64) -> Vec<CpuComparison> {
+    let mut cpus: Vec<u32> = baseline.iter().chain(candidate).map(|summary| summary.cpu).collect();
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    cpus.into_iter()
+        .map(|cpu| {
+            let baseline = baseline.iter().find(|summary| summary.cpu == cpu).cloned();
+            let candidate = candidate.iter().find(|summary| summary.cpu == cpu).cloned();
+            let regressions = match (&baseline, &candidate) {
+                (Some(baseline), Some(candidate)) => metrics(baseline).iter().zip(metrics(candidate).iter())
+                    .filter(|((name, _), _)| GATED.contains(name))
+                    .map(|(&(name, before), &(_, after))| (name, change_percent(before, after)))
+                    .filter(|&(_, change)| change > max_regression_percent)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            CpuComparison { cpu, baseline, candidate, regressions }
+        })
+        .collect()
+}
+
+
+pub fn write_table(writer: &mut impl Write, comparisons: &[CpuComparison], max_regression_percent: f64) -> io::Result<()> {
+    writeln!(writer, "{:>4}  {:<7} {:>12} {:>12} {:>12} {:>9}", "cpu", "metric", "baseline", "candidate", "delta", "change")?;
+    for comparison in comparisons {
+        let (baseline, candidate) = match (&comparison.baseline, &comparison.candidate) {
+            (Some(baseline), Some(candidate)) => (baseline, candidate),
+            (None, _) => {
+                writeln!(writer, "{:>4}  missing from the baseline", comparison.cpu)?;
+                continue;
+            },
+            (_, None) => {
+                writeln!(writer, "{:>4}  missing from the candidate", comparison.cpu)?;
+                continue;
+            },
+        };
+        for (&(name, before), &(_, after)) in metrics(baseline).iter().zip(metrics(candidate).iter()) {
+            let regressed = comparison.regressions.iter().any(|&(regressed, _)| regressed == name);
+            writeln!(writer, "{:>4}  {:<7} {:>12} {:>12} {:>+12} {:>+8.1}%{}", comparison.cpu, name, before, after, after - before, change_percent(before, after),
+                     if regressed { "  regression" } else { "" })?;
+        }
+        if baseline.all_latencies != candidate.all_latencies {
+            writeln!(writer, "{:>4}  only one of the runs covers all latencies, the other its interval maxima: not comparable", comparison.cpu)?;
+        }
+    }
+
+    let failed: Vec<String> = comparisons.iter().filter(|comparison| !comparison.passed()).map(|comparison| comparison.cpu.to_string()).collect();
+    if failed.is_empty() {
+        writeln!(writer, "PASS: no percentile of {} regressed by more than {}%", GATED.join(", "), max_regression_percent)?;
+    } else {
+        writeln!(writer, "FAIL: cpus {} regressed by more than {}% or are missing from one of the runs", failed.join(","), max_regression_percent)?;
+    }
+
+    writer.flush()
+}
+
+
+fn metrics(summary: &Summary) -> [(&'static str, i64); 4] {
+    [("p50", summary.p50), ("p99", summary.p99), ("p99.99", summary.p9999), ("max", summary.max)]
+}
+
+
+fn change_percent(before: i64, after: i64) -> f64 {
+    if before == 0 {
+        return if after == 0 { 0.0 } else { f64::INFINITY };
+    }
+
+    (after - before) as f64 * 100.0 / before as f64
+}
+
+
+/// Summaries as printed by `--summary-format json`, skipping the entries of cpus that failed.
+fn parse_summary_json(content: &str) -> io::Result<Vec<Summary>> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(entries.iter()
+        .filter(|entry| entry.get("failed").is_none())
+        .filter_map(|entry| Some(Summary {
+            cpu: entry["cpu"].as_u64()? as u32,
+            intervals: entry["intervals"].as_u64()? as usize,
+            all_latencies: entry["of"] == "latencies",
+            min: entry["min"].as_i64()?,
+            mean: entry["mean"].as_f64()?,
+            p50: entry["p50"].as_i64()?,
+            p99: entry["p99"].as_i64()?,
+            p9999: entry["p99.99"].as_i64()?,
+            max: entry["max"].as_i64()?,
+            worst_ts: entry["worst_ts"].as_i64()?,
+        }))
+        .collect())
+}
+
+
+/// Interval samples of a jsonl results file, leaving out events and run metadata, which carry a type.
+fn parse_jsonl(content: &str) -> io::Result<Vec<(u32, Jitter)>> {
+    let mut samples = Vec::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let line: serde_json::Value = serde_json::from_str(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if line.get("type").is_some() {
+            continue;
+        }
+        if let (Some(cpu), Some(ts), Some(latency)) = (line["cpu"].as_u64(), line["ts"].as_i64(), line["latency"].as_i64()) {
+            samples.push((cpu as u32, Jitter { ts, latency, fields: Vec::new() }));
+        }
+    }
+
+    Ok(samples)
+}
+
+
+fn parse_csv(content: &str) -> io::Result<Vec<(u32, Jitter)>> {
+    let mut lines = content.lines();
+    if !lines.next().is_some_and(|header| header.starts_with("timestamp,cpu,latency")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a json summary, nor a jsonl or csv results file"));
+    }
+
+    lines.filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let columns: Vec<&str> = line.splitn(4, ',').collect();
+            match (columns.first().and_then(|ts| ts.parse().ok()), columns.get(1).and_then(|cpu| cpu.parse().ok()), columns.get(2).and_then(|latency| latency.parse().ok())) {
+                (Some(ts), Some(cpu), Some(latency)) => Ok((cpu, Jitter { ts, latency, fields: Vec::new() })),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid csv row: {}", line))),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,176 @@
+use std::{fs, io, sync::Arc};
+
+use log::{info, warn};
+
+use crate::{error::{self, JitterError}, jitter::Field, probe::IntervalProbe};
+
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const MSR_MPERF: u64 = 0xe7;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const MSR_APERF: u64 = 0xe8;
+
+
+/// Publishes the frequency of the sampled cpu with every interval: `cpu_frequency_khz` as last set by cpufreq
+/// (scaling_cur_freq) and, on x86 with the msr driver loaded, `effective_frequency_khz` averaged over the interval
+/// from the APERF/MPERF counters, which also catches turbo drops and thermal throttling.
+pub struct FrequencyProbe {
+    scaling_cur_freq: Option<String>,
+    scaling_field: Arc<str>,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    aperf_mperf: Option<AperfMperf>,
+}
+
+impl FrequencyProbe {
+    pub fn new(cpu: u32) -> io::Result<FrequencyProbe> {
+        let scaling_cur_freq = format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_cur_freq", cpu);
+        let scaling_cur_freq = fs::metadata(&scaling_cur_freq).ok().map(|_| scaling_cur_freq);
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let aperf_mperf = match AperfMperf::open(cpu) {
+            Ok(aperf_mperf) => Some(aperf_mperf),
+            Err(err) => {
+                log::info!("APERF/MPERF of cpu: {} unavailable ({}), effective frequency will not be published", cpu, err);
+                None
+            }
+        };
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let available = scaling_cur_freq.is_some() || aperf_mperf.is_some();
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        let available = scaling_cur_freq.is_some();
+
+        if !available {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "neither cpufreq nor APERF/MPERF is available"));
+        }
+
+        Ok(FrequencyProbe {
+            scaling_cur_freq,
+            scaling_field: Arc::from("cpu_frequency_khz"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            aperf_mperf,
+        })
+    }
+}
+
+impl IntervalProbe for FrequencyProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        if let Some(path) = &self.scaling_cur_freq {
+            let khz = fs::read_to_string(path).ok().and_then(|khz| khz.trim().parse::<i64>().ok()).unwrap_or(0);
+            fields.push(Field { name: self.scaling_field.clone(), value: khz });
+        }
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if let Some(aperf_mperf) = self.aperf_mperf.as_mut() {
+            fields.push(Field { name: aperf_mperf.field.clone(), value: aperf_mperf.effective_khz() });
+        }
+    }
+}
+
+
+/// APERF counts at the actual and MPERF at the nominal (TSC) frequency while the cpu is in C0, so the ratio of
+/// their deltas scales the TSC frequency to the average frequency the cpu actually ran at.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+struct AperfMperf {
+    msr: fs::File,
+    tsc_khz: f64,
+    previous: (u64, u64),
+    field: Arc<str>,
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl AperfMperf {
+    fn open(cpu: u32) -> io::Result<AperfMperf> {
+        let msr = fs::File::open(format!("/dev/cpu/{}/msr", cpu))?;
+        let mut aperf_mperf = AperfMperf { msr, tsc_khz: crate::utils::detect_tsc_frequency() * 1e6, previous: (0, 0), field: Arc::from("effective_frequency_khz") };
+        aperf_mperf.previous = aperf_mperf.read()?;
+        Ok(aperf_mperf)
+    }
+
+    fn read(&self) -> io::Result<(u64, u64)> {
+        use std::os::unix::fs::FileExt;
+
+        let mut aperf = [0u8; 8];
+        let mut mperf = [0u8; 8];
+        self.msr.read_exact_at(&mut aperf, MSR_APERF)?;
+        self.msr.read_exact_at(&mut mperf, MSR_MPERF)?;
+        Ok((u64::from_le_bytes(aperf), u64::from_le_bytes(mperf)))
+    }
+
+    fn effective_khz(&mut self) -> i64 {
+        let current = match self.read() {
+            Ok(current) => current,
+            Err(_) => return 0,
+        };
+        let aperf = current.0.wrapping_sub(self.previous.0);
+        let mperf = current.1.wrapping_sub(self.previous.1);
+        self.previous = current;
+
+        if mperf == 0 {
+            0
+        } else {
+            (self.tsc_khz * aperf as f64 / mperf as f64) as i64
+        }
+    }
+}
+
+
+/// Switches the sampled cpus to the performance governor and/or disables turbo for as long as it lives, putting
+/// back whatever was configured before when dropped.
+pub struct FrequencyTuning {
+    previous: Vec<(String, String)>,
+}
+
+impl FrequencyTuning {
+    pub fn apply(cpus: &[u32], performance_governor: bool, disable_turbo: bool) -> error::Result<FrequencyTuning> {
+        let mut tuning = FrequencyTuning { previous: Vec::new() };
+        if performance_governor {
+            for &cpu in cpus {
+                tuning.set(format!("{}/cpu{}/cpufreq/scaling_governor", CPU_SYSFS, cpu), "performance")?;
+            }
+        }
+
+        if disable_turbo {
+            let no_turbo = format!("{}/intel_pstate/no_turbo", CPU_SYSFS);
+            let boost = format!("{}/cpufreq/boost", CPU_SYSFS);
+            if fs::metadata(&no_turbo).is_ok() {
+                tuning.set(no_turbo, "1")?;
+            } else if fs::metadata(&boost).is_ok() {
+                tuning.set(boost, "0")?;
+            } else {
+                return Err(JitterError::Frequency { path: no_turbo, source: io::Error::new(io::ErrorKind::NotFound, "neither intel_pstate nor cpufreq boost control is available") });
+            }
+        }
+
+        Ok(tuning)
+    }
+
+    fn set(&mut self, path: String, value: &str) -> error::Result<()> {
+        let previous = match fs::read_to_string(&path) {
+            Ok(previous) => previous.trim().to_string(),
+            Err(source) => return Err(JitterError::Frequency { path, source }),
+        };
+        // cpus sharing a cpufreq policy share its governor as well
+        if previous == value {
+            return Ok(());
+        }
+
+        if let Err(source) = fs::write(&path, value) {
+            return Err(JitterError::Frequency { path, source });
+        }
+        info!("Set {} to {} for the run, was {}", path, value, previous);
+        self.previous.push((path, previous));
+        Ok(())
+    }
+}
+
+impl Drop for FrequencyTuning {
+    fn drop(&mut self) {
+        for (path, previous) in self.previous.drain(..).rev() {
+            match fs::write(&path, &previous) {
+                Ok(()) => info!("Restored {} to {}", path, previous),
+                Err(err) => warn!(phase = "setup", error:% = err; "Unable to restore {} to {}: {}", path, previous, err),
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{fs, io};
+
+use log::warn;
+
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+
+
+/// Cpus of a list such as `1,4-6,8-15:2`, where `all` and `isolated` stand for the online and the isolated (isolcpus)
+/// cpus and `!` excludes a cpu or range from the rest of the list. Cpus named explicitly have to be online, offline
+/// ones are only skipped when they come from `all` or `isolated`. Duplicates and reversed ranges are rejected.
+pub fn parse_cpu_list(cpu_list: &str) -> io::Result<Vec<u32>> {
+    let online = sysfs_cpu_list("online")?;
+    let mut cpus: Vec<u32> = Vec::default();
+    let mut excluded: Vec<u32> = Vec::default();
+
+    for element in cpu_list.trim().split(',').map(str::trim) {
+        let listed = match element {
+            "all" => sysfs_cpu_list("present")?.into_iter().filter(|cpu| is_online_or_skipped(&online, *cpu)).collect(),
+            "isolated" => sysfs_cpu_list("isolated")?.into_iter().filter(|cpu| is_online_or_skipped(&online, *cpu)).collect(),
+            _ => match element.strip_prefix('!') {
+                Some(range) => {
+                    excluded.extend(parse_cpu_range(range)?);
+                    continue;
+                },
+                None => {
+                    let range = parse_cpu_range(element)?;
+                    if let Some(cpu) = range.iter().find(|cpu| !online.contains(cpu)) {
+                        return Err(invalid(format!("cpu {} is not online (online cpus: {})", cpu, format_cpu_list(&online))));
+                    }
+                    range
+                },
+            },
+        };
+
+        for cpu in listed {
+            if cpus.contains(&cpu) {
+                return Err(invalid(format!("cpu {} is listed more than once", cpu)));
+            }
+            cpus.push(cpu);
+        }
+    }
+
+    cpus.retain(|cpu| !excluded.contains(cpu));
+    if cpus.is_empty() {
+        return Err(invalid(format!("no cpus left to sample in: {}", cpu_list)));
+    }
+    Ok(cpus)
+}
+
+
+/// Compact form of a cpu list, eg: `0-3,8`.
+pub fn format_cpu_list(cpus: &[u32]) -> String {
+    let mut ranges: Vec<String> = Vec::default();
+    let mut i = 0;
+    while i < cpus.len() {
+        let begin = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        ranges.push(if cpus[i] == begin { begin.to_string() } else { format!("{}-{}", begin, cpus[i]) });
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+
+/// A single cpu or an inclusive range, optionally taking every n-th cpu of it, eg: `0-31:2`.
+fn parse_cpu_range(element: &str) -> io::Result<Vec<u32>> {
+    if element.is_empty() {
+        return Err(invalid("empty element, expected a cpu, a range of cpus, all or isolated".to_string()));
+    }
+
+    let (range, stride) = match element.split_once(':') {
+        Some((range, stride)) => match stride.parse::<usize>() {
+            Ok(stride) if stride > 0 => (range, stride),
+            _ => return Err(invalid(format!("stride of {} is not a positive number", element))),
+        },
+        None => (element, 1),
+    };
+    let parse_cpu = |cpu: &str| cpu.trim().parse::<u32>().map_err(|_| invalid(format!("{} is not a cpu number", cpu)));
+
+    match range.split_once('-') {
+        Some((begin, end)) => {
+            let (begin, end) = (parse_cpu(begin)?, parse_cpu(end)?);
+            if begin > end {
+                return Err(invalid(format!("range {} is reversed, did you mean {}-{}?", range, end, begin)));
+            }
+            Ok((begin..=end).step_by(stride).collect())
+        },
+        None if stride == 1 => Ok(vec![parse_cpu(range)?]),
+        None => Err(invalid(format!("stride only applies to a range of cpus, not to {}", element))),
+    }
+}
+
+
+/// Online hyperthread siblings of `cpu`, not counting `cpu` itself.
+pub fn smt_siblings(cpu: u32) -> io::Result<Vec<u32>> {
+    Ok(sysfs_cpu_list(&format!("cpu{}/topology/thread_siblings_list", cpu))?.into_iter().filter(|&sibling| sibling != cpu).collect())
+}
+
+
+/// Cpus isolated from the scheduler with isolcpus.
+pub fn isolated_cpus() -> io::Result<Vec<u32>> {
+    sysfs_cpu_list("isolated")
+}
+
+
+/// Cpus running tickless with nohz_full.
+pub fn nohz_full_cpus() -> io::Result<Vec<u32>> {
+    sysfs_cpu_list("nohz_full")
+}
+
+
+pub fn online_cpus() -> io::Result<Vec<u32>> {
+    sysfs_cpu_list("online")
+}
+
+
+fn is_online_or_skipped(online: &[u32], cpu: u32) -> bool {
+    let is_online = online.contains(&cpu);
+    if !is_online {
+        warn!("Skipping offline cpu: {}", cpu);
+    }
+    is_online
+}
+
+
+/// One of the cpu lists the kernel publishes in /sys/devices/system/cpu (online, present, isolated, ...).
+fn sysfs_cpu_list(name: &str) -> io::Result<Vec<u32>> {
+    read_cpu_list(&format!("{}/{}", CPU_SYSFS, name))
+}
+
+
+/// Cpus of a list in the kernel's format (eg: `0-3,8`), as found in sysfs and procfs.
+pub fn read_cpu_list(path: &str) -> io::Result<Vec<u32>> {
+    let cpu_list = fs::read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("Unable to read {}: {}", path, err)))?;
+    let mut cpus = Vec::default();
+    for element in cpu_list.trim().split(',').filter(|element| !element.is_empty()) {
+        cpus.extend(parse_cpu_range(element)?);
+    }
+    Ok(cpus)
+}
+
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_take_a_stride() {
+        assert_eq!(parse_cpu_range("3").unwrap(), vec![3]);
+        assert_eq!(parse_cpu_range("2-5").unwrap(), vec![2, 3, 4, 5]);
+        assert_eq!(parse_cpu_range("0-7:2").unwrap(), vec![0, 2, 4, 6]);
+        assert_eq!(parse_cpu_range(" 1 - 2 ").unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        for range in ["", "a", "5-2", "0-7:0", "0-7:x", "3:2", "-1"] {
+            assert!(parse_cpu_range(range).is_err(), "{}", range);
+        }
+    }
+
+    #[test]
+    fn cpu_lists_reject_duplicates_and_empty_results() {
+        // cpu 0 is online on every machine
+        assert_eq!(parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(parse_cpu_list("0,0-0:1").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(parse_cpu_list("0,!0").is_err());
+        assert!(parse_cpu_list("0,").is_err());
+        assert!(parse_cpu_list("4294967295").is_err());
+    }
+
+    #[test]
+    fn cpu_lists_are_formatted_as_ranges() {
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8]), "0-3,8");
+        assert_eq!(format_cpu_list(&[1, 3, 5]), "1,3,5");
+        assert_eq!(format_cpu_list(&[]), "");
+    }
+}
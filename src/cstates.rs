@@ -0,0 +1,62 @@
+use std::{fs, io, sync::Arc};
+
+use crate::{jitter::Field, probe::IntervalProbe};
+
+
+/// Publishes how often and for how long the sampled cpu entered each of its idle states during every interval, as
+/// `cstate_<name>_usage` and `cstate_<name>_time_us` fields taken from cpuidle.
+pub struct CStatesProbe {
+    states: Vec<IdleState>,
+}
+
+struct IdleState {
+    dir: String,
+    usage_field: Arc<str>,
+    time_field: Arc<str>,
+    previous: (i64, i64),
+}
+
+impl CStatesProbe {
+    pub fn new(cpu: u32) -> io::Result<CStatesProbe> {
+        let mut states = Vec::new();
+        for state in 0.. {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpuidle/state{}", cpu, state);
+            let name = match fs::read_to_string(format!("{}/name", dir)) {
+                Ok(name) => name.trim().to_string(),
+                Err(_) => break,
+            };
+            let mut idle_state = IdleState {
+                usage_field: Arc::from(format!("cstate_{}_usage", name)),
+                time_field: Arc::from(format!("cstate_{}_time_us", name)),
+                previous: (0, 0),
+                dir,
+            };
+            idle_state.previous = idle_state.read();
+            states.push(idle_state);
+        }
+
+        if states.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no cpuidle states exposed"));
+        }
+
+        Ok(CStatesProbe { states })
+    }
+}
+
+impl IntervalProbe for CStatesProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        for state in self.states.iter_mut() {
+            let current = state.read();
+            fields.push(Field { name: state.usage_field.clone(), value: current.0 - state.previous.0 });
+            fields.push(Field { name: state.time_field.clone(), value: current.1 - state.previous.1 });
+            state.previous = current;
+        }
+    }
+}
+
+impl IdleState {
+    fn read(&self) -> (i64, i64) {
+        let read = |attribute| fs::read_to_string(format!("{}/{}", self.dir, attribute)).ok().and_then(|value| value.trim().parse::<i64>().ok()).unwrap_or(0);
+        (read("usage"), read("time"))
+    }
+}
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{self, BufWriter, Write}, sync::Mutex};
+
+use crate::{jitter::Jitter, sink::Sink};
+
+
+struct CsvWriter {
+    writer: BufWriter<File>,
+    header_written: bool,
+    path: String,
+}
+
+impl CsvWriter {
+    fn create(path: &str) -> io::Result<CsvWriter> {
+        Ok(CsvWriter { writer: BufWriter::new(File::create(path)?), header_written: false, path: path.to_string() })
+    }
+
+    /// Only writes a header if the file is new or empty.
+    fn append(path: &str) -> io::Result<CsvWriter> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let header_written = file.metadata()?.len() > 0;
+        Ok(CsvWriter { writer: BufWriter::new(file), header_written, path: path.to_string() })
+    }
+
+    fn write_rows(&mut self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        for data_point in samples {
+            if !self.header_written {
+                write!(self.writer, "timestamp,cpu,latency")?;
+                for field in &data_point.fields {
+                    write!(self.writer, ",{}", field.name)?;
+                }
+                writeln!(self.writer)?;
+                self.header_written = true;
+            }
+
+            write!(self.writer, "{},{},{}", data_point.ts, cpu, data_point.latency)?;
+            for field in &data_point.fields {
+                write!(self.writer, ",{}", field.value)?;
+            }
+            writeln!(self.writer)?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+
+/// Writes one row per sample. Extra per-interval fields become additional columns, taken from the first published sample.
+/// Events (outliers, worst latencies) go to a separate file per measurement next to the results, eg: `results_jitter_outlier.csv`,
+/// created once the first event of that kind gets published.
+pub struct CsvSink {
+    samples: Mutex<CsvWriter>,
+    events: Mutex<HashMap<String, CsvWriter>>,
+    path_prefix: String,
+}
+
+impl CsvSink {
+    pub fn create(path: &str) -> io::Result<CsvSink> {
+        Ok(CsvSink {
+            samples: Mutex::new(CsvWriter::create(path)?),
+            events: Mutex::new(HashMap::new()),
+            path_prefix: path.strip_suffix(".csv").unwrap_or(path).to_string(),
+        })
+    }
+}
+
+impl Sink for CsvSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.samples.lock().unwrap().write_rows(cpu, samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        let mut writers = self.events.lock().unwrap();
+        if !writers.contains_key(measurement) {
+            let writer = CsvWriter::create(&format!("{}_{}.csv", self.path_prefix, measurement))?;
+            writers.insert(measurement.to_string(), writer);
+        }
+
+        writers.get_mut(measurement).unwrap().write_rows(cpu, events)
+    }
+
+    fn reopen(&self) -> io::Result<()> {
+        let mut samples = self.samples.lock().unwrap();
+        *samples = CsvWriter::append(&samples.path)?;
+        for writer in self.events.lock().unwrap().values_mut() {
+            *writer = CsvWriter::append(&writer.path)?;
+        }
+
+        Ok(())
+    }
+}
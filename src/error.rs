@@ -0,0 +1,53 @@
+use std::io;
+
+use nix::sys::signal::Signal;
+
+
+/// Failures setting up or running the sampler, returned rather than panicking so that embedders can recover
+/// and a cpu that cannot be sampled does not take the others down with it.
+#[derive(Debug, thiserror::Error)]
+pub enum JitterError {
+    #[error("Unable to set CPU affinity to cpu: {cpu}: {source}")]
+    Affinity { cpu: u32, source: nix::Error },
+    #[error("Hyperthread siblings {siblings} of cpu: {cpu} are online and not isolated, their activity shows up as jitter")]
+    BusySiblings { cpu: u32, siblings: String },
+    #[error("Unable to find hyperthread siblings of cpu: {cpu}: {source}")]
+    Topology { cpu: u32, source: io::Error },
+    #[error("Unable to take cpu: {cpu} offline: {source}")]
+    CpuOnline { cpu: u32, source: io::Error },
+    #[error("Unable to tune cpu frequency through {path}: {source}")]
+    Frequency { path: String, source: io::Error },
+    #[error("Unable to move interrupts off the sampled cpus: {0}")]
+    Irq(io::Error),
+    #[error("Unable to drop privileges to user: {user}: {source}")]
+    Privileges { user: String, source: io::Error },
+    #[error("Ping-pong mode measures between exactly two cpus, {0} given")]
+    PingPongCpus(usize),
+    #[error("Unable to set SCHED_FIFO priority {priority}: permission denied, run as root or grant CAP_SYS_NICE (e.g. setcap cap_sys_nice+ep)")]
+    PriorityDenied { priority: i32 },
+    #[error("Unable to set SCHED_FIFO priority {priority}: {source}")]
+    Priority { priority: i32, source: io::Error },
+    #[error("Unable to install {signal} handler: {source}")]
+    SignalHandler { signal: Signal, source: nix::Error },
+    #[error("Unable to mlock {what}: {source}")]
+    Mlock { what: &'static str, source: io::Error },
+    #[error("RLIMIT_MEMLOCK of {limit_kib}KiB (hard limit {hard_limit_kib}KiB) is below the {needed_kib}KiB to be mlocked, raise it with `ulimit -l {needed_kib}` or LimitMEMLOCK= of the systemd unit, or grant CAP_IPC_LOCK")]
+    MemlockLimit { needed_kib: u64, limit_kib: u64, hard_limit_kib: u64 },
+    #[error("Unable to change privilege level of the process with iopl(), required to disable local interrupts: {0}")]
+    IoPrivilege(io::Error),
+    #[error("Unable to detect the counter frequency: {0}, pass it with --tsc-frequency")]
+    CounterFrequency(String),
+    #[error("{0} is not supported on this architecture")]
+    Unsupported(&'static str),
+    #[error("Unable to arm ftrace: {0}")]
+    Ftrace(io::Error),
+    #[error("Unable to create raw sample file: {path}: {source}")]
+    RawOutput { path: String, source: io::Error },
+    #[error("{0}")]
+    Numa(String),
+    #[error("Sampler thread of cpu: {cpu} panicked: {message}")]
+    SamplerPanicked { cpu: u32, message: String },
+}
+
+
+pub type Result<T> = std::result::Result<T, JitterError>;
@@ -0,0 +1,56 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Write}, path::PathBuf};
+
+use log::info;
+
+const TRACEFS_MOUNTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+const TRACED_EVENTS: &str = "sched:sched_switch\nirq:*\ntimer:*\n";
+
+
+/// Kernel ftrace session recording scheduler, interrupt and timer events into the ring buffer for as long as the
+/// sampler runs. Tracing is global, so freezing it for one cpu's outlier also pauses it for the others until the
+/// buffer has been dumped.
+pub struct Ftrace {
+    root: PathBuf,
+    tracing_on: File,
+}
+
+impl Ftrace {
+    pub fn arm() -> io::Result<Ftrace> {
+        let root = tracefs_root()?;
+
+        fs::write(root.join("set_event"), TRACED_EVENTS)?;
+        File::create(root.join("trace"))?;
+        let mut tracing_on = OpenOptions::new().write(true).open(root.join("tracing_on"))?;
+        tracing_on.write_all(b"1")?;
+        info!("Tracing {} events through {}", TRACED_EVENTS.trim().replace('\n', ", "), root.display());
+
+        Ok(Ftrace { root, tracing_on })
+    }
+
+    /// Stops recording so that the events leading up to an outlier are not overwritten before they get dumped.
+    pub fn freeze(&self) {
+        let _ = (&self.tracing_on).write_all(b"0");
+    }
+
+    /// Saves the events recorded on `cpu` to `path`, then clears them and resumes tracing.
+    pub fn dump(&self, cpu: u32, path: &str) -> io::Result<()> {
+        let cpu_trace = self.root.join(format!("per_cpu/cpu{}/trace", cpu));
+        fs::copy(&cpu_trace, path)?;
+        File::create(&cpu_trace)?;
+        (&self.tracing_on).write_all(b"1")
+    }
+}
+
+impl Drop for Ftrace {
+    fn drop(&mut self) {
+        let _ = self.tracing_on.write_all(b"0");
+        let _ = File::create(self.root.join("set_event"));
+    }
+}
+
+
+pub fn tracefs_root() -> io::Result<PathBuf> {
+    TRACEFS_MOUNTS.iter().map(PathBuf::from)
+        .find(|root| root.join("tracing_on").exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "tracefs is not mounted"))
+}
@@ -0,0 +1,85 @@
+use std::{io::{self, BufWriter, Write}, net::TcpStream, sync::Mutex, time::Duration};
+
+use log::info;
+
+use crate::{jitter::Jitter, sink::Sink};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+
+/// Publishes over the carbon plaintext protocol as `jitter.<host>.cpu<N>.max <latency> <seconds>`, plus one metric per probe field.
+/// Graphite only keeps one value per metric and second, so report intervals shorter than a second mostly overwrite each other.
+pub struct GraphiteSink {
+    address: String,
+    prefix: String,
+    connection: Mutex<Option<BufWriter<TcpStream>>>,
+}
+
+impl GraphiteSink {
+    pub fn new(address: &str, local_hostname: &str) -> GraphiteSink {
+        GraphiteSink {
+            address: address.to_string(),
+            prefix: format!("jitter.{}", metric_node(local_hostname)),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn write_metrics(&self, cpu: u32, metric: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(BufWriter::new(self.connect()?));
+        }
+
+        let result = write_lines(connection.as_mut().unwrap(), &format!("{}.cpu{}", self.prefix, cpu), metric, data_points);
+        if result.is_err() {
+            // carbon closes idle connections, start over with a fresh one next time
+            *connection = None;
+        }
+        result
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, format!("Graphite address does not resolve: {}", self.address));
+        for address in std::net::ToSocketAddrs::to_socket_addrs(self.address.as_str())? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    info!("Connected to Graphite at: {}", address);
+                    return Ok(stream);
+                },
+                Err(err) => last_err = err,
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+
+fn write_lines(writer: &mut impl Write, path: &str, metric: &str, data_points: &[Jitter]) -> io::Result<()> {
+    for data_point in data_points {
+        let seconds = data_point.ts / NANOS_PER_SECOND;
+        writeln!(writer, "{}.{} {} {}", path, metric, data_point.latency, seconds)?;
+        for field in &data_point.fields {
+            writeln!(writer, "{}.{} {} {}", path, metric_node(&field.name), field.value, seconds)?;
+        }
+    }
+
+    writer.flush()
+}
+
+
+/// Dots separate path nodes and whitespace separates the value, so neither may appear within a node.
+fn metric_node(name: &str) -> String {
+    name.chars().map(|c| if c == '.' || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+impl Sink for GraphiteSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.write_metrics(cpu, "max", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.write_metrics(cpu, &metric_node(measurement), events)
+    }
+}
@@ -0,0 +1,225 @@
+use std::{io::{self, PipeWriter, Write}, sync::Mutex, thread::{self, JoinHandle}};
+
+use log::{error, info};
+use isahc::{Body, HttpClient, Request, config::{Configurable, VersionNegotiation}, prelude::*};
+
+use crate::{jitter::Jitter, sink::Sink, utils::ProgramArgs};
+
+const PUBLISH_METHOD: &str = "/jitter.JitterCollector/Publish";
+
+const VARINT: u8 = 0;
+const LENGTH_DELIMITED: u8 = 2;
+
+
+/// Streams every sample as a `JitterSample` message (see proto/jitter.proto) over one client streaming gRPC call
+/// per run, so that a central collector sees the samples of many hosts as they get published.
+pub struct GrpcSink {
+    client: HttpClient,
+    url: String,
+    local_hostname: String,
+    run_id: String,
+    measurement: String,
+    call: Mutex<Option<Call>>,
+}
+
+/// Request body of an open call is fed through a pipe, while a thread waits for the collector to answer it.
+struct Call {
+    writer: PipeWriter,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl GrpcSink {
+    pub fn create(url: &str, program_args: &ProgramArgs) -> io::Result<GrpcSink> {
+        let client = HttpClient::builder()
+            .version_negotiation(VersionNegotiation::http2())
+            .build()
+            .map_err(io::Error::other)?;
+
+        Ok(GrpcSink {
+            client,
+            url: format!("{}{}", url.trim_end_matches('/'), PUBLISH_METHOD),
+            local_hostname: program_args.local_hostname.clone(),
+            run_id: program_args.run_id.clone(),
+            measurement: program_args.influx_measurement.clone(),
+            call: Mutex::new(None),
+        })
+    }
+
+    fn publish_messages(&self, measurement: &str, cpu: u32, data_points: &[Jitter]) -> io::Result<()> {
+        let mut frames = Vec::new();
+        for data_point in data_points {
+            put_frame(&mut frames, &self.encode_sample(measurement, cpu, data_point));
+        }
+
+        let mut call = self.call.lock().unwrap();
+        if let Some(ended) = call.take_if(|call| call.handle.is_finished()) {
+            // the collector answered early; report why, if it failed, and open a new call
+            ended.finish()?;
+        }
+        if call.is_none() {
+            *call = Some(self.open()?);
+        }
+
+        if let Err(err) = call.as_mut().unwrap().writer.write_all(&frames) {
+            // the reason the call broke off is more telling than a broken pipe
+            return Err(call.take().unwrap().finish().err().unwrap_or(err));
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<Call> {
+        let (reader, writer) = io::pipe()?;
+        let request = Request::post(self.url.as_str())
+            .header("Content-Type", "application/grpc+proto")
+            .header("TE", "trailers")
+            .body(Body::from_reader(reader))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let client = self.client.clone();
+        let handle = thread::Builder::new()
+            .name("grpc-publish".to_string())
+            .spawn(move || {
+                let mut response = client.send(request)?;
+                if !response.status().is_success() {
+                    return Err(io::Error::other(format!("Collector responded with {}", response.status())));
+                }
+                io::copy(response.body_mut(), &mut io::sink())?;
+
+                // a failure before any message is answered with headers only, otherwise the status comes in the trailers
+                let status = match response.headers().get("grpc-status") {
+                    Some(_) => response.headers(),
+                    None => response.trailer().wait(),
+                };
+                match status.get("grpc-status").and_then(|status| status.to_str().ok()) {
+                    Some("0") => Ok(()),
+                    code => {
+                        let message = status.get("grpc-message").and_then(|message| message.to_str().ok()).unwrap_or_default();
+                        Err(io::Error::other(format!("Collector ended the stream with status {}: {}", code.unwrap_or("unknown"), message)))
+                    },
+                }
+            })?;
+
+        info!("Streaming samples over gRPC to: {}", self.url);
+        Ok(Call { writer, handle })
+    }
+
+    fn encode_sample(&self, measurement: &str, cpu: u32, data_point: &Jitter) -> Vec<u8> {
+        let mut message = Vec::with_capacity(64 + self.local_hostname.len() + self.run_id.len());
+        put_bytes(&mut message, 1, self.local_hostname.as_bytes());
+        put_bytes(&mut message, 2, self.run_id.as_bytes());
+        put_varint_field(&mut message, 3, cpu as u64);
+        put_bytes(&mut message, 4, measurement.as_bytes());
+        put_varint_field(&mut message, 5, data_point.ts as u64);
+        put_varint_field(&mut message, 6, data_point.latency as u64);
+        for field in &data_point.fields {
+            let mut entry = Vec::with_capacity(field.name.len() + 12);
+            put_bytes(&mut entry, 1, field.name.as_bytes());
+            put_varint_field(&mut entry, 2, field.value as u64);
+            put_bytes(&mut message, 7, &entry);
+        }
+        message
+    }
+}
+
+impl Call {
+    /// Ends the request body and waits for the collector's answer.
+    fn finish(self) -> io::Result<()> {
+        drop(self.writer);
+        self.handle.join().unwrap_or_else(|_| Err(io::Error::other("gRPC publishing thread panicked")))
+    }
+}
+
+impl Sink for GrpcSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.publish_messages(&self.measurement, cpu, samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.publish_messages(measurement, cpu, events)
+    }
+}
+
+impl Drop for GrpcSink {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.get_mut().unwrap().take() {
+            if let Err(err) = call.finish() {
+                error!(phase = "publish", error:% = err; "gRPC stream to {} failed: {}", self.url, err);
+            }
+        }
+    }
+}
+
+
+/// gRPC length-prefixed message: a compressed flag and the big-endian length ahead of the message.
+fn put_frame(buf: &mut Vec<u8>, message: &[u8]) {
+    buf.push(0); // not compressed
+    buf.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    buf.extend_from_slice(message);
+}
+
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+
+/// Negative int64 values go as their two's complement, ten bytes long, as protobuf expects.
+fn put_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buf, (field << 3 | VARINT as u32) as u64);
+    put_varint(buf, value);
+}
+
+
+fn put_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(buf, (field << 3 | LENGTH_DELIMITED as u32) as u64);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::jitter::Field;
+
+    #[test]
+    fn samples_encode_as_jitter_sample_messages() {
+        let program_args = ProgramArgs { local_hostname: "h1".to_string(), run_id: "r".to_string(), ..ProgramArgs::default() };
+        let sink = GrpcSink::create("http://localhost:50051/", &program_args).unwrap();
+        let sample = Jitter { ts: 300, latency: -1, fields: vec![Field { name: Arc::from("p99"), value: 150 }] };
+
+        let mut expected = vec![0x0a, 2, b'h', b'1', 0x12, 1, b'r', 0x18, 3, 0x22, 6];
+        expected.extend_from_slice(b"jitter");
+        expected.extend_from_slice(&[0x28, 0xac, 0x02]);
+        // int64 (not sint64): negative values are ten byte two's complement varints, not zigzag encoded
+        expected.extend_from_slice(&[0x30, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        // map entries are embedded messages with the key as field 1 and the value as field 2
+        expected.extend_from_slice(&[0x3a, 8, 0x0a, 3, b'p', b'9', b'9', 0x10, 0x96, 0x01]);
+        assert_eq!(sink.encode_sample("jitter", 3, &sample), expected);
+    }
+
+    #[test]
+    fn varints_take_seven_bits_per_byte() {
+        for (value, encoded) in [(0, vec![0]), (1, vec![1]), (127, vec![0x7f]), (128, vec![0x80, 1]), (300, vec![0xac, 2]), (u32::MAX as u64, vec![0xff, 0xff, 0xff, 0xff, 0x0f])] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+            assert_eq!(buf, encoded, "{}", value);
+        }
+    }
+
+    #[test]
+    fn frames_carry_an_uncompressed_flag_and_big_endian_length() {
+        let mut buf = Vec::new();
+        put_frame(&mut buf, &[1, 2, 3]);
+        put_frame(&mut buf, &[0; 300]);
+        assert_eq!(&buf[..8], &[0, 0, 0, 0, 3, 1, 2, 3]);
+        assert_eq!(&buf[8..13], &[0, 0, 0, 1, 44]);
+        assert_eq!(buf.len(), 13 + 300);
+    }
+}
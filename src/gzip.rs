@@ -0,0 +1,132 @@
+use std::{convert::TryFrom, io, mem, os::raw::{c_int, c_void}};
+
+use libz_sys as zlib;
+
+/// zlib window size, with 16 added to produce a gzip header and trailer instead of a zlib wrapper.
+const GZIP_WINDOW_BITS: c_int = 15 + 16;
+const DEFAULT_MEM_LEVEL: c_int = 8;
+
+
+/// Compresses `data` into a single gzip member, as expected for `Content-Encoding: gzip`.
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = zlib::z_stream {
+        next_in: data.as_ptr() as *mut u8,
+        avail_in: checked_length(data.len())?,
+        total_in: 0,
+        next_out: std::ptr::null_mut(),
+        avail_out: 0,
+        total_out: 0,
+        msg: std::ptr::null_mut(),
+        state: std::ptr::null_mut(),
+        zalloc,
+        zfree,
+        opaque: std::ptr::null_mut(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0,
+    };
+
+    let result = unsafe {
+        zlib::deflateInit2_(&mut stream, zlib::Z_DEFAULT_COMPRESSION, zlib::Z_DEFLATED, GZIP_WINDOW_BITS, DEFAULT_MEM_LEVEL,
+            zlib::Z_DEFAULT_STRATEGY, zlib::zlibVersion(), mem::size_of::<zlib::z_stream>() as c_int)
+    };
+    if result != zlib::Z_OK {
+        return Err(io::Error::other(format!("Unable to initialize gzip compression: {}", result)));
+    }
+
+    // the bound covers the whole stream, so a single Z_FINISH call always completes it
+    let mut compressed: Vec<u8> = Vec::with_capacity(unsafe { zlib::deflateBound(&mut stream, data.len() as zlib::uLong) } as usize);
+    stream.next_out = compressed.as_mut_ptr();
+    stream.avail_out = checked_length(compressed.capacity())?;
+    let result = unsafe { zlib::deflate(&mut stream, zlib::Z_FINISH) };
+    let length = stream.total_out as usize;
+    unsafe { zlib::deflateEnd(&mut stream) };
+
+    if result != zlib::Z_STREAM_END {
+        return Err(io::Error::other(format!("Unable to gzip {} bytes: {}", data.len(), result)));
+    }
+    unsafe { compressed.set_len(length) };
+    Ok(compressed)
+}
+
+
+fn checked_length(length: usize) -> io::Result<zlib::uInt> {
+    zlib::uInt::try_from(length).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes is too much to gzip at once", length)))
+}
+
+
+unsafe extern "C" fn zalloc(_opaque: *mut c_void, items: zlib::uInt, size: zlib::uInt) -> *mut c_void {
+    nix::libc::calloc(items as usize, size as usize)
+}
+
+
+unsafe extern "C" fn zfree(_opaque: *mut c_void, address: *mut c_void) {
+    nix::libc::free(address)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inflates a gzip member with zlib, a chunk of output at a time.
+    fn decompress(compressed: &[u8]) -> Vec<u8> {
+        let mut stream = zlib::z_stream {
+            next_in: compressed.as_ptr() as *mut u8,
+            avail_in: compressed.len() as zlib::uInt,
+            total_in: 0,
+            next_out: std::ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg: std::ptr::null_mut(),
+            state: std::ptr::null_mut(),
+            zalloc,
+            zfree,
+            opaque: std::ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0,
+        };
+        assert_eq!(unsafe { zlib::inflateInit2_(&mut stream, GZIP_WINDOW_BITS, zlib::zlibVersion(), mem::size_of::<zlib::z_stream>() as c_int) }, zlib::Z_OK);
+
+        let mut decompressed = Vec::new();
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            stream.next_out = chunk.as_mut_ptr();
+            stream.avail_out = chunk.len() as zlib::uInt;
+            let result = unsafe { zlib::inflate(&mut stream, zlib::Z_NO_FLUSH) };
+            decompressed.extend_from_slice(&chunk[..chunk.len() - stream.avail_out as usize]);
+            match result {
+                zlib::Z_STREAM_END => break,
+                zlib::Z_OK => continue,
+                err => panic!("inflate failed: {}", err),
+            }
+        }
+        assert_eq!(stream.avail_in, 0, "trailing bytes after the gzip member");
+        unsafe { zlib::inflateEnd(&mut stream) };
+        decompressed
+    }
+
+    #[test]
+    fn compressed_data_inflates_to_the_original() {
+        // a xorshift sequence compresses poorly, so the last input spans many deflate blocks and inflate calls
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..1 << 20).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        let lines = "jitter,host=h1,cpu=3 latency=1500i 1700000000000000000\n".repeat(10_000);
+
+        for data in [&b""[..], b"a", lines.as_bytes(), &noise] {
+            let compressed = compress(data).unwrap();
+            assert_eq!(&compressed[..3], &[0x1f, 0x8b, 8], "gzip magic and deflate method");
+            let trailer = &compressed[compressed.len() - 8..];
+            let crc = unsafe { zlib::crc32(0, data.as_ptr(), data.len() as zlib::uInt) } as u32;
+            assert_eq!(u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]), crc);
+            assert_eq!(u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]), data.len() as u32);
+            assert_eq!(decompress(&compressed), data);
+        }
+    }
+}
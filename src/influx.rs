@@ -1,22 +1,302 @@
-use crate::{jitter::Jitter, utils::ProgramArgs};
+use std::{fs::{self, OpenOptions}, io::{self, Write}, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-const BATCH_PUBLISH_THRESHOLD_BYTES: usize = 768 * 1024;
+use log::{error, warn};
+use isahc::{HttpClient, Request, auth::{Authentication, Credentials}, config::{CaCertificate, SslOption}, prelude::*};
 
-pub fn publish_results(program_args: &ProgramArgs, cpu: u32, results: Vec<Jitter>) {
-    let mut body: String = String::default();
+use crate::{gzip, jitter::Jitter, metadata::RunMetadata, sink::Sink, utils::ProgramArgs};
 
-    for data_point in results {
-        body.push_str(format!("jitter,host={},cpu={} jitter={} {}\n", program_args.local_hostname, cpu, data_point.latency, data_point.ts).as_str());
-        if body.len() >= BATCH_PUBLISH_THRESHOLD_BYTES {
-            post_batch(&program_args, &body);
-            body.clear();
+const TCP_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const RUN_MEASUREMENT: &str = "jitter_run";
+
+
+pub struct InfluxSink {
+    client: HttpClient,
+    write_url: String,
+    auth_token: Option<String>,
+    gzip_enabled: bool,
+    batch_max_bytes: usize,
+    batch_max_points: usize,
+    max_retries: u32,
+    retry_backoff: Duration,
+    spill_path: Option<String>,
+    measurement: String,
+    tags: String,
+}
+
+impl InfluxSink {
+    pub fn create(program_args: &ProgramArgs) -> io::Result<InfluxSink> {
+        Ok(InfluxSink {
+            client: http_client(program_args)?,
+            write_url: write_url(program_args),
+            auth_token: program_args.influx_token.as_ref().map(|token| token.0.clone()),
+            gzip_enabled: program_args.influx_gzip_enabled,
+            batch_max_bytes: program_args.influx_batch_max_bytes,
+            batch_max_points: program_args.influx_batch_max_points.unwrap_or(usize::MAX),
+            max_retries: program_args.influx_retries,
+            retry_backoff: Duration::from_millis(program_args.influx_retry_backoff_millis),
+            spill_path: program_args.influx_spill_path.clone(),
+            measurement: program_args.influx_measurement.clone(),
+            tags: series_tags(program_args),
+        })
+    }
+
+    /// Re-sends line protocol spilled by an earlier run, batched like freshly published points. Never spills again:
+    /// the lines are already on disk, so a failed write is returned for the caller to retry the whole file.
+    pub fn resend(&self, spill_path: &str) -> io::Result<()> {
+        let lines = fs::read_to_string(spill_path)?;
+        self.publish_lines(lines.lines().filter(|line| !line.trim().is_empty()).map(str::to_string), None)
+    }
+
+    fn publish_measurement(&self, measurement: &str, value_field: &str, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        let measurement = escape(measurement, " ,");
+        self.publish_lines(samples.iter().map(|data_point| line(&measurement, &self.tags, value_field, cpu, data_point)), self.spill_path.as_deref())
+    }
+
+    fn publish_lines<I: Iterator<Item = String>>(&self, lines: I, spill_path: Option<&str>) -> io::Result<()> {
+        let mut body: String = String::default();
+        let mut points = 0;
+        let mut failure = None;
+
+        for line in lines {
+            body.push_str(&line);
+            body.push('\n');
+            points += 1;
+            if body.len() >= self.batch_max_bytes || points >= self.batch_max_points {
+                self.send_batch(&body, spill_path, &mut failure)?;
+                body.clear();
+                points = 0;
+            }
+        }
+
+        if !body.is_empty() {
+            self.send_batch(&body, spill_path, &mut failure)?;
+        }
+
+        match failure {
+            Some(err) if spill_path.is_none() => Err(err),
+            _ => Ok(()),
         }
     }
 
-    post_batch(&program_args, &body);
+    /// Posts `batch` unless an earlier batch of the same publish already exhausted its retries,
+    /// in which case Influx is assumed to be down and the batch goes straight to the spill file.
+    fn send_batch(&self, batch: &str, spill_path: Option<&str>, failure: &mut Option<io::Error>) -> io::Result<()> {
+        if failure.is_none() {
+            match self.post_batch(batch) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    if let Some(path) = spill_path {
+                        error!(phase = "publish", error:% = err; "Giving up on Influx write: {}. Spilling this and any remaining batches of line protocol to: {}", err, path);
+                    }
+                    *failure = Some(err);
+                },
+            }
+        }
+
+        match spill_path {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?.write_all(batch.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    fn post_batch(&self, batch: &str) -> io::Result<()> {
+        let body = if self.gzip_enabled { gzip::compress(batch.as_bytes())? } else { batch.as_bytes().to_vec() };
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            match self.try_post_batch(&body) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.max_retries => {
+                    attempt += 1;
+                    let delay = backoff + backoff.mul_f64(fastrand::f64() * 0.5);
+                    warn!(phase = "publish", error:% = err; "Influx write failed (attempt {} of {}): {}. Retrying in {:?}", attempt, self.max_retries + 1, err, delay);
+                    thread::sleep(delay);
+                    backoff *= 2;
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn try_post_batch(&self, body: &[u8]) -> io::Result<()> {
+        let mut request = Request::post(self.write_url.as_str());
+        if self.gzip_enabled {
+            request = request.header("Content-Encoding", "gzip");
+        }
+        if let Some(token) = &self.auth_token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+
+        let request = request.body(body.to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut response = self.client.send(request)?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let message = response.text().unwrap_or_default();
+            match message.trim() {
+                "" => Err(io::Error::other(format!("Influx responded with {}", response.status()))),
+                message => Err(io::Error::other(format!("Influx responded with {}: {}", response.status(), message))),
+            }
+        }
+    }
 }
 
-pub fn post_batch(program_args: &ProgramArgs, batch: &String) {
-    let url = format!("{}/write?db={}", program_args.influx_url, program_args.influx_db);
-    isahc::post(url, batch.as_str());
-}
\ No newline at end of file
+
+/// Interval samples of the run tagged with `run_id`, by cpu, queried through the InfluxQL endpoint
+/// (which 2.x serves for buckets mapped to a database).
+pub fn query_run(program_args: &ProgramArgs, run_id: &str) -> io::Result<Vec<(u32, Jitter)>> {
+    let query = format!("SELECT \"jitter\" FROM \"{}\" WHERE \"run_id\" = '{}' GROUP BY \"cpu\"",
+        escape(&program_args.influx_measurement, "\"\\"), escape(run_id, "'\\"));
+    let database = program_args.influx_bucket.as_deref().unwrap_or(&program_args.influx_db);
+    let mut params = form_urlencoded::Serializer::new(String::new());
+    params.append_pair("db", database).append_pair("epoch", "ns").append_pair("q", &query);
+    let url = format!("{}/query?{}", program_args.influx_url.trim_end_matches('/'), params.finish());
+
+    let mut request = Request::get(url.as_str());
+    if let Some(token) = &program_args.influx_token {
+        request = request.header("Authorization", format!("Token {}", token.0));
+    }
+    let request = request.body(()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut response = http_client(program_args)?.send(request)?;
+    let body = response.text()?;
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!("Influx responded with {}: {}", response.status(), body.trim())));
+    }
+
+    let body: serde_json::Value = serde_json::from_str(&body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if let Some(err) = body.pointer("/results/0/error").and_then(|err| err.as_str()) {
+        return Err(io::Error::other(format!("Influx query failed: {}", err)));
+    }
+    let mut samples = Vec::new();
+    for series in body.pointer("/results/0/series").and_then(|series| series.as_array()).into_iter().flatten() {
+        let cpu = series.pointer("/tags/cpu").and_then(|cpu| cpu.as_str()).and_then(|cpu| cpu.parse::<u32>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Influx series without a cpu tag"))?;
+        for value in series["values"].as_array().into_iter().flatten() {
+            if let (Some(ts), Some(latency)) = (value[0].as_i64(), value[1].as_i64()) {
+                samples.push((cpu, Jitter { ts, latency, fields: Vec::new() }));
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+
+/// One point of line protocol, without the trailing newline; `measurement` is expected to be escaped already.
+/// Field keys are escaped like tag keys, since interrupt and cstate names come from /proc and sysfs as they are.
+pub(crate) fn line(measurement: &str, tags: &str, value_field: &str, cpu: u32, data_point: &Jitter) -> String {
+    let mut line = format!("{},{},cpu={} {}={}", measurement, tags, cpu, escape(value_field, " ,="), data_point.latency);
+    for field in &data_point.fields {
+        line.push_str(format!(",{}={}", escape(&field.name, " ,="), field.value).as_str());
+    }
+    line.push_str(format!(" {}", data_point.ts).as_str());
+    line
+}
+
+
+/// The run metadata as a single `jitter_run` point, timestamped with the current time.
+pub(crate) fn metadata_line(tags: &str, metadata: &RunMetadata) -> String {
+    let fields: Vec<String> = metadata.fields().into_iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(&value, "\"\\")))
+        .collect();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{},{} {} {}", RUN_MEASUREMENT, tags, fields.join(","), now.as_nanos())
+}
+
+
+/// Host and user supplied tags, rendered once since they are the same for every point.
+pub(crate) fn series_tags(program_args: &ProgramArgs) -> String {
+    let mut tags = format!("host={}", escape(&program_args.local_hostname, " ,="));
+    if !program_args.run_id.is_empty() {
+        tags.push_str(format!(",run_id={}", escape(&program_args.run_id, " ,=")).as_str());
+    }
+    for (key, value) in &program_args.influx_tags {
+        tags.push_str(format!(",{}={}", escape(key, " ,="), escape(value, " ,=")).as_str());
+    }
+
+    tags
+}
+
+
+/// Backslash escapes the characters line protocol treats as delimiters in measurements and tags.
+pub(crate) fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+
+/// Shared by every batch, so that its connection cache keeps the connection (and TLS session) to Influx alive between writes.
+fn http_client(program_args: &ProgramArgs) -> io::Result<HttpClient> {
+    let mut builder = HttpClient::builder()
+        .timeout(Duration::from_secs(program_args.influx_timeout_seconds))
+        .tcp_keepalive(TCP_KEEPALIVE_INTERVAL);
+    if let Some(user) = &program_args.influx_user {
+        let password = program_args.influx_password.as_ref().map(|password| password.0.as_str()).unwrap_or_default();
+        builder = builder.authentication(Authentication::basic()).credentials(Credentials::new(user.as_str(), password));
+    }
+    if let Some(path) = &program_args.influx_ca_cert {
+        builder = builder.ssl_ca_certificate(CaCertificate::file(path));
+    }
+    if program_args.influx_insecure_skip_verify {
+        builder = builder.ssl_options(SslOption::DANGER_ACCEPT_INVALID_CERTS | SslOption::DANGER_ACCEPT_INVALID_HOSTS);
+    }
+
+    Ok(builder.build()?)
+}
+
+
+fn write_url(program_args: &ProgramArgs) -> String {
+    let base_url = program_args.influx_url.trim_end_matches('/');
+    let mut query = form_urlencoded::Serializer::new(String::new());
+
+    match &program_args.influx_bucket {
+        Some(bucket) => {
+            query.append_pair("org", &program_args.influx_org).append_pair("bucket", bucket).append_pair("precision", "ns");
+            format!("{}/api/v2/write?{}", base_url, query.finish())
+        },
+        None => {
+            query.append_pair("db", &program_args.influx_db).append_pair("precision", "ns");
+            format!("{}/write?{}", base_url, query.finish())
+        }
+    }
+}
+
+impl Sink for InfluxSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.publish_measurement(&self.measurement, "jitter", cpu, samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.publish_measurement(measurement, "latency", cpu, events)
+    }
+
+    fn publish_metadata(&self, metadata: &RunMetadata) -> io::Result<()> {
+        self.publish_lines(std::iter::once(metadata_line(&self.tags, metadata)), self.spill_path.as_deref())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::jitter::Field;
+
+    #[test]
+    fn field_keys_are_escaped() {
+        let fields = vec![Field { name: Arc::from("irq_PCI-MSI 524288-edge"), value: 3 }, Field { name: Arc::from("cstate_C1,E=x"), value: 4 }];
+        let data_point = Jitter { ts: 17, latency: 1500, fields };
+        assert_eq!(line("jitter", "host=h1", "latency", 2, &data_point),
+            "jitter,host=h1,cpu=2 latency=1500,irq_PCI-MSI\\ 524288-edge=3,cstate_C1\\,E\\=x=4 17");
+    }
+}
@@ -0,0 +1,223 @@
+use std::{fs::{self, File}, io::{self, Read}, sync::Arc};
+
+use log::{info, warn};
+
+use crate::{cpulist, error::{self, JitterError}, jitter::{Field, Jitter}, probe::IntervalProbe, utils};
+
+const PROC_INTERRUPTS: &str = "/proc/interrupts";
+const PROC_IRQ: &str = "/proc/irq";
+// nohz_full keeps a 1Hz tick for housekeeping, allow for the odd other timer on top
+const MAX_RESIDUAL_TICKS_PER_SEC: f64 = 2.0;
+
+
+/// Publishes how many times every interrupt listed in /proc/interrupts fired on the sampled cpu during each interval,
+/// as `irq_<name>` fields (eg: irq_LOC, irq_NMI, irq_24).
+pub struct InterruptsProbe {
+    column: usize,
+    names: Vec<Arc<str>>,
+    previous: Vec<u64>,
+    content: String,
+}
+
+impl InterruptsProbe {
+    pub fn new(cpu: u32) -> io::Result<InterruptsProbe> {
+        let content = fs::read_to_string(PROC_INTERRUPTS)?;
+        let cpu_label = format!("CPU{}", cpu);
+        let column = content.lines().next()
+            .and_then(|header| header.split_whitespace().position(|label| label == cpu_label))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} column in {}", cpu_label, PROC_INTERRUPTS)))?;
+
+        let (names, previous) = parse_counts(&content, column)
+            .map(|(name, count)| (Arc::from(format!("irq_{}", name)), count))
+            .unzip();
+
+        Ok(InterruptsProbe { column, names, previous, content })
+    }
+}
+
+impl IntervalProbe for InterruptsProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        self.content.clear();
+        if let Err(err) = File::open(PROC_INTERRUPTS).and_then(|mut file| file.read_to_string(&mut self.content)) {
+            log::error!(phase = "sample", error:% = err; "Unable to read {}: {}", PROC_INTERRUPTS, err);
+        }
+
+        // interrupts are keyed by position; lines only change when drivers come and go, in which case the
+        // mismatched ones are reported as 0 rather than shifting counts between names
+        let mut counts = parse_counts(&self.content, self.column);
+        for (name, previous) in self.names.iter().zip(self.previous.iter_mut()) {
+            let irq = &name["irq_".len()..];
+            let value = match counts.next() {
+                Some((current_name, count)) if current_name == irq => {
+                    let delta = count.saturating_sub(*previous);
+                    *previous = count;
+                    delta
+                },
+                _ => 0,
+            };
+            fields.push(Field { name: name.clone(), value: value as i64 });
+        }
+    }
+}
+
+
+/// Publishes the rate of local timer interrupts on the sampled cpu (`timer_interrupts_per_sec`). While the busy loop
+/// keeps the cpu running a single task, a nohz_full cpu only takes the residual 1Hz tick, any other cpu takes CONFIG_HZ.
+pub struct TickProbe {
+    column: usize,
+    timer: String,
+    name: Arc<str>,
+    previous: (u64, i64),
+    content: String,
+}
+
+impl TickProbe {
+    pub fn new(cpu: u32) -> io::Result<TickProbe> {
+        let content = fs::read_to_string(PROC_INTERRUPTS)?;
+        let cpu_label = format!("CPU{}", cpu);
+        let column = content.lines().next()
+            .and_then(|header| header.split_whitespace().position(|label| label == cpu_label))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} column in {}", cpu_label, PROC_INTERRUPTS)))?;
+        // LOC on x86, a numbered line described as arch_timer on arm64 or riscv-timer on riscv
+        let timer = content.lines().skip(1)
+            .find(|line| line.trim_start().starts_with("LOC:") || line.ends_with("arch_timer") || line.ends_with("riscv-timer"))
+            .and_then(|line| line.split_whitespace().next()?.strip_suffix(':'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no local timer interrupt in {}", PROC_INTERRUPTS)))?
+            .to_string();
+
+        let count = parse_counts(&content, column).find(|(name, _)| *name == timer).map_or(0, |(_, count)| count);
+        Ok(TickProbe { column, timer, name: Arc::from("timer_interrupts_per_sec"), previous: (count, utils::clock_monotonic()), content })
+    }
+}
+
+impl IntervalProbe for TickProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        self.content.clear();
+        if let Err(err) = File::open(PROC_INTERRUPTS).and_then(|mut file| file.read_to_string(&mut self.content)) {
+            log::error!(phase = "sample", error:% = err; "Unable to read {}: {}", PROC_INTERRUPTS, err);
+        }
+
+        let now = utils::clock_monotonic();
+        let count = parse_counts(&self.content, self.column).find(|(name, _)| *name == self.timer).map_or(self.previous.0, |(_, count)| count);
+        let elapsed = now - self.previous.1;
+        let per_sec = if elapsed > 0 { (count.saturating_sub(self.previous.0) as i64 * utils::NANOS_IN_SEC) / elapsed } else { 0 };
+        self.previous = (count, now);
+        fields.push(Field { name: self.name.clone(), value: per_sec });
+    }
+}
+
+
+/// Logs whether the scheduler tick stopped on `cpu` over the run, from the `timer_interrupts_per_sec` of its samples.
+pub fn report_tick_rate(cpu: u32, samples: &[Jitter]) {
+    let rates: Vec<i64> = samples.iter()
+        .filter_map(|sample| sample.fields.iter().find(|field| &*field.name == "timer_interrupts_per_sec"))
+        .map(|field| field.value)
+        .collect();
+    if rates.is_empty() {
+        return;
+    }
+
+    let mean = rates.iter().sum::<i64>() as f64 / rates.len() as f64;
+    let nohz_full = cpulist::nohz_full_cpus().unwrap_or_default().contains(&cpu);
+    if mean <= MAX_RESIDUAL_TICKS_PER_SEC {
+        info!("Cpu: {} took {:.1} timer interrupts/s, the scheduler tick stopped{}", cpu, mean, if nohz_full { " (nohz_full engaged)" } else { "" });
+    } else if nohz_full {
+        warn!(cpu = cpu, phase = "summary"; "Cpu: {} is nohz_full but took {:.1} timer interrupts/s, the tick did not stop (more than one runnable task, pending timers or rcu callbacks?)", cpu, mean);
+    } else {
+        warn!(cpu = cpu, phase = "summary"; "Cpu: {} took {:.1} timer interrupts/s, the scheduler tick is running (cpu not in nohz_full=)", cpu, mean);
+    }
+}
+
+
+/// Per-interrupt counts of the cpu in the given column. Summary lines such as ERR and MIS only carry a single
+/// system wide count, which is attributed to the first cpu.
+fn parse_counts(content: &str, column: usize) -> impl Iterator<Item = (&str, u64)> {
+    content.lines().skip(1).filter_map(move |line| {
+        let mut tokens = line.split_whitespace();
+        let name = tokens.next()?.strip_suffix(':')?;
+        let count = tokens.nth(column)?.parse::<u64>().ok()?;
+        Some((name, count))
+    })
+}
+
+
+/// An interrupt the kernel may deliver to some of the sampled cpus.
+#[derive(Debug, Clone)]
+pub struct RoutedIrq {
+    pub irq: u32,
+    /// Names of the handlers registered for it (eg: nvme0q1, eth0-TxRx-3), empty for unused lines.
+    pub actions: Vec<String>,
+    pub cpus: Vec<u32>,
+}
+
+
+/// Interrupts whose smp_affinity includes any of `cpus`.
+pub fn irqs_routed_to(cpus: &[u32]) -> io::Result<Vec<RoutedIrq>> {
+    let mut routed = Vec::new();
+    for entry in fs::read_dir(PROC_IRQ)? {
+        let entry = entry?;
+        let irq = match entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) {
+            Some(irq) => irq,
+            None => continue,
+        };
+        let affinity = cpulist::read_cpu_list(&format!("{}/{}/smp_affinity_list", PROC_IRQ, irq))?;
+        if !affinity.iter().any(|cpu| cpus.contains(cpu)) {
+            continue;
+        }
+
+        let mut actions: Vec<String> = fs::read_dir(entry.path())?
+            .filter_map(|action| action.ok())
+            .filter(|action| action.file_type().is_ok_and(|file_type| file_type.is_dir()))
+            .filter_map(|action| action.file_name().into_string().ok())
+            .collect();
+        actions.sort();
+        routed.push(RoutedIrq { irq, actions, cpus: affinity.into_iter().filter(|cpu| cpus.contains(cpu)).collect() });
+    }
+
+    routed.sort_by_key(|routed_irq| routed_irq.irq);
+    Ok(routed)
+}
+
+
+/// Keeps the interrupts routed to the sampled cpus on the housekeeping cpus (the online ones that are neither
+/// sampled nor isolated) for as long as it lives, restoring their previous affinity when dropped. Interrupts the
+/// kernel manages itself (eg: per-queue nvme ones) cannot be moved and only get reported.
+pub struct MovedIrqs {
+    previous: Vec<(u32, String)>,
+}
+
+impl MovedIrqs {
+    pub fn move_away_from(cpus: &[u32]) -> error::Result<MovedIrqs> {
+        let online = cpulist::online_cpus().map_err(JitterError::Irq)?;
+        let isolated = cpulist::isolated_cpus().unwrap_or_default();
+        let housekeeping: Vec<u32> = online.into_iter().filter(|cpu| !cpus.contains(cpu) && !isolated.contains(cpu)).collect();
+        if housekeeping.is_empty() {
+            return Err(JitterError::Irq(io::Error::new(io::ErrorKind::NotFound, "no housekeeping cpu left to move interrupts to")));
+        }
+        let housekeeping = cpulist::format_cpu_list(&housekeeping);
+
+        let mut moved = MovedIrqs { previous: Vec::new() };
+        for routed_irq in irqs_routed_to(cpus).map_err(JitterError::Irq)? {
+            let path = format!("{}/{}/smp_affinity_list", PROC_IRQ, routed_irq.irq);
+            let previous = fs::read_to_string(&path).map_err(JitterError::Irq)?.trim().to_string();
+            match fs::write(&path, &housekeeping) {
+                Ok(()) => moved.previous.push((routed_irq.irq, previous)),
+                Err(err) => warn!(phase = "setup", error:% = err; "Unable to move irq {} ({}) off cpus {}: {}",
+                                  routed_irq.irq, routed_irq.actions.join(","), cpulist::format_cpu_list(&routed_irq.cpus), err),
+            }
+        }
+        info!("Moved {} irqs to housekeeping cpus {} for the run", moved.previous.len(), housekeeping);
+
+        Ok(moved)
+    }
+}
+
+impl Drop for MovedIrqs {
+    fn drop(&mut self) {
+        for (irq, previous) in self.previous.drain(..) {
+            if let Err(err) = fs::write(format!("{}/{}/smp_affinity_list", PROC_IRQ, irq), &previous) {
+                warn!(phase = "setup", error:% = err; "Unable to restore affinity of irq {} to {}: {}", irq, previous, err);
+            }
+        }
+    }
+}
@@ -1,61 +1,282 @@
+use std::sync::Arc;
+
 use log::{info, warn};
+use nix::libc;
+
+use crate::{error::{self, JitterError}, ftrace::Ftrace, interrupts, memory::memory_loop, numa, observer::IntervalObserver, probe, raw::{RawRecorder, raw_output_path}, recorder::IntervalRecorder, sampler::{CpuJitter, SetupArrival, SetupLatch}, stalls, utils::{self, Clock, InterruptMode, MlockMode, Mode, ProgramArgs, ReadFuncConsumer, NANOS_IN_SEC, InterruptGuard}, wakeup::wakeup_loop, workload::WorkloadKernel};
+
+
+const MAX_OUTLIERS_PER_CPU: usize = 65_536;
+const MAX_DISCONTINUITIES_PER_CPU: usize = 1_024;
+const OVERHEAD_CALIBRATION_SAMPLES: usize = 100_000;
+const ROLLING_SAMPLES: usize = 10_000;
 
-use crate::{utils::{ProgramArgs, NANOS_IN_SEC, disable_lapic, enable_lapic}, influx::publish_results};
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: Arc<str>,
+    pub value: i64,
+}
 
 
-#[derive(Debug, Clone, Copy)]
+/// Aligned to (and padded out to) a cache line, so that the buffers of different sampler threads
+/// never share one and writing a record never touches more than one line.
+#[derive(Debug, Clone, Default)]
+#[repr(align(64))]
 pub struct Jitter {
     pub ts: i64,
     pub latency: i64,
+    pub fields: Vec<Field>,
 }
 
 
-pub fn capture_jitter(cpu: u32, program_args: &ProgramArgs) {
+pub const OUTLIER_MEASUREMENT: &str = "jitter_outlier";
+pub const TOP_LATENCIES_MEASUREMENT: &str = "jitter_top";
+pub const DISCONTINUITY_MEASUREMENT: &str = "jitter_discontinuity";
+pub const CORE_TO_CORE_MEASUREMENT: &str = "core_to_core_latency";
+pub const HISTOGRAM_BUCKETS_MEASUREMENT: &str = "jitter_histogram";
+
+
+pub fn capture_jitter(cpu: u32, program_args: &ProgramArgs, observers: &[Arc<dyn IntervalObserver>], tracer: Option<&Ftrace>, setup_latch: Option<&SetupLatch>) -> error::Result<CpuJitter> {
+    let setup = SetupArrival::new(setup_latch);
+    let program_args = &program_args.for_cpu(cpu);
+    if program_args.cpu_overrides.iter().any(|cpu_override| cpu_override.cpu == cpu) {
+        info!("Sampling cpu: {} in {:?} mode with workload: {:?} and rt priority: {:?}", cpu, program_args.mode, program_args.workload, program_args.rt_priority);
+    }
+
     info!("Affinitizing jitter sampler thread to cpu: {}", cpu);
-    crate::utils::affinitize_to_cpu(cpu);
+    utils::affinitize_to_cpu(cpu)?;
 
-    if program_args.lapic_disabled {
-        warn!("Disabling local APIC interrupts on cpu: {}. This may result in the whole machine becoming unresponsive", cpu);
-        disable_lapic();
+    if let Some(priority) = program_args.rt_priority {
+        info!("Setting SCHED_FIFO priority {} for sampler thread of cpu: {}", priority, cpu);
+        utils::set_realtime_priority(priority)?;
+    }
+
+    let mut clock = program_args.clock;
+    if clock.source().is_cycle_counter() {
+        clock.align_with_realtime();
+        info!("Aligned {} of cpu: {} with realtime, offset differs by {}ns from the calibrating thread", clock.source().name(), cpu, clock.offset() - program_args.clock.offset());
+    }
+    // wakeup mode hands nanoseconds of CLOCK_MONOTONIC shifted to realtime over to the recorder
+    let recorded_clock = match program_args.mode {
+        Mode::Busy | Mode::Memory | Mode::Tick | Mode::PingPong => clock,
+        Mode::Wakeup => Clock::default(),
+    };
+
+    let raw_recorder = program_args.raw_output.as_ref().map(|path| {
+        let path = raw_output_path(path, cpu, program_args.raw_format);
+        info!("Recording raw samples of cpu: {} to: {}", cpu, path);
+        RawRecorder::create(&path, cpu, &recorded_clock, program_args.raw_format)
+            .map_err(|source| JitterError::RawOutput { path, source })
+    }).transpose()?;
+
+    let node = bind_to_local_node(cpu, program_args.numa_strict)?;
+    let (sample_count, outlier_capacity, top_latencies_capacity) = buffer_capacities(program_args);
+    let mut result = CpuJitter {
+        cpu,
+        samples: Vec::with_capacity(sample_count),
+        outliers: Vec::with_capacity(outlier_capacity),
+        top_latencies: Vec::with_capacity(top_latencies_capacity),
+        discontinuities: Vec::with_capacity(MAX_DISCONTINUITIES_PER_CPU),
+        round_trips: Vec::new(),
+        histogram_buckets: Vec::new(),
+        histogram: None,
+    };
+    if program_args.huge_pages_enabled {
+        let advised = utils::advise_huge_pages(&result.samples)
+            .and_then(|samples| Ok(samples + utils::advise_huge_pages(&result.outliers)? + utils::advise_huge_pages(&result.top_latencies)?));
+        match advised {
+            Ok(bytes) => info!("Backing {}KiB of the buffers of cpu: {} with huge pages", bytes / 1024, cpu),
+            Err(err) => warn!(cpu = cpu, phase = "setup", error:% = err; "Unable to back buffers of cpu: {} with huge pages, falling back to regular pages: {}", cpu, err),
+        }
+    }
+    result.samples.resize(sample_count, Jitter::default());
+    if let Some(node) = node {
+        // pages recycled by the allocator may have been faulted in before the thread got bound
+        if let Err(err) = numa::bind_buffer_to_node(&result.samples, node) {
+            numa_failure(program_args.numa_strict, cpu, format!("Unable to move sample buffer of cpu: {} to numa node: {}: {}", cpu, node, err))?;
+        }
+    }
+    // outliers and top latencies get appended while sampling, fault their pages in now rather than on first write
+    utils::prefault(&mut result.outliers);
+    utils::prefault(&mut result.top_latencies);
+    if program_args.mlock == MlockMode::Targeted {
+        info!("Mlocking buffers of cpu: {} to RAM", cpu);
+        utils::mlock_buffer(&result.samples)?;
+        utils::mlock_buffer(&result.outliers)?;
+        utils::mlock_buffer(&result.top_latencies)?;
+    }
+
+    let clock_overhead = if program_args.subtract_overhead {
+        let overhead = utils::measure_clock_overhead(&clock, OVERHEAD_CALIBRATION_SAMPLES);
+        info!("Calibrated {} overhead on cpu: {} at {}ns, subtracting it from measured latencies", clock.source().name(), cpu, overhead);
+        Some(overhead)
+    } else {
+        None
+    };
+
+    if let Some(start_at) = program_args.start_at_nanos {
+        // before the probes take their first readings, so that the wait does not count towards the first interval
+        utils::wait_until_realtime(start_at);
+    }
+    let probes = probe::configure_probes(program_args, cpu);
+    let mut recorder = IntervalRecorder::new(program_args, observers, &mut result, raw_recorder, probes, &recorded_clock, clock_overhead)
+        .with_tracer(tracer);
+    setup.wait();
+
+    let interrupt_guard = if program_args.interrupt_mode == InterruptMode::Cli {
+        warn!(cpu = cpu, phase = "setup"; "Disabling local APIC interrupts on cpu: {}. This may result in the whole machine becoming unresponsive", cpu);
+        Some(InterruptGuard::disable())
+    } else {
+        None
+    };
+    match program_args.mode {
+        Mode::Busy | Mode::Tick => clock.source().with_read_func(BusyLoop { program_args, clock: &clock, interrupt_guard: interrupt_guard.as_ref(), recorder: &mut recorder }),
+        Mode::Wakeup => wakeup_loop(program_args, &mut recorder),
+        Mode::Memory => memory_loop(program_args, &clock, &mut recorder),
+        Mode::PingPong => unreachable!("ping-pong mode is measured by pingpong::capture_round_trips"),
     }
     
-    let sample_count = (program_args.duration_seconds * 1000 / program_args.report_interval_millis) as usize;
-    let mut results: Vec<Jitter> = vec![Jitter { ts: 0, latency: 0 }; sample_count];
-    busy_loop(program_args, &mut results);
-    
-    if program_args.lapic_disabled {
+    if let Some(interrupt_guard) = interrupt_guard {
         info!("Re-enabling local APIC interrupts on cpu: {}", cpu);
-        enable_lapic();
+        drop(interrupt_guard);
+    }
+
+    if utils::stop_requested() {
+        info!("Stop requested, ending sampling on cpu: {} early", cpu);
+    }
+
+    recorder.finish();
+
+    if program_args.mode == Mode::Tick {
+        interrupts::report_tick_rate(cpu, &result.samples);
     }
 
-    publish_results(program_args, cpu, results);
+    if program_args.stall_attribution_enabled {
+        stalls::attribute_worst_intervals(&result);
+    }
+
+    if result.outliers.len() == result.outliers.capacity() && !result.outliers.is_empty() {
+        warn!(cpu = cpu, phase = "sample"; "Outlier buffer of cpu: {} filled up, outliers beyond the first {} were not recorded", cpu, result.outliers.len());
+    }
+
+    Ok(result)
 }
 
 
-fn busy_loop(program_args: &ProgramArgs, jitter: &mut Vec<Jitter>) {
-    let mut previous = (program_args.time_func)();
-    let deadline = previous + program_args.duration_seconds * NANOS_IN_SEC;
-    let mut next_report = previous + program_args.report_interval_millis * 1_000_000;
+/// Capacities of the sample, outlier and top latency buffers of a cpu.
+fn buffer_capacities(program_args: &ProgramArgs) -> (usize, usize, usize) {
+    let sample_count = if program_args.duration_seconds == 0 {
+        ROLLING_SAMPLES
+    } else {
+        (program_args.duration_seconds * 1_000_000 / program_args.report_interval_micros) as usize
+    };
+    let outlier_capacity = if program_args.outlier_threshold_nanos.is_some() { MAX_OUTLIERS_PER_CPU } else { 0 };
+    let top_latencies_capacity = if program_args.duration_seconds == 0 { 1 } else { sample_count } * program_args.top_latencies;
 
-    let mut max = i64::MIN;
-    let mut idx = 0;
+    (sample_count, outlier_capacity, top_latencies_capacity)
+}
+
+
+/// Bytes of the buffers of `cpu` that get mlocked, counting every buffer as starting and ending mid-page.
+pub fn locked_buffer_bytes(program_args: &ProgramArgs, cpu: u32) -> usize {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let (sample_count, outlier_capacity, top_latencies_capacity) = buffer_capacities(&program_args.for_cpu(cpu));
+    [sample_count, outlier_capacity, top_latencies_capacity].iter()
+        .filter(|&&capacity| capacity > 0)
+        .map(|&capacity| (capacity * std::mem::size_of::<Jitter>()).div_ceil(page_size) * page_size + page_size)
+        .sum()
+}
 
-    while previous < deadline {
-        let mut now = (program_args.time_func)();
+
+/// Binds the allocations of the sampling thread to the numa node of its cpu, so that it never writes to remote memory.
+fn bind_to_local_node(cpu: u32, strict: bool) -> error::Result<Option<u32>> {
+    let node = match numa::cpu_node(cpu) {
+        Ok(node) => node,
+        Err(err) => {
+            numa_failure(strict, cpu, format!("Unable to find numa node of cpu: {}: {}", cpu, err))?;
+            return Ok(None);
+        },
+    };
+
+    match numa::bind_thread_to_node(node) {
+        Ok(()) => {
+            info!("Allocating buffers of cpu: {} on numa node: {}", cpu, node);
+            Ok(Some(node))
+        },
+        Err(err) => {
+            numa_failure(strict, cpu, format!("Unable to bind allocations of cpu: {} to numa node: {}: {}", cpu, node, err))?;
+            Ok(None)
+        },
+    }
+}
+
+
+fn numa_failure(strict: bool, cpu: u32, message: String) -> error::Result<()> {
+    if strict {
+        return Err(JitterError::Numa(message));
+    }
+    warn!(cpu = cpu, phase = "setup"; "{}", message);
+    Ok(())
+}
+
+
+/// The busy loop, monomorphized for the configured time source.
+struct BusyLoop<'a, 'b> {
+    program_args: &'a ProgramArgs,
+    clock: &'a Clock,
+    interrupt_guard: Option<&'a InterruptGuard>,
+    recorder: &'a mut IntervalRecorder<'b>,
+}
+
+impl ReadFuncConsumer for BusyLoop<'_, '_> {
+    type Output = ();
+
+    fn consume<R: Fn() -> i64>(self, read_ticks: R) {
+        busy_loop(self.program_args, self.clock, read_ticks, self.interrupt_guard, self.recorder)
+    }
+}
+
+
+/// `read_ticks` returns the same raw readings as `clock.ticks()`, without the indirect call.
+#[inline(always)]
+fn busy_loop(program_args: &ProgramArgs, clock: &Clock, read_ticks: impl Fn() -> i64, interrupt_guard: Option<&InterruptGuard>, recorder: &mut IntervalRecorder) {
+    let mut workload = WorkloadKernel::new(program_args.workload, program_args.working_set_kib);
+    // everything in here is in raw clock ticks, converting them is left to the recorder
+    let mut previous = read_ticks();
+    let deadline = if program_args.duration_seconds == 0 { i64::MAX } else { previous + clock.nanos_to_ticks(program_args.duration_seconds * NANOS_IN_SEC) };
+    let report_interval = clock.nanos_to_ticks(program_args.report_interval_micros * 1_000);
+    let mut next_report = previous + report_interval;
+    let interrupts_off = clock.nanos_to_ticks(program_args.lapic_max_off_millis * 1_000_000);
+    let mut next_interrupt_window = if interrupt_guard.is_some() { previous + interrupts_off } else { i64::MAX };
+    recorder.resync(previous);
+
+    while previous < deadline && !utils::stop_requested() {
+        workload.step();
+        let mut now = read_ticks();
         let latency = now - previous;
-        if latency > max {
-            max = latency
+        if recorder.record(latency, now) {
+            now = read_ticks();
+            recorder.resync(now);
         }
 
         if now > next_report {
-            next_report = now + program_args.report_interval_millis * 1_000_000;
-            jitter[idx].ts = now;
-            jitter[idx].latency = max;
-            max = i64::MIN;
-            idx += 1;
-            now = (program_args.time_func)();
+            // stay on the schedule of the run, skipping any intervals a stall went past
+            next_report += report_interval * ((now - next_report) / report_interval + 1);
+            recorder.report(now);
+            now = read_ticks();
+            recorder.resync(now);
+        }
+
+        if now > next_interrupt_window {
+            if let Some(interrupt_guard) = interrupt_guard {
+                interrupt_guard.open_window();
+            }
+            now = read_ticks();
+            next_interrupt_window = now + interrupts_off;
+            recorder.resync(now);
         }
 
         previous = now;
     }
-}
\ No newline at end of file
+}
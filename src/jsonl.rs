@@ -0,0 +1,72 @@
+use std::{fs::{File, OpenOptions}, io::{self, BufWriter, Write}, sync::Mutex};
+
+use serde_json::json;
+
+use crate::{jitter::Jitter, metadata::RunMetadata, sink::Sink};
+
+
+pub struct JsonLinesSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+    path: Option<String>,
+    local_hostname: String,
+    run_id: String,
+}
+
+impl JsonLinesSink {
+    pub fn create(path: Option<&str>, local_hostname: &str, run_id: &str) -> io::Result<JsonLinesSink> {
+        let path = path.filter(|&path| path != "-");
+        let writer: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+
+        Ok(JsonLinesSink { writer: Mutex::new(writer), path: path.map(str::to_string), local_hostname: local_hostname.to_string(), run_id: run_id.to_string() })
+    }
+}
+
+impl Sink for JsonLinesSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for data_point in samples {
+            let mut line = json!({ "host": self.local_hostname, "run_id": self.run_id, "cpu": cpu, "ts": data_point.ts, "latency": data_point.latency });
+            for field in &data_point.fields {
+                line[field.name.as_ref()] = json!(field.value);
+            }
+            writeln!(writer, "{}", line)?;
+        }
+
+        writer.flush()
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for event in events {
+            let mut line = json!({ "type": measurement, "host": self.local_hostname, "run_id": self.run_id, "cpu": cpu, "ts": event.ts, "latency": event.latency });
+            for field in &event.fields {
+                line[field.name.as_ref()] = json!(field.value);
+            }
+            writeln!(writer, "{}", line)?;
+        }
+
+        writer.flush()
+    }
+
+    fn publish_metadata(&self, metadata: &RunMetadata) -> io::Result<()> {
+        let mut line = json!({ "type": "run", "host": self.local_hostname, "run_id": metadata.run_id });
+        for (name, value) in metadata.fields() {
+            line[name] = json!(value);
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{}", line)?;
+        writer.flush()
+    }
+
+    fn reopen(&self) -> io::Result<()> {
+        if let Some(path) = self.path.as_deref() {
+            *self.writer.lock().unwrap() = Box::new(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?));
+        }
+
+        Ok(())
+    }
+}
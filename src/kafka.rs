@@ -0,0 +1,98 @@
+use std::{io, time::Duration};
+
+use isahc::{HttpClient, Request, auth::{Authentication, Credentials}, prelude::*};
+use serde_json::{Value, json};
+
+use crate::{jitter::Jitter, sink::Sink, utils::{KafkaFormat, ProgramArgs}};
+
+const RECORDS_PER_REQUEST: usize = 1000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const AVRO_VALUE_SCHEMA: &str = r#"{"type": "record", "name": "Jitter", "namespace": "jitter", "fields": [
+    {"name": "type", "type": "string"},
+    {"name": "host", "type": "string"},
+    {"name": "cpu", "type": "int"},
+    {"name": "ts", "type": "long"},
+    {"name": "latency", "type": "long"},
+    {"name": "fields", "type": {"type": "map", "values": "long"}}
+]}"#;
+
+
+/// Produces every interval (and event) as one record keyed by `<host>/cpu<N>`, so that the records of a cpu stay in order on one partition.
+/// Goes through a Kafka REST Proxy (v2 api), which takes care of the brokers, SASL and, for avro, the schema registry;
+/// see `KafkaProducerSink` for producing to the brokers directly.
+pub struct KafkaSink {
+    client: HttpClient,
+    topic_url: String,
+    format: KafkaFormat,
+    local_hostname: String,
+}
+
+impl KafkaSink {
+    pub fn create(rest_url: &str, program_args: &ProgramArgs) -> io::Result<KafkaSink> {
+        let mut builder = HttpClient::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(user) = &program_args.kafka_user {
+            let password = program_args.kafka_password.as_ref().map(|password| password.0.as_str()).unwrap_or_default();
+            builder = builder.authentication(Authentication::basic()).credentials(Credentials::new(user.as_str(), password));
+        }
+
+        Ok(KafkaSink {
+            client: builder.build()?,
+            topic_url: format!("{}/topics/{}", rest_url.trim_end_matches('/'), program_args.kafka_topic),
+            format: program_args.kafka_format,
+            local_hostname: program_args.local_hostname.clone(),
+        })
+    }
+
+    fn produce(&self, cpu: u32, record_type: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let key = format!("{}/cpu{}", self.local_hostname, cpu);
+        for chunk in data_points.chunks(RECORDS_PER_REQUEST) {
+            let records: Vec<Value> = chunk.iter()
+                .map(|data_point| json!({ "key": key, "value": record_value(&self.local_hostname, record_type, cpu, data_point) }))
+                .collect();
+            self.post(records)?;
+        }
+
+        Ok(())
+    }
+
+    fn post(&self, records: Vec<Value>) -> io::Result<()> {
+        let (content_type, body) = match self.format {
+            KafkaFormat::Json => ("application/vnd.kafka.json.v2+json", json!({ "records": records })),
+            KafkaFormat::Avro => ("application/vnd.kafka.avro.v2+json", json!({ "key_schema": "\"string\"", "value_schema": AVRO_VALUE_SCHEMA, "records": records })),
+        };
+        let request = Request::post(self.topic_url.as_str())
+            .header("Content-Type", content_type)
+            .header("Accept", "application/vnd.kafka.v2+json")
+            .body(body.to_string())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut response = self.client.send(request)?;
+        let message = response.text().unwrap_or_default();
+        if !response.status().is_success() {
+            return Err(io::Error::other(format!("Kafka REST proxy responded with {}: {}", response.status(), message.trim())));
+        }
+
+        // the proxy accepts the request as a whole but reports failures of individual records next to their offsets
+        let reply: Value = serde_json::from_str(&message).unwrap_or_default();
+        let offsets = reply["offsets"].as_array().map(Vec::as_slice).unwrap_or_default();
+        match offsets.iter().filter(|offset| !offset["error"].is_null()).count() {
+            0 => Ok(()),
+            failed => Err(io::Error::other(format!("Kafka rejected {} of {} records: {}", failed, offsets.len(), message.trim()))),
+        }
+    }
+}
+
+/// Value of the record of one interval (or event), whether produced through the REST proxy or straight to the brokers.
+pub fn record_value(local_hostname: &str, record_type: &str, cpu: u32, data_point: &Jitter) -> Value {
+    let fields: serde_json::Map<String, Value> = data_point.fields.iter().map(|field| (field.name.to_string(), json!(field.value))).collect();
+    json!({ "type": record_type, "host": local_hostname, "cpu": cpu, "ts": data_point.ts, "latency": data_point.latency, "fields": fields })
+}
+
+impl Sink for KafkaSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.produce(cpu, "jitter", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.produce(cpu, measurement, events)
+    }
+}
@@ -0,0 +1,781 @@
+use std::{collections::HashMap, io::{self, Read, Write}, net::TcpStream, os::raw::{c_int, c_uint}, sync::Mutex, thread, time::Duration};
+
+use log::{info, warn};
+use openssl_sys as ssl;
+
+use crate::{jitter::Jitter, kafka, sink::Sink, tls::TlsStream, utils::{KafkaFormat, KafkaSaslMechanism, ProgramArgs}};
+
+const DEFAULT_PORT: u16 = 9092;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const RECORDS_PER_BATCH: usize = 1000;
+// well below the 1MiB the brokers accept by default (message.max.bytes)
+const MAX_BATCH_BYTES: usize = 512 * 1024;
+const ACKS_ALL: i16 = -1;
+const METADATA_ATTEMPTS: u32 = 5;
+const METADATA_BACKOFF: Duration = Duration::from_millis(200);
+
+// api keys, each spoken in the oldest version every broker since Kafka 2.1 (and still 4.x) accepts
+const PRODUCE: (i16, i16) = (0, 3);
+const METADATA: (i16, i16) = (3, 4);
+const SASL_HANDSHAKE: (i16, i16) = (17, 1);
+const SASL_AUTHENTICATE: (i16, i16) = (36, 0);
+
+const LEADER_NOT_AVAILABLE: i16 = 5;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+
+/// Produces every interval (and event) as one json record keyed by `<host>/cpu<N>` straight to the brokers, speaking
+/// the Kafka protocol over TCP (optionally TLS, optionally authenticating with SASL PLAIN or SCRAM). Keys are partitioned
+/// like the Java client does (murmur2), so the records of a cpu stay in order on one partition; every batch waits for
+/// all in-sync replicas (acks=all).
+pub struct KafkaProducerSink {
+    bootstrap: Vec<String>,
+    topic: String,
+    client_id: String,
+    tls_enabled: bool,
+    ca_cert: Option<String>,
+    credentials: Option<(KafkaSaslMechanism, String, String)>,
+    local_hostname: String,
+    cluster: Mutex<Cluster>,
+}
+
+#[derive(Default)]
+struct Cluster {
+    correlation_id: i32,
+    /// `host:port` of every broker, by node id.
+    brokers: HashMap<i32, String>,
+    /// Node id of the leader of every partition of the topic, by partition.
+    leaders: Vec<i32>,
+    connections: HashMap<i32, Connection>,
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+}
+
+impl KafkaProducerSink {
+    pub fn create(brokers: &[String], program_args: &ProgramArgs) -> io::Result<KafkaProducerSink> {
+        if program_args.kafka_format == KafkaFormat::Avro {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Avro records need the schema registry of a Kafka REST proxy (--kafka), brokers only take json"));
+        }
+
+        let sink = KafkaProducerSink {
+            bootstrap: brokers.iter().map(|broker| with_default_port(broker)).collect(),
+            topic: program_args.kafka_topic.clone(),
+            client_id: format!("jitter-{}", program_args.local_hostname),
+            tls_enabled: program_args.kafka_tls_enabled || program_args.kafka_ca_cert.is_some(),
+            ca_cert: program_args.kafka_ca_cert.clone(),
+            credentials: program_args.kafka_user.as_ref().map(|user| {
+                (program_args.kafka_sasl_mechanism, user.clone(), program_args.kafka_password.as_ref().map(|password| password.0.clone()).unwrap_or_default())
+            }),
+            local_hostname: program_args.local_hostname.clone(),
+            cluster: Mutex::new(Cluster::default()),
+        };
+
+        // fail at startup rather than with the first interval when the brokers are unreachable or refuse the credentials
+        let mut cluster = sink.cluster.lock().unwrap();
+        sink.refresh_metadata(&mut cluster)?;
+        info!("Producing to {} partitions of Kafka topic {}", cluster.leaders.len(), sink.topic);
+        drop(cluster);
+
+        Ok(sink)
+    }
+
+    fn produce(&self, cpu: u32, record_type: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let key = format!("{}/cpu{}", self.local_hostname, cpu);
+        let mut cluster = self.cluster.lock().unwrap();
+        if cluster.leaders.is_empty() {
+            self.refresh_metadata(&mut cluster)?;
+        }
+        let partition = (murmur2(key.as_bytes()) & 0x7fff_ffff) as usize % cluster.leaders.len();
+
+        let mut records: Vec<(i64, Vec<u8>)> = Vec::with_capacity(data_points.len().min(RECORDS_PER_BATCH));
+        let mut batch_bytes = 0;
+        for (i, data_point) in data_points.iter().enumerate() {
+            let value = kafka::record_value(&self.local_hostname, record_type, cpu, data_point).to_string().into_bytes();
+            batch_bytes += value.len() + key.len();
+            records.push((data_point.ts / 1_000_000, value));
+            if records.len() == RECORDS_PER_BATCH || batch_bytes >= MAX_BATCH_BYTES || i + 1 == data_points.len() {
+                self.produce_batch(&mut cluster, partition, &record_batch(key.as_bytes(), &records))?;
+                records.clear();
+                batch_bytes = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends one batch to the leader of `partition`, looking the leader up again and retrying once should that fail.
+    fn produce_batch(&self, cluster: &mut Cluster, partition: usize, batch: &[u8]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(batch.len() + 64);
+        put_i16(&mut body, -1); // no transactional id
+        put_i16(&mut body, ACKS_ALL);
+        put_i32(&mut body, REQUEST_TIMEOUT.as_millis() as i32);
+        put_i32(&mut body, 1);
+        put_string(&mut body, &self.topic);
+        put_i32(&mut body, 1);
+        put_i32(&mut body, partition as i32);
+        put_bytes(&mut body, batch);
+
+        let mut attempt = 0;
+        loop {
+            let leader = *cluster.leaders.get(partition).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Partition {} of {} is gone", partition, self.topic)))?;
+            let result = self.request(cluster, leader, PRODUCE, &body).and_then(|response| {
+                let mut response = Decoder::new(&response);
+                for _ in 0..response.i32()? {
+                    response.string()?;
+                    for _ in 0..response.i32()? {
+                        let (_, error_code) = (response.i32()?, response.i16()?);
+                        response.i64()?;
+                        response.i64()?;
+                        if error_code != 0 {
+                            return Err(kafka_error(error_code, &format!("Producing to partition {} of {}", partition, self.topic)));
+                        }
+                    }
+                }
+                Ok(())
+            });
+
+            match result {
+                Err(err) if attempt == 0 => {
+                    warn!(phase = "publish", error:% = err; "Kafka produce failed: {}. Looking up the partition leaders again", err);
+                    cluster.connections.remove(&leader);
+                    self.refresh_metadata(cluster)?;
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Looks up the brokers and the leaders of the partitions of the topic (creating it when the brokers auto-create
+    /// topics), asking the bootstrap brokers in turn.
+    fn refresh_metadata(&self, cluster: &mut Cluster) -> io::Result<()> {
+        let mut body = Vec::new();
+        put_i32(&mut body, 1);
+        put_string(&mut body, &self.topic);
+        body.push(1); // allow_auto_topic_creation
+
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No Kafka brokers given");
+        for attempt in 1..=METADATA_ATTEMPTS {
+            for address in &self.bootstrap {
+                let response = self.connect(address).and_then(|mut connection| {
+                    cluster.correlation_id = cluster.correlation_id.wrapping_add(1);
+                    connection.request(cluster.correlation_id, &self.client_id, METADATA, &body)
+                });
+                match response.and_then(|response| parse_metadata(&response, &self.topic)) {
+                    Ok((brokers, leaders)) => {
+                        cluster.brokers = brokers;
+                        cluster.leaders = leaders;
+                        let (connections, leaders) = (&mut cluster.connections, &cluster.leaders);
+                        connections.retain(|node, _| leaders.contains(node));
+                        return Ok(());
+                    },
+                    // retrying will not change the mind of a broker that refused the credentials
+                    Err(err) if err.kind() == io::ErrorKind::PermissionDenied => return Err(io::Error::new(err.kind(), format!("Kafka broker {}: {}", address, err))),
+                    Err(err) => last_error = io::Error::new(err.kind(), format!("Kafka broker {}: {}", address, err)),
+                }
+            }
+            if attempt < METADATA_ATTEMPTS {
+                thread::sleep(METADATA_BACKOFF * attempt);
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn request(&self, cluster: &mut Cluster, node: i32, api: (i16, i16), body: &[u8]) -> io::Result<Vec<u8>> {
+        if !cluster.connections.contains_key(&node) {
+            let address = cluster.brokers.get(&node).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Kafka broker {} is not in the cluster metadata", node)))?;
+            let connection = self.connect(address)?;
+            cluster.connections.insert(node, connection);
+        }
+
+        cluster.correlation_id = cluster.correlation_id.wrapping_add(1);
+        let result = cluster.connections.get_mut(&node).unwrap().request(cluster.correlation_id, &self.client_id, api, body);
+        if result.is_err() {
+            // the response may still arrive, the connection can no longer tell which request it belongs to
+            cluster.connections.remove(&node);
+        }
+        result
+    }
+
+    fn connect(&self, address: &str) -> io::Result<Connection> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let stream: Box<dyn Stream> = if self.tls_enabled {
+            let host = address.rsplit_once(':').map_or(address, |(host, _)| host).trim_start_matches('[').trim_end_matches(']');
+            Box::new(TlsStream::connect(stream, host, self.ca_cert.as_deref())?)
+        } else {
+            Box::new(stream)
+        };
+
+        let mut connection = Connection { stream };
+        if let Some((mechanism, user, password)) = &self.credentials {
+            connection.authenticate(&self.client_id, *mechanism, user, password)
+                .map_err(|err| io::Error::new(err.kind(), format!("SASL {} authentication with {} failed: {}", mechanism.name(), address, err)))?;
+        }
+
+        Ok(connection)
+    }
+}
+
+impl Connection {
+    /// Sends a request and waits for its response, returning the body of the response.
+    fn request(&mut self, correlation_id: i32, client_id: &str, (api_key, api_version): (i16, i16), body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut request = Vec::with_capacity(body.len() + 64);
+        put_i32(&mut request, 0);
+        put_i16(&mut request, api_key);
+        put_i16(&mut request, api_version);
+        put_i32(&mut request, correlation_id);
+        put_string(&mut request, client_id);
+        request.extend_from_slice(body);
+        let size = (request.len() - 4) as i32;
+        request[..4].copy_from_slice(&size.to_be_bytes());
+        self.stream.write_all(&request)?;
+        self.stream.flush()?;
+
+        let mut size = [0; 4];
+        self.stream.read_exact(&mut size)?;
+        let size = i32::from_be_bytes(size);
+        if size < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid Kafka response size: {}", size)));
+        }
+        let mut response = vec![0; size as usize];
+        self.stream.read_exact(&mut response)?;
+        let responded_to = i32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+        if responded_to != correlation_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Kafka responded to request {} instead of {}", responded_to, correlation_id)));
+        }
+
+        response.drain(..4);
+        Ok(response)
+    }
+
+    fn authenticate(&mut self, client_id: &str, mechanism: KafkaSaslMechanism, user: &str, password: &str) -> io::Result<()> {
+        let mut body = Vec::new();
+        put_string(&mut body, mechanism.name());
+        let response = self.request(0, client_id, SASL_HANDSHAKE, &body)?;
+        let mut response = Decoder::new(&response);
+        let error_code = response.i16()?;
+        if error_code != 0 {
+            let enabled = (0..response.i32()?.max(0)).map(|_| response.string()).collect::<io::Result<Vec<String>>>()?;
+            return Err(kafka_error(error_code, &format!("Mechanism not enabled (enabled: {})", enabled.join(", "))));
+        }
+
+        match mechanism {
+            KafkaSaslMechanism::Plain => {
+                self.sasl_exchange(client_id, format!("\0{}\0{}", user, password).as_bytes())?;
+                Ok(())
+            },
+            KafkaSaslMechanism::ScramSha256 | KafkaSaslMechanism::ScramSha512 => self.scram(client_id, mechanism, user, password),
+        }
+    }
+
+    /// SCRAM (RFC 5802, without channel binding): proves knowledge of the password without sending it, and checks
+    /// that the broker knows it too.
+    fn scram(&mut self, client_id: &str, mechanism: KafkaSaslMechanism, user: &str, password: &str) -> io::Result<()> {
+        let mut nonce = [0u8; 24];
+        if unsafe { ssl::RAND_bytes(nonce.as_mut_ptr(), nonce.len() as c_int) } != 1 {
+            return Err(io::Error::other("Unable to generate a SCRAM nonce"));
+        }
+        let client_nonce = base64_encode(&nonce);
+        let client_first = scram_client_first(user, &client_nonce);
+
+        let server_first = String::from_utf8(self.sasl_exchange(client_id, format!("n,,{}", client_first).as_bytes())?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let (client_final, server_signature) = scram_client_final(mechanism, password, &client_first, &client_nonce, &server_first)?;
+
+        let server_final = String::from_utf8(self.sasl_exchange(client_id, client_final.as_bytes())?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if let Some(error) = scram_attribute(&server_final, 'e') {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+        }
+        if scram_attribute(&server_final, 'v').and_then(|signature| base64_decode(&signature)) != Some(server_signature) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the broker does not know the password (server signature mismatch)"));
+        }
+
+        Ok(())
+    }
+
+    /// One round of SASL authentication, returning what the broker answered.
+    fn sasl_exchange(&mut self, client_id: &str, auth_bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut body = Vec::with_capacity(auth_bytes.len() + 4);
+        put_bytes(&mut body, auth_bytes);
+        let response = self.request(0, client_id, SASL_AUTHENTICATE, &body)?;
+        let mut response = Decoder::new(&response);
+        let error_code = response.i16()?;
+        let message = response.nullable_string()?;
+        if error_code != 0 {
+            return Err(kafka_error(error_code, message.as_deref().unwrap_or("Authentication failed")));
+        }
+
+        response.bytes().map(<[u8]>::to_vec)
+    }
+}
+
+
+/// The bare client-first-message of SCRAM (without the gs2 header).
+fn scram_client_first(user: &str, client_nonce: &str) -> String {
+    format!("n={},r={}", user.replace('=', "=3D").replace(',', "=2C"), client_nonce)
+}
+
+
+/// The client-final-message answering `server_first`, with the proof of the password, and the signature the broker
+/// has to answer with.
+fn scram_client_final(mechanism: KafkaSaslMechanism, password: &str, client_first: &str, client_nonce: &str, server_first: &str) -> io::Result<(String, Vec<u8>)> {
+    let digest = unsafe { if mechanism == KafkaSaslMechanism::ScramSha256 { ssl::EVP_sha256() } else { ssl::EVP_sha512() } };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected SCRAM message: {}", server_first));
+    let server_nonce = scram_attribute(server_first, 'r').filter(|nonce| nonce.starts_with(client_nonce)).ok_or_else(invalid)?;
+    let salt = scram_attribute(server_first, 's').and_then(|salt| base64_decode(&salt)).ok_or_else(invalid)?;
+    let iterations = scram_attribute(server_first, 'i').and_then(|iterations| iterations.parse::<c_int>().ok()).filter(|&iterations| iterations > 0).ok_or_else(invalid)?;
+
+    let salted_password = pbkdf2(digest, password.as_bytes(), &salt, iterations)?;
+    let client_key = hmac(digest, &salted_password, b"Client Key")?;
+    let stored_key = hash(mechanism, &client_key);
+    let client_final = format!("c=biws,r={}", server_nonce);
+    let auth_message = format!("{},{},{}", client_first, server_first, client_final);
+    let client_signature = hmac(digest, &stored_key, auth_message.as_bytes())?;
+    let proof: Vec<u8> = client_key.iter().zip(&client_signature).map(|(key, signature)| key ^ signature).collect();
+    let server_signature = hmac(digest, &hmac(digest, &salted_password, b"Server Key")?, auth_message.as_bytes())?;
+
+    Ok((format!("{},p={}", client_final, base64_encode(&proof)), server_signature))
+}
+
+
+fn scram_attribute(message: &str, name: char) -> Option<String> {
+    message.split(',').find_map(|attribute| attribute.strip_prefix(name).and_then(|value| value.strip_prefix('='))).map(str::to_string)
+}
+
+
+/// Brokers by node id, and the leader of every partition of `topic`, from a Metadata (v4) response.
+fn parse_metadata(response: &[u8], topic: &str) -> io::Result<(HashMap<i32, String>, Vec<i32>)> {
+    let mut response = Decoder::new(response);
+    response.i32()?; // throttle time
+    let mut brokers = HashMap::new();
+    for _ in 0..response.i32()? {
+        let node = response.i32()?;
+        let host = response.string()?;
+        let port = response.i32()?;
+        response.nullable_string()?;
+        brokers.insert(node, if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) });
+    }
+    response.nullable_string()?; // cluster id
+    response.i32()?; // controller
+
+    for _ in 0..response.i32()? {
+        let error_code = response.i16()?;
+        let name = response.string()?;
+        response.i8()?;
+        let mut leaders = Vec::new();
+        for _ in 0..response.i32()? {
+            let (partition_error, partition, leader) = (response.i16()?, response.i32()?, response.i32()?);
+            // replicas and in-sync replicas
+            for _ in 0..2 {
+                for _ in 0..response.i32()?.max(0) {
+                    response.i32()?;
+                }
+            }
+            leaders.push((partition, partition_error, leader));
+        }
+        if name != topic {
+            continue;
+        }
+        if error_code != 0 {
+            return Err(kafka_error(error_code, &format!("Topic {}", topic)));
+        }
+        if let Some(&(partition, _, _)) = leaders.iter().find(|&&(_, partition_error, leader)| partition_error == LEADER_NOT_AVAILABLE || leader < 0) {
+            return Err(kafka_error(LEADER_NOT_AVAILABLE, &format!("Partition {} of {} has no leader yet", partition, topic)));
+        }
+        let mut leaders: Vec<(i32, i32)> = leaders.into_iter().map(|(partition, _, leader)| (partition, leader)).collect();
+        leaders.sort_unstable();
+        if leaders.is_empty() || leaders.iter().enumerate().any(|(i, &(partition, _))| partition != i as i32) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Incomplete partition metadata of {}", topic)));
+        }
+        return Ok((brokers, leaders.into_iter().map(|(_, leader)| leader).collect()));
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("Topic {} is missing from the metadata", topic)))
+}
+
+
+/// A record batch (magic 2, uncompressed) of `records` given as (timestamp in ms, value), all sharing `key`.
+fn record_batch(key: &[u8], records: &[(i64, Vec<u8>)]) -> Vec<u8> {
+    let base_timestamp = records[0].0;
+    let max_timestamp = records.iter().map(|&(timestamp, _)| timestamp).max().unwrap_or(base_timestamp);
+
+    let mut body = Vec::with_capacity(records.iter().map(|(_, value)| value.len() + key.len() + 16).sum::<usize>() + 64);
+    put_i16(&mut body, 0); // attributes: no compression, create time
+    put_i32(&mut body, records.len() as i32 - 1);
+    put_i64(&mut body, base_timestamp);
+    put_i64(&mut body, max_timestamp);
+    put_i64(&mut body, -1); // no producer id
+    put_i16(&mut body, -1);
+    put_i32(&mut body, -1);
+    put_i32(&mut body, records.len() as i32);
+    let mut record = Vec::new();
+    for (offset, (timestamp, value)) in records.iter().enumerate() {
+        record.clear();
+        record.push(0);
+        put_varint(&mut record, timestamp - base_timestamp);
+        put_varint(&mut record, offset as i64);
+        put_varint(&mut record, key.len() as i64);
+        record.extend_from_slice(key);
+        put_varint(&mut record, value.len() as i64);
+        record.extend_from_slice(value);
+        put_varint(&mut record, 0); // headers
+        put_varint(&mut body, record.len() as i64);
+        body.extend_from_slice(&record);
+    }
+
+    let mut batch = Vec::with_capacity(body.len() + 21);
+    put_i64(&mut batch, 0);
+    put_i32(&mut batch, (4 + 1 + 4 + body.len()) as i32);
+    put_i32(&mut batch, -1); // partition leader epoch
+    batch.push(2);
+    batch.extend_from_slice(&crc32c(&body).to_be_bytes());
+    batch.extend_from_slice(&body);
+    batch
+}
+
+
+fn with_default_port(broker: &str) -> String {
+    let broker = broker.trim();
+    match broker.rsplit_once(':') {
+        Some((host, _)) if !host.is_empty() && (!broker.starts_with('[') || host.ends_with(']')) => broker.to_string(),
+        _ => format!("{}:{}", broker, DEFAULT_PORT),
+    }
+}
+
+
+fn kafka_error(code: i16, context: &str) -> io::Error {
+    let name = match code {
+        3 => "UNKNOWN_TOPIC_OR_PARTITION",
+        5 => "LEADER_NOT_AVAILABLE",
+        6 => "NOT_LEADER_OR_FOLLOWER",
+        7 => "REQUEST_TIMED_OUT",
+        10 => "MESSAGE_TOO_LARGE",
+        19 => "NOT_ENOUGH_REPLICAS",
+        29 => "TOPIC_AUTHORIZATION_FAILED",
+        33 => "UNSUPPORTED_SASL_MECHANISM",
+        34 => "ILLEGAL_SASL_STATE",
+        58 => "SASL_AUTHENTICATION_FAILED",
+        _ => "",
+    };
+    let kind = if matches!(code, 29 | 33 | 34 | 58) { io::ErrorKind::PermissionDenied } else { io::ErrorKind::Other };
+    io::Error::new(kind, format!("{}: Kafka error {} {}", context, code, name).trim_end().to_string())
+}
+
+
+/// Murmur2 hash of a record key, as the default partitioner of the Java client computes it.
+fn murmur2(data: &[u8]) -> i32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h: u32 = 0x9747_b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).wrapping_mul(M);
+        k ^= k >> 24;
+        h = h.wrapping_mul(M) ^ k.wrapping_mul(M);
+    }
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate().rev() {
+            h ^= (byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+
+fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+
+fn hmac(digest: *const ssl::EVP_MD, key: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut mac = vec![0; ssl::EVP_MAX_MD_SIZE as usize];
+    let mut length: c_uint = 0;
+    let signed = unsafe {
+        let context = ssl::HMAC_CTX_new();
+        let signed = !context.is_null()
+            && ssl::HMAC_Init_ex(context, key.as_ptr() as *const _, key.len() as c_int, digest, std::ptr::null_mut()) == 1
+            && ssl::HMAC_Update(context, data.as_ptr(), data.len()) == 1
+            && ssl::HMAC_Final(context, mac.as_mut_ptr(), &mut length) == 1;
+        ssl::HMAC_CTX_free(context);
+        signed
+    };
+    if !signed {
+        return Err(io::Error::other("Unable to compute an HMAC"));
+    }
+
+    mac.truncate(length as usize);
+    Ok(mac)
+}
+
+
+fn hash(mechanism: KafkaSaslMechanism, data: &[u8]) -> Vec<u8> {
+    let mut hash = vec![0; ssl::EVP_MAX_MD_SIZE as usize];
+    let length = unsafe {
+        if mechanism == KafkaSaslMechanism::ScramSha256 {
+            ssl::SHA256(data.as_ptr(), data.len(), hash.as_mut_ptr());
+            32
+        } else {
+            ssl::SHA512(data.as_ptr(), data.len(), hash.as_mut_ptr());
+            64
+        }
+    };
+    hash.truncate(length);
+    hash
+}
+
+
+fn pbkdf2(digest: *const ssl::EVP_MD, password: &[u8], salt: &[u8], iterations: c_int) -> io::Result<Vec<u8>> {
+    let mut key = vec![0; unsafe { ssl::EVP_MD_size(digest) } as usize];
+    let derived = unsafe {
+        ssl::PKCS5_PBKDF2_HMAC(password.as_ptr() as *const _, password.len() as c_int, salt.as_ptr(), salt.len() as c_int, iterations, digest, key.len() as c_int, key.as_mut_ptr())
+    };
+    if derived != 1 {
+        return Err(io::Error::other("Unable to derive the salted password"));
+    }
+
+    Ok(key)
+}
+
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() { BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char } else { '=' });
+        }
+    }
+    encoded
+}
+
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        let bits = chunk.iter().enumerate().try_fold(0u32, |bits, (i, &symbol)| {
+            BASE64_ALPHABET.iter().position(|&letter| letter == symbol).map(|value| bits | (value as u32) << (18 - 6 * i))
+        })?;
+        match chunk.len() {
+            1 => return None,
+            length => decoded.extend_from_slice(&bits.to_be_bytes()[1..length]),
+        }
+    }
+    Some(decoded)
+}
+
+
+fn put_i16(buffer: &mut Vec<u8>, value: i16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+
+fn put_i32(buffer: &mut Vec<u8>, value: i32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+
+fn put_i64(buffer: &mut Vec<u8>, value: i64) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+
+fn put_string(buffer: &mut Vec<u8>, value: &str) {
+    put_i16(buffer, value.len() as i16);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+
+fn put_bytes(buffer: &mut Vec<u8>, value: &[u8]) {
+    put_i32(buffer, value.len() as i32);
+    buffer.extend_from_slice(value);
+}
+
+
+/// Zigzag encoded variable length integer, as used inside record batches.
+fn put_varint(buffer: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data }
+    }
+
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Truncated Kafka response"));
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn i8(&mut self) -> io::Result<i8> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let bytes = self.take(8)?;
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        Ok(i64::from_be_bytes(value))
+    }
+
+    fn nullable_string(&mut self) -> io::Result<Option<String>> {
+        match self.i16()? {
+            length if length < 0 => Ok(None),
+            length => Ok(Some(String::from_utf8_lossy(self.take(length as usize)?).into_owned())),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.nullable_string().map(Option::unwrap_or_default)
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        match self.i32()? {
+            length if length < 0 => Ok(&[]),
+            length => self.take(length as usize),
+        }
+    }
+}
+
+
+impl Sink for KafkaProducerSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.produce(cpu, "jitter", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.produce(cpu, measurement, events)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_hash_like_the_java_client() {
+        assert_eq!(murmur2(b"21"), -973_932_308);
+        assert_eq!(murmur2(b"foobar"), -790_332_482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985_981_536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1_486_304_829);
+        assert_eq!(murmur2(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8"), -58_897_971);
+        assert_eq!(murmur2(b"abc"), 479_470_107);
+    }
+
+    #[test]
+    fn batches_carry_a_castagnoli_checksum() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        let batch = record_batch(b"host/cpu0", &[(1_000, b"{}".to_vec()), (1_001, b"{}".to_vec())]);
+        assert_eq!(i32::from_be_bytes([batch[8], batch[9], batch[10], batch[11]]) as usize, batch.len() - 12);
+        assert_eq!(batch[16], 2);
+        assert_eq!(u32::from_be_bytes([batch[17], batch[18], batch[19], batch[20]]), crc32c(&batch[21..]));
+    }
+
+    #[test]
+    fn varints_are_zigzag_encoded() {
+        for (value, encoded) in [(0, vec![0]), (-1, vec![1]), (1, vec![2]), (63, vec![0x7e]), (-64, vec![0x7f]), (64, vec![0x80, 1]), (300, vec![0xd8, 4])] {
+            let mut buffer = Vec::new();
+            put_varint(&mut buffer, value);
+            assert_eq!(buffer, encoded, "{}", value);
+        }
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for (data, encoded) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy")] {
+            assert_eq!(base64_encode(data), encoded);
+            assert_eq!(base64_decode(encoded).as_deref(), Some(data));
+        }
+        assert_eq!(base64_decode("Z"), None);
+        assert_eq!(base64_decode("Zm9v!"), None);
+    }
+
+    #[test]
+    fn scram_sha_256_matches_rfc_7677() {
+        let client_first = scram_client_first("user", "rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(client_first, "n=user,r=rOprNGfwEbeRWgbNEkqO");
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        let (client_final, server_signature) = scram_client_final(KafkaSaslMechanism::ScramSha256, "pencil", &client_first, "rOprNGfwEbeRWgbNEkqO", server_first).unwrap();
+        assert_eq!(client_final, "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=");
+        assert_eq!(base64_encode(&server_signature), "6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+    }
+
+    #[test]
+    fn scram_rejects_foreign_nonces_and_malformed_messages() {
+        let client_first = scram_client_first("us=er,", "abc");
+        assert_eq!(client_first, "n=us=3Der=2C,r=abc");
+        for server_first in ["r=xyz123,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096", "r=abc123,s=!,i=4096", "r=abc123,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=0", "r=abc123,i=4096"] {
+            let err = scram_client_final(KafkaSaslMechanism::ScramSha512, "pencil", &client_first, "abc", server_first).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", server_first);
+        }
+    }
+
+    #[test]
+    fn broker_addresses_get_the_default_port() {
+        assert_eq!(with_default_port("kafka1"), "kafka1:9092");
+        assert_eq!(with_default_port("kafka1:19092"), "kafka1:19092");
+        assert_eq!(with_default_port("[::1]"), "[::1]:9092");
+        assert_eq!(with_default_port("[::1]:19092"), "[::1]:19092");
+    }
+}
@@ -0,0 +1,58 @@
+pub mod utils;
+pub mod error;
+pub mod cpulist;
+pub mod smt;
+pub mod jitter;
+pub mod recorder;
+pub mod wakeup;
+pub mod memory;
+pub mod pingpong;
+pub mod workload;
+pub mod influx;
+pub mod gzip;
+pub mod tls;
+pub mod csv;
+pub mod jsonl;
+pub mod graphite;
+pub mod statsd;
+pub mod kafka;
+pub mod kafka_producer;
+pub mod mqtt;
+pub mod socket;
+pub mod postgres;
+pub mod clickhouse;
+pub mod sqlite;
+pub mod grpc;
+pub mod raw;
+pub mod parquet;
+pub mod topn;
+pub mod sampler;
+pub mod observer;
+pub mod publisher;
+pub mod prometheus;
+pub mod sink;
+pub mod calibrate;
+pub mod check;
+pub mod compare;
+pub mod probe;
+pub mod interrupts;
+pub mod cpufreq;
+pub mod cstates;
+pub mod virt;
+pub mod cgroup;
+pub mod perf;
+pub mod ftrace;
+pub mod stalls;
+pub mod numa;
+pub mod systemd;
+pub mod summary;
+pub mod metadata;
+pub mod logging;
+pub mod tui;
+
+pub use jitter::{Jitter, Field};
+pub use sampler::{Sampler, CpuJitter};
+pub use sink::Sink;
+pub use observer::IntervalObserver;
+pub use utils::ProgramArgs;
+pub use error::JitterError;
@@ -0,0 +1,42 @@
+use std::io::Write;
+
+use env_logger::{Builder, Env};
+use log::kv::{self, VisitSource};
+use serde_json::{Map, Value, json};
+
+use crate::utils::LogFormat;
+
+
+/// Installs env_logger, filtered through `RUST_LOG` (info by default). The json format writes one object per record,
+/// with the structured fields of the record (eg: cpu, phase, error) next to its level, target and message.
+pub fn init(format: LogFormat) {
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("ts".to_string(), json!(buf.timestamp_micros().to_string()));
+            line.insert("level".to_string(), json!(record.level().as_str()));
+            line.insert("target".to_string(), json!(record.target()));
+            line.insert("message".to_string(), json!(record.args().to_string()));
+            // fields can only fail to render through a custom source, which the log macros never produce
+            let _ = record.key_values().visit(&mut Fields(&mut line));
+            writeln!(buf, "{}", Value::Object(line))
+        });
+    }
+
+    builder.init();
+}
+
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = value.to_u64().map(Value::from)
+            .or_else(|| value.to_i64().map(Value::from))
+            .or_else(|| value.to_bool().map(Value::from))
+            .unwrap_or_else(|| Value::from(value.to_string()));
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -1,197 +1,1868 @@
-mod utils;
-mod jitter;
-mod influx;
+use std::{ffi::OsString, fs, process::exit, time::Duration};
 
-use std::{iter::FromIterator, process::exit};
-
-use env_logger::Env;
-use log::{info, error};
-use nix::libc;
-use utils::*;
-use jitter::*;
-use clap::{Arg, ArgMatches, Command, ArgAction};
+use log::{info, warn, error};
+use jitter::{CpuJitter, JitterError, Sampler, ProgramArgs, compare::{CpuComparison, RunSource}, cpulist, influx::InfluxSink, logging, metadata::{self, RunMetadata}, observer, publisher::StreamingPublisher, raw, sink, summary::{self, CpuFailure}, systemd, utils::{self, ClickhouseFormat, Clock, CpuOverride, InterruptMode, KafkaFormat, KafkaSaslMechanism, LogFormat, MlockMode, Mode, Output, PerfCounter, RawFormat, Secret, SummaryFormat, TimeSource, Workload}};
+use clap::{Arg, ArgMatches, Command, ArgAction, parser::ValueSource};
 
 
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let matches = match_arguments();
+    match matches.subcommand() {
+        Some(("run", matches)) => run(parse_program_args(matches)),
+        Some(("calibrate", matches)) => calibrate(parse_calibration_args(matches)),
+        Some(("check", matches)) => check(&configure_cpus(matches)),
+        Some(("export", matches)) if *matches.get_one::<bool>("line_protocol").unwrap() => resend(matches.get_many::<String>("raw_files").unwrap().collect(), parse_publishing_args(matches)),
+        Some(("compare", matches)) => compare(matches),
+        Some(("export", matches)) => export(matches.get_many::<String>("raw_files").unwrap().collect(), parse_publishing_args(matches)),
+        _ => unreachable!("clap enforces a known subcommand"),
+    }
+}
+
 
-    let program_args = parse_program_args();
-    info!("Running with args:\n{:#?}", program_args);
+fn run(program_args: ProgramArgs) {
+    info!("Running with args:\n{:#?}", program_args.redacted());
 
-    if program_args.mlock_enabled {
-        mlock()
+    if (program_args.duration_seconds == 0 || program_args.daemon) && program_args.flush_intervals == 0 {
+        error!("Sampling until stopped requires --flush-intervals, results would otherwise only be published on exit");
+        exit(1);
     }
 
-    if program_args.lapic_disabled {
-        unsafe { 
-            if libc::iopl(3) != 0 {
-                error!("Error while changing privilege level of the process with iopl(). Unable to turn off LAPIC.");
-                exit(1);
-            }
+    let duration_micros = program_args.duration_seconds * MICROS_IN_SEC;
+    if duration_micros > 0 && program_args.report_interval_micros > duration_micros {
+        error!("Report interval of {}us is longer than the run of {}s, nothing would get reported", program_args.report_interval_micros, program_args.duration_seconds);
+        exit(1);
+    }
+    if duration_micros > 0 && duration_micros % program_args.report_interval_micros != 0 {
+        warn!("Report interval of {}us does not divide the run of {}s, the last {}us of it will not be reported", program_args.report_interval_micros, program_args.duration_seconds, duration_micros % program_args.report_interval_micros);
+    }
+
+    if let Some(start_at) = program_args.start_at_nanos {
+        let now = utils::clock_realtime();
+        if start_at <= now {
+            error!("Start time given with --start-at passed {}ms ago", (now - start_at) / 1_000_000);
+            exit(1);
+        }
+        match utils::realtime_sync_error_micros() {
+            Ok(Some(error_micros)) => info!("Sampling starts in {}ms, system clock synchronized to within an estimated {}us", (start_at - now) / 1_000_000, error_micros),
+            Ok(None) => warn!("Sampling starts in {}ms, but the system clock is not synchronized (NTP/PTP), runs on other hosts may not line up", (start_at - now) / 1_000_000),
+            Err(err) => warn!("Unable to tell whether the system clock is synchronized: {}", err),
         }
     }
 
+    // before anything starts a thread, only the forking one survives detaching
+    if program_args.daemon {
+        info!("Detaching, writing pid to: {}", program_args.pid_file);
+        if let Err(err) = utils::daemonize(&program_args.pid_file) {
+            error!("Unable to run as a daemon: {}", err);
+            exit(1);
+        }
+    }
+    let pid_file = if program_args.daemon { Some(program_args.pid_file.clone()) } else { None };
+
+    let sinks = sink::configure_sinks(&program_args).unwrap_or_else(|err| {
+        error!(phase = "setup", error:% = err; "Unable to configure output: {}", err);
+        exit(1);
+    });
+    info!("Run id: {}", program_args.run_id);
+    sink::publish_metadata(&sinks, &RunMetadata::collect(&program_args));
+    let mut observers = observer::configure_observers(&program_args).unwrap_or_else(|err| {
+        error!("Unable to configure live metrics: {}", err);
+        exit(1);
+    });
+
+    utils::install_stop_handler().unwrap_or_else(|err| {
+        error!(phase = "setup", error:% = err; "{}", err);
+        exit(1);
+    });
+
+    let (max_budget, p99_budget) = (program_args.fail_if_max_above_nanos, program_args.fail_if_p99_above_nanos);
+    let summary_format = program_args.summary_format;
     let cpus = program_args.cpus.clone();
-    let args = &program_args;
+    let results_on_stdout = program_args.outputs.contains(&Output::JsonLines) && program_args.output_path.as_deref().is_none_or(|path| path == "-");
+    let (results, failures) = if program_args.flush_intervals > 0 {
+        let flush_period = Duration::from_micros((program_args.report_interval_micros as usize * program_args.flush_intervals) as u64);
+        let publisher = StreamingPublisher::start(sinks, &program_args.cpus, flush_period, program_args.flush_intervals);
+        observers.push(publisher.clone());
+        utils::install_reopen_handler().unwrap_or_else(|err| {
+            error!(phase = "setup", error:% = err; "{}", err);
+            exit(1);
+        });
 
-    crossbeam::scope(|s| {
-        for cpu in cpus {
-            s.spawn(move |_| { capture_jitter(cpu, args); });
+        notify_systemd(&format!("READY=1\nMAINPID={}", std::process::id()));
+        let results = Sampler::new(program_args).with_observers(observers).run();
+        notify_systemd("STOPPING=1");
+        publisher.finish();
+        split_failures(results, &cpus)
+    } else {
+        if systemd::watchdog_interval().is_some() {
+            warn!("The systemd watchdog only gets pinged by the publisher thread, which requires --flush-intervals");
         }
-    }).unwrap();
-}
+        notify_systemd(&format!("READY=1\nMAINPID={}", std::process::id()));
+        let (results, failures) = split_failures(Sampler::new(program_args).with_observers(observers).run(), &cpus);
+        notify_systemd("STOPPING=1");
+        sink::publish_all(&sinks, &results);
+        // ends streams (eg: gRPC calls) before a failed budget exits without dropping them
+        drop(sinks);
+        (results, failures)
+    };
 
+    if let Some(pid_file) = pid_file {
+        let _ = fs::remove_file(pid_file);
+    }
 
-pub fn parse_program_args() -> ProgramArgs {
-    let matches = match_arguments();
-    
-    let program_args = ProgramArgs {
-        duration_seconds: *matches.get_one::<i64>("duration_seconds").expect("Unable to parse duration argument"),
-        report_interval_millis: *matches.get_one::<i64>("report_interval_millis").expect("Incorrect value for reporting interval"),
-        cpus: parse_cpu_list(matches.get_one::<String>("cpus").expect("Unable to extract cpu list from arg: cpus")),
-        time_func: configure_clock(&matches),
-        mlock_enabled: *matches.get_one::<bool>("mlock").unwrap(),
-        lapic_disabled: *matches.get_one::<bool>("lapic").unwrap(),
-        influx_url: matches.get_one::<String>("influx_url").expect("Unable to extract InfluxDB url from program args").clone(),
-        influx_db: matches.get_one::<String>("influx_db").expect("Unable to extract Influx database name from program args").clone(),
-        local_hostname: gethostname::gethostname().into_string().expect("Unable to obtain local hostname"),
+    let summaries: Vec<_> = results.iter().filter_map(summary::summarize).collect();
+    let printed = match (summary_format, results_on_stdout) {
+        (SummaryFormat::Off, _) => Ok(()),
+        (SummaryFormat::Table, false) => summary::write_table(&mut std::io::stdout(), &summaries, &failures),
+        (SummaryFormat::Table, true) => summary::write_table(&mut std::io::stderr(), &summaries, &failures),
+        (SummaryFormat::Json, false) => summary::write_json(&mut std::io::stdout(), &summaries, &failures),
+        (SummaryFormat::Json, true) => summary::write_json(&mut std::io::stderr(), &summaries, &failures),
     };
+    if let Err(err) = printed {
+        error!("Unable to print summary: {}", err);
+    }
+
+    if !within_budget(&results, max_budget, p99_budget) {
+        exit(3);
+    }
+    if !failures.is_empty() {
+        exit(4);
+    }
+}
+
+
+/// Separates the samples of the cpus that completed their run from the ones that failed, logging why they did.
+/// Failing to set up the run at all exits straight away.
+fn split_failures(results: Result<Vec<Result<CpuJitter, JitterError>>, JitterError>, cpus: &[u32]) -> (Vec<CpuJitter>, Vec<CpuFailure>) {
+    let results = results.unwrap_or_else(|err| {
+        error!(phase = "setup", error:% = err; "{}", err);
+        exit(1);
+    });
+
+    let mut successes = Vec::with_capacity(results.len());
+    let mut failures = Vec::new();
+    for (result, &cpu) in results.into_iter().zip(cpus) {
+        match result {
+            Ok(result) => successes.push(result),
+            Err(err) => {
+                error!(cpu = cpu, phase = "sample", error:% = err; "Sampling cpu: {} failed, carrying on with the other cpus: {}", cpu, err);
+                failures.push(CpuFailure { cpu, error: err.to_string() });
+            },
+        }
+    }
+
+    (successes, failures)
+}
+
+
+fn within_budget(results: &[CpuJitter], max_budget: Option<i64>, p99_budget: Option<i64>) -> bool {
+    let mut within_budget = true;
+    for result in results {
+        let max = result.histogram.as_ref().map(|histogram| histogram.max() as i64)
+            .or_else(|| result.samples.iter().map(|sample| sample.latency).max());
+        if let (Some(budget), Some(max)) = (max_budget, max) {
+            if max > budget {
+                error!(cpu = result.cpu, phase = "summary"; "Worst latency on cpu: {} was {}ns, above the budget of {}ns", result.cpu, max, budget);
+                within_budget = false;
+            }
+        }
+
+        let p99 = result.histogram.as_ref().filter(|histogram| !histogram.is_empty()).map(|histogram| histogram.value_at_quantile(0.99) as i64);
+        if let (Some(budget), Some(p99)) = (p99_budget, p99) {
+            if p99 > budget {
+                error!(cpu = result.cpu, phase = "summary"; "99th percentile latency on cpu: {} was {}ns, above the budget of {}ns", result.cpu, p99, budget);
+                within_budget = false;
+            }
+        }
+    }
+
+    within_budget
+}
+
+
+fn notify_systemd(state: &str) {
+    if let Err(err) = systemd::notify(state) {
+        warn!(phase = "publish", error:% = err; "Unable to notify systemd: {}", err);
+    }
+}
+
+
+fn calibrate(program_args: ProgramArgs) {
+    let clock = program_args.clock;
+    println!("time source: {}", clock.source().name());
+    if clock.source().is_cycle_counter() {
+        println!("counter frequency: {:.6} GHz", clock.frequency());
+    }
+
+    let calibrations = jitter::calibrate::calibrate(&program_args).unwrap_or_else(|err| {
+        error!(phase = "calibrate", error:% = err; "{}", err);
+        exit(1);
+    });
+    for calibration in calibrations {
+        println!("cpu {}: clock overhead {}ns, realtime offset {:+}ns relative to the main thread", calibration.cpu, calibration.clock_overhead, calibration.offset - clock.offset());
+    }
+}
+
+
+fn check(cpus: &[u32]) {
+    let findings = jitter::check::audit(cpus);
+    for finding in &findings {
+        println!("[{}] {}: {}", if finding.ok { " OK " } else { "WARN" }, finding.subject, finding.detail);
+    }
 
-    program_args
+    if findings.iter().any(|finding| !finding.ok) {
+        exit(2);
+    }
 }
 
 
-fn configure_clock(matches: &ArgMatches) -> fn() -> i64 {
-    if matches.contains_id("tsc_frequency") {
-        unsafe {
-            utils::TSC_FREQUENCY = *matches.get_one::<f64>("tsc_frequency").expect("Unable to parse TSC frequency");
+fn compare(matches: &ArgMatches) {
+    let program_args = parse_query_args(matches);
+    let max_regression_percent = *matches.get_one::<f64>("max_regression_percent").expect("Incorrect value for max regression");
+    let [baseline, candidate] = ["baseline", "candidate"].map(|run| {
+        let spec = matches.get_one::<String>(run).unwrap();
+        let source = RunSource::parse(spec).unwrap_or_else(|err| {
+            error!("Invalid {} run {}: {}", run, spec, err);
+            exit(1);
+        });
+        source.load(&program_args).unwrap_or_else(|err| {
+            error!(phase = "compare", error:% = err; "Unable to load {} run from {}: {}", run, spec, err);
+            exit(1);
+        })
+    });
+    if baseline.is_empty() || candidate.is_empty() {
+        error!("No samples found in the {} run", if baseline.is_empty() { "baseline" } else { "candidate" });
+        exit(1);
+    }
+
+    let comparisons = jitter::compare::compare(&baseline, &candidate, max_regression_percent);
+    if let Err(err) = jitter::compare::write_table(&mut std::io::stdout(), &comparisons, max_regression_percent) {
+        error!("Unable to print comparison: {}", err);
+    }
+
+    if !comparisons.iter().all(CpuComparison::passed) {
+        exit(3);
+    }
+}
+
+
+fn export(raw_files: Vec<&String>, program_args: ProgramArgs) {
+    let sinks = sink::configure_sinks(&program_args).unwrap_or_else(|err| {
+        error!(phase = "setup", error:% = err; "Unable to configure output: {}", err);
+        exit(1);
+    });
+
+    let results: Vec<_> = raw_files.into_iter()
+        .map(|path| {
+            info!("Replaying raw samples from: {}", path);
+            raw::replay(path, program_args.report_interval_micros).unwrap_or_else(|err| {
+                error!(phase = "export", error:% = err; "Unable to replay raw sample file {}: {}", path, err);
+                exit(1);
+            })
+        })
+        .collect();
+    sink::publish_all(&sinks, &results);
+}
+
+
+fn resend(spill_files: Vec<&String>, program_args: ProgramArgs) {
+    if !program_args.outputs.contains(&Output::Influx) {
+        error!("Line protocol can only be resent to influx");
+        exit(1);
+    }
+
+    let influx = InfluxSink::create(&program_args).unwrap_or_else(|err| {
+        error!(phase = "setup", error:% = err; "Unable to configure output: {}", err);
+        exit(1);
+    });
+
+    let mut failed = false;
+    for path in spill_files {
+        info!("Resending line protocol from: {}", path);
+        if let Err(err) = influx.resend(path) {
+            error!(phase = "export", error:% = err; "Unable to resend {}: {}", path, err);
+            failed = true;
         }
     }
-    
-    let time_func: TimeFunc = match matches.get_one::<String>("time_source").map(|s| { s.as_str() }) {
+
+    if failed {
+        exit(1);
+    }
+}
+
+
+pub fn parse_program_args(matches: &ArgMatches) -> ProgramArgs {
+    ProgramArgs {
+        duration_seconds: if *matches.get_one::<bool>("daemon").unwrap() && matches.value_source("duration_seconds") == Some(ValueSource::DefaultValue) {
+            0
+        } else {
+            *matches.get_one::<i64>("duration_seconds").expect("Unable to parse duration argument")
+        },
+        mode: configure_mode(matches),
+        wakeup_interval_micros: *matches.get_one::<i64>("wakeup_interval_micros").expect("Incorrect value for wakeup interval"),
+        workload: configure_workload(matches),
+        working_set_kib: *matches.get_one::<usize>("working_set_kib").expect("Incorrect value for working set size"),
+        cpus: configure_cpus(matches),
+        cpu_overrides: configure_cpu_overrides(matches),
+        clock: configure_clock(matches),
+        rt_priority: matches.get_one::<i32>("rt_priority").copied(),
+        mlock: configure_mlock(matches),
+        numa_strict: *matches.get_one::<bool>("numa_strict").unwrap(),
+        require_idle_siblings: *matches.get_one::<bool>("require_idle_siblings").unwrap(),
+        offline_siblings: *matches.get_one::<bool>("offline_siblings").unwrap(),
+        performance_governor: *matches.get_one::<bool>("performance_governor").unwrap(),
+        disable_turbo: *matches.get_one::<bool>("disable_turbo").unwrap(),
+        move_irqs: *matches.get_one::<bool>("move_irqs").unwrap(),
+        run_as: configure_run_as(matches),
+        huge_pages_enabled: *matches.get_one::<bool>("huge_pages").unwrap(),
+        interrupt_mode: configure_interrupt_mode(matches),
+        lapic_max_off_millis: configure_lapic_max_off(matches),
+        subtract_overhead: *matches.get_one::<bool>("subtract_overhead").unwrap(),
+        histogram_enabled: *matches.get_one::<bool>("histogram").unwrap() || *matches.get_one::<bool>("histogram_buckets").unwrap() || matches.value_source("percentiles") == Some(ValueSource::CommandLine) || matches.contains_id("fail_if_p99_above_nanos"),
+        histogram_significant_digits: *matches.get_one::<u8>("histogram_digits").expect("Incorrect value for histogram digits"),
+        histogram_max_nanos: *matches.get_one::<i64>("histogram_max_nanos").expect("Incorrect value for histogram max"),
+        histogram_buckets_enabled: *matches.get_one::<bool>("histogram_buckets").unwrap(),
+        interrupts_enabled: *matches.get_one::<bool>("interrupts").unwrap(),
+        context_switches_enabled: *matches.get_one::<bool>("context_switches").unwrap(),
+        iterations_enabled: *matches.get_one::<bool>("iterations").unwrap(),
+        cpu_frequency_enabled: *matches.get_one::<bool>("cpu_frequency").unwrap(),
+        cstates_enabled: *matches.get_one::<bool>("cstates").unwrap(),
+        steal_time_enabled: *matches.get_one::<bool>("steal_time").unwrap(),
+        cgroup_throttling_enabled: *matches.get_one::<bool>("cgroup_throttling").unwrap(),
+        perf_counters: matches.get_one::<String>("perf_counters").map(|list| parse_perf_counter_list(list)).unwrap_or_default(),
+        stall_attribution_enabled: *matches.get_one::<bool>("attribute_stalls").unwrap(),
+        raw_output: matches.get_one::<String>("raw_output").cloned(),
+        raw_format: configure_raw_format(matches),
+        outlier_threshold_nanos: matches.get_one::<i64>("outlier_threshold_nanos").copied(),
+        discontinuity_threshold_nanos: *matches.get_one::<i64>("discontinuity_threshold_micros").expect("Incorrect value for discontinuity threshold") * 1_000,
+        trace_on_outlier: *matches.get_one::<bool>("trace_on_outlier").unwrap(),
+        top_latencies: *matches.get_one::<usize>("top_latencies").expect("Incorrect value for top latencies"),
+        percentiles: parse_percentile_list(matches.get_one::<String>("percentiles").expect("Unable to extract percentile list from arg: percentiles")),
+        flush_intervals: *matches.get_one::<usize>("flush_intervals").expect("Incorrect value for flush intervals"),
+        prometheus_listen: matches.get_one::<String>("prometheus_listen").cloned(),
+        tui_enabled: *matches.get_one::<bool>("tui").unwrap(),
+        summary_format: configure_summary_format(matches),
+        fail_if_max_above_nanos: matches.get_one::<i64>("fail_if_max_above_nanos").copied(),
+        fail_if_p99_above_nanos: matches.get_one::<i64>("fail_if_p99_above_nanos").copied(),
+        daemon: *matches.get_one::<bool>("daemon").unwrap(),
+        pid_file: matches.get_one::<String>("pid_file").cloned().unwrap(),
+        start_at_nanos: matches.get_one::<i64>("start_at").copied(),
+        ..parse_publishing_args(matches)
+    }
+}
+
+
+/// Options shared by every subcommand that publishes results.
+fn parse_publishing_args(matches: &ArgMatches) -> ProgramArgs {
+    ProgramArgs {
+        report_interval_micros: *matches.get_one::<i64>("report_interval_micros").expect("Incorrect value for reporting interval"),
+        outputs: configure_outputs(matches),
+        output_path: matches.get_one::<String>("output_path").cloned(),
+        influx_url: matches.get_one::<String>("influx_url").cloned().unwrap_or_default(),
+        influx_db: matches.get_one::<String>("influx_db").cloned().unwrap_or_default(),
+        influx_org: matches.get_one::<String>("influx_org").cloned().unwrap_or_default(),
+        influx_bucket: matches.get_one::<String>("influx_bucket").cloned(),
+        influx_token: matches.get_one::<String>("influx_token").cloned().map(Secret),
+        influx_user: matches.get_one::<String>("influx_user").cloned(),
+        influx_password: matches.get_one::<String>("influx_password").cloned().map(Secret),
+        influx_ca_cert: matches.get_one::<String>("influx_ca_cert").cloned(),
+        influx_insecure_skip_verify: *matches.get_one::<bool>("insecure_skip_verify").unwrap(),
+        influx_timeout_seconds: *matches.get_one::<u64>("influx_timeout_seconds").expect("Incorrect value for Influx timeout"),
+        influx_gzip_enabled: !*matches.get_one::<bool>("influx_no_gzip").unwrap(),
+        influx_batch_max_bytes: *matches.get_one::<usize>("influx_batch_max_bytes").expect("Incorrect value for Influx batch size"),
+        influx_batch_max_points: matches.get_one::<usize>("influx_batch_max_points").copied(),
+        influx_retries: *matches.get_one::<u32>("influx_retries").expect("Incorrect value for Influx retries"),
+        influx_retry_backoff_millis: *matches.get_one::<u64>("influx_retry_backoff_millis").expect("Incorrect value for Influx retry backoff"),
+        influx_spill_path: Some(matches.get_one::<String>("influx_spill_path").cloned().unwrap_or_else(default_spill_path)),
+        influx_measurement: matches.get_one::<String>("influx_measurement").cloned().unwrap(),
+        influx_tags: matches.get_many::<(String, String)>("influx_tags").map(|tags| tags.cloned().collect()).unwrap_or_default(),
+        graphite_address: matches.get_one::<String>("graphite_address").cloned(),
+        statsd_address: matches.get_one::<String>("statsd_address").cloned(),
+        kafka_rest_url: matches.get_one::<String>("kafka_rest_url").cloned(),
+        kafka_brokers: matches.get_one::<String>("kafka_brokers").map_or_else(Vec::default, |brokers| brokers.split(',').map(str::trim).filter(|broker| !broker.is_empty()).map(str::to_string).collect()),
+        kafka_topic: matches.get_one::<String>("kafka_topic").cloned().unwrap(),
+        kafka_format: configure_kafka_format(matches),
+        kafka_user: matches.get_one::<String>("kafka_user").cloned(),
+        kafka_password: matches.get_one::<String>("kafka_password").cloned().map(Secret),
+        kafka_sasl_mechanism: configure_kafka_sasl_mechanism(matches),
+        kafka_tls_enabled: *matches.get_one::<bool>("kafka_tls").unwrap(),
+        kafka_ca_cert: matches.get_one::<String>("kafka_ca_cert").cloned(),
+        line_protocol_socket: matches.get_one::<String>("line_protocol_socket").cloned(),
+        postgres_url: matches.get_one::<String>("postgres_url").cloned(),
+        postgres_table: matches.get_one::<String>("postgres_table").cloned().unwrap(),
+        postgres_create_table: *matches.get_one::<bool>("postgres_create_table").unwrap(),
+        clickhouse_url: matches.get_one::<String>("clickhouse_url").cloned(),
+        clickhouse_table: matches.get_one::<String>("clickhouse_table").cloned().unwrap(),
+        clickhouse_format: configure_clickhouse_format(matches),
+        clickhouse_create_table: *matches.get_one::<bool>("clickhouse_create_table").unwrap(),
+        clickhouse_user: matches.get_one::<String>("clickhouse_user").cloned(),
+        clickhouse_password: matches.get_one::<String>("clickhouse_password").cloned().map(Secret),
+        sqlite_path: matches.get_one::<String>("sqlite_path").cloned(),
+        grpc_url: matches.get_one::<String>("grpc_url").cloned(),
+        mqtt_url: matches.get_one::<String>("mqtt_url").cloned(),
+        mqtt_topic: matches.get_one::<String>("mqtt_topic").cloned().unwrap(),
+        mqtt_qos: *matches.get_one::<u8>("mqtt_qos").expect("Incorrect value for MQTT QoS"),
+        mqtt_user: matches.get_one::<String>("mqtt_user").cloned(),
+        mqtt_password: matches.get_one::<String>("mqtt_password").cloned().map(Secret),
+        local_hostname: configure_hostname(matches),
+        run_id: matches.get_one::<String>("run_id").cloned().unwrap_or_else(metadata::new_run_id),
+        ..ProgramArgs::default()
+    }
+}
+
+
+/// Influx connection options of subcommands that only read results back.
+fn parse_query_args(matches: &ArgMatches) -> ProgramArgs {
+    ProgramArgs {
+        influx_url: matches.get_one::<String>("influx_url").cloned().unwrap_or_default(),
+        influx_db: matches.get_one::<String>("influx_db").cloned().unwrap_or_default(),
+        influx_bucket: matches.get_one::<String>("influx_bucket").cloned(),
+        influx_token: matches.get_one::<String>("influx_token").cloned().map(Secret),
+        influx_user: matches.get_one::<String>("influx_user").cloned(),
+        influx_password: matches.get_one::<String>("influx_password").cloned().map(Secret),
+        influx_ca_cert: matches.get_one::<String>("influx_ca_cert").cloned(),
+        influx_insecure_skip_verify: *matches.get_one::<bool>("insecure_skip_verify").unwrap(),
+        influx_timeout_seconds: *matches.get_one::<u64>("influx_timeout_seconds").expect("Incorrect value for Influx timeout"),
+        influx_measurement: matches.get_one::<String>("influx_measurement").cloned().unwrap(),
+        ..ProgramArgs::default()
+    }
+}
+
+
+fn parse_calibration_args(matches: &ArgMatches) -> ProgramArgs {
+    ProgramArgs {
+        cpus: configure_cpus(matches),
+        clock: configure_clock(matches),
+        ..ProgramArgs::default()
+    }
+}
+
+
+/// Counter based time sources, each only compiled in on the architecture providing it.
+const ARCH_SPECIFIC_TIME_SOURCES: [&str; 6] = ["rdtsc", "rdtsc_lfence", "rdtscp", "cntvct", "rdtime", "rdcycle"];
+
+
+fn configure_clock(matches: &ArgMatches) -> Clock {
+    let time_source = match matches.get_one::<String>("time_source").map(|s| { s.as_str() }) {
         Some(clock_type) => match clock_type {
-            "clock_realtime" => clock_realtime,
-            "clock_monotonic" => clock_monotonic,
-            "rdtsc" => clock_rdtsc,
+            "clock_realtime" => TimeSource::ClockRealtime,
+            "clock_monotonic" => TimeSource::ClockMonotonic,
+            "clock_monotonic_raw" => TimeSource::ClockMonotonicRaw,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            "rdtsc" => {
+                verify_invariant_tsc(matches);
+                TimeSource::Rdtsc
+            },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            "rdtsc_lfence" => {
+                verify_invariant_tsc(matches);
+                TimeSource::RdtscLfence
+            },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            "rdtscp" => {
+                verify_invariant_tsc(matches);
+                TimeSource::Rdtscp
+            },
+            #[cfg(target_arch = "aarch64")]
+            "cntvct" => TimeSource::Cntvct,
+            #[cfg(target_arch = "riscv64")]
+            "rdtime" => TimeSource::Rdtime,
+            #[cfg(target_arch = "riscv64")]
+            "rdcycle" => {
+                if !matches.contains_id("tsc_frequency") {
+                    error!(phase = "calibrate"; "rdcycle ticks at the core clock which cannot be detected, pass its frequency with --tsc-frequency");
+                    exit(1);
+                }
+                TimeSource::Rdcycle
+            },
+            "instant" => TimeSource::Instant,
+            other if ARCH_SPECIFIC_TIME_SOURCES.contains(&other) => {
+                log::warn!(phase = "calibrate"; "Time source {} is not available on this architecture, falling back to instant", clock_type);
+                TimeSource::Instant
+            },
             _ => {
                 error!("Unrecognized clock type: {}", clock_type);
                 exit(1);
             }
         },
-        None => clock_realtime
+        None => TimeSource::ClockRealtime
+    };
+
+    Clock::new(time_source, matches.get_one::<f64>("tsc_frequency").copied()).unwrap_or_else(|err| {
+        error!(phase = "calibrate", error:% = err; "{}", err);
+        exit(1);
+    })
+}
+
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn verify_invariant_tsc(matches: &ArgMatches) {
+    if utils::tsc_is_invariant() {
+        return;
+    }
+
+    if *matches.get_one::<bool>("allow_unstable_tsc").unwrap() {
+        log::warn!(phase = "calibrate"; "!!! TSC on this machine is NOT invariant. Its rate changes with frequency scaling and C-states, latencies measured with rdtsc are not meaningful !!!");
+    } else {
+        error!(phase = "calibrate"; "TSC on this machine is not invariant, refusing to use rdtsc as time source (pass --allow-unstable-tsc to override)");
+        exit(1);
+    }
+}
+
+
+const MICROS_IN_SEC: i64 = 1_000_000;
+
+
+/// Seconds of a run duration such as 90, 90s, 15m or 2h; 0 and `forever` sample until stopped.
+fn parse_duration(value: &str) -> Result<i64, String> {
+    match value {
+        "forever" => Ok(0),
+        _ => parse_time_span(value, MICROS_IN_SEC)
+            .filter(|&micros| micros % MICROS_IN_SEC == 0)
+            .map(|micros| micros / MICROS_IN_SEC)
+            .ok_or_else(|| format!("expected whole seconds, with an optional unit: s | m | h | d (eg: 15m), or 'forever', got: {}", value)),
+    }
+}
+
+
+/// Microseconds of a report interval; a plain number is taken as milliseconds, as it always has been.
+fn parse_report_interval(value: &str) -> Result<i64, String> {
+    parse_time_span(value, 1_000)
+        .filter(|&micros| micros > 0)
+        .ok_or_else(|| format!("expected a positive interval such as 100, 500us, 10ms or 1s, got: {}", value))
+}
+
+
+/// Nanoseconds since the epoch of an RFC 3339 timestamp, eg: 2024-05-01T12:00:00.5+02:00 (or Z for UTC).
+fn parse_rfc3339(value: &str) -> Result<i64, String> {
+    let invalid = || format!("expected an RFC 3339 timestamp such as 2024-05-01T12:00:00Z, got: {}", value);
+    let number = |digits: &str| if !digits.is_empty() && digits.bytes().all(|digit| digit.is_ascii_digit()) { digits.parse::<i64>().ok() } else { None };
+    let (date, time) = value.trim().split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+
+    let (year, month, day) = match date.split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] => (number(year).ok_or_else(invalid)?, number(month).ok_or_else(invalid)?, number(day).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    let (time, offset_seconds) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (hours, minutes) = time[sign_at + 1..].split_once(':').ok_or_else(invalid)?;
+        let offset = number(hours).ok_or_else(invalid)? * 3_600 + number(minutes).ok_or_else(invalid)? * 60;
+        (&time[..sign_at], if time[sign_at..].starts_with('-') { -offset } else { offset })
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let (hour, minute, second) = match time.split(':').collect::<Vec<_>>()[..] {
+        [hour, minute, second] => (number(hour).ok_or_else(invalid)?, number(minute).ok_or_else(invalid)?, number(second).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || fraction.len() > 9 {
+        return Err(invalid());
+    }
+    let nanos = if fraction.is_empty() { 0 } else { number(fraction).ok_or_else(invalid)? * 10_i64.pow(9 - fraction.len() as u32) };
+
+    // days since the epoch of the proleptic Gregorian date, with years starting in March so that leap days come last
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok((days * 86_400 + hour * 3_600 + minute * 60 + second - offset_seconds) * utils::NANOS_IN_SEC + nanos)
+}
+
+
+/// Microseconds of a time span with one of the units us, ms, s, m, h or d; a plain number counts `default_unit` microseconds.
+fn parse_time_span(value: &str, default_unit: i64) -> Option<i64> {
+    const UNITS: [(&str, i64); 6] = [("us", 1), ("ms", 1_000), ("s", MICROS_IN_SEC), ("m", 60 * MICROS_IN_SEC), ("h", 3_600 * MICROS_IN_SEC), ("d", 86_400 * MICROS_IN_SEC)];
+    let value = value.trim();
+    let (number, unit) = UNITS.iter()
+        .find_map(|&(suffix, unit)| value.strip_suffix(suffix).map(|number| (number, unit)))
+        .unwrap_or((value, default_unit));
+    number.trim().parse::<i64>().ok()
+        .filter(|&number| number >= 0)
+        .and_then(|number| number.checked_mul(unit))
+}
+
+
+/// Bytes of a size such as 65536, 512KiB, 1MiB or 1MB: K, M and G (optionally followed by iB) are powers of 1024, KB, MB and GB powers of 1000.
+fn parse_size(value: &str) -> Result<usize, String> {
+    const UNITS: [(&str, usize); 10] = [
+        ("KiB", 1 << 10), ("MiB", 1 << 20), ("GiB", 1 << 30),
+        ("KB", 1_000), ("MB", 1_000_000), ("GB", 1_000_000_000),
+        ("K", 1 << 10), ("M", 1 << 20), ("G", 1 << 30),
+        ("B", 1),
+    ];
+    let value = value.trim();
+    let (number, unit) = UNITS.iter()
+        .find_map(|&(suffix, unit)| value.strip_suffix(suffix).map(|number| (number, unit)))
+        .unwrap_or((value, 1));
+    number.trim().parse::<usize>().ok()
+        .and_then(|number| number.checked_mul(unit))
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("expected a positive number of bytes, with an optional unit such as KiB, MiB or MB (eg: 512KiB), got: {}", value))
+}
+
+
+/// Timestamped, so that spills of separate runs can be told apart and resent one by one with `export --line-protocol`.
+fn default_spill_path() -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    format!("jitter.spill.{}", now.as_secs())
+}
+
+
+fn configure_hostname(matches: &ArgMatches) -> String {
+    if let Some(hostname) = matches.get_one::<String>("hostname") {
+        return hostname.clone();
+    }
+
+    let hostname = gethostname::gethostname().into_string().unwrap_or_else(|hostname| {
+        error!("Local hostname is not valid unicode: {:?}", hostname);
+        exit(1);
+    });
+    if *matches.get_one::<bool>("fqdn").unwrap() {
+        match utils::fully_qualified_hostname(&hostname) {
+            Some(fqdn) => return fqdn,
+            None => warn!("Unable to resolve fully qualified name of {}, publishing the short hostname instead", hostname),
+        }
+    }
+
+    hostname
+}
+
+
+fn parse_tag(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, tag_value)) if !key.is_empty() && !tag_value.is_empty() => Ok((key.to_string(), tag_value.to_string())),
+        _ => Err(format!("expected key=value, got: {}", value)),
+    }
+}
+
+
+/// Table names go into the SQL handed to psql as they are, so only plain (optionally schema-qualified) identifiers pass.
+fn parse_postgres_table(value: &str) -> Result<String, String> {
+    let identifier = |name: &str| name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match value.split_once('.') {
+        Some((schema, table)) if identifier(schema) && identifier(table) => Ok(value.to_string()),
+        None if identifier(value) => Ok(value.to_string()),
+        _ => Err(format!("expected a table name such as jitter or metrics.jitter (letters, digits and underscores), got: {}", value)),
+    }
+}
+
+
+fn configure_mlock(matches: &ArgMatches) -> MlockMode {
+    if *matches.get_one::<bool>("mlock_all").unwrap() {
+        MlockMode::All
+    } else if *matches.get_one::<bool>("mlock").unwrap() {
+        MlockMode::Targeted
+    } else {
+        MlockMode::Off
+    }
+}
+
+
+fn configure_run_as(matches: &ArgMatches) -> Option<String> {
+    let user = matches.get_one::<String>("run_as")?;
+    match nix::unistd::User::from_name(user) {
+        Ok(Some(_)) => Some(user.clone()),
+        Ok(None) => {
+            error!("Unknown user to run as: {}", user);
+            exit(1);
+        },
+        Err(err) => {
+            error!("Unable to look up user to run as: {}: {}", user, err);
+            exit(1);
+        },
+    }
+}
+
+
+fn configure_interrupt_mode(matches: &ArgMatches) -> InterruptMode {
+    if *matches.get_one::<bool>("lapic").unwrap() {
+        InterruptMode::Cli
+    } else {
+        InterruptMode::Normal
+    }
+}
+
+
+fn configure_lapic_max_off(matches: &ArgMatches) -> i64 {
+    let max_off_millis = *matches.get_one::<i64>("lapic_max_off_millis").expect("Incorrect value for maximum interrupt-off duration");
+    if configure_interrupt_mode(matches) == InterruptMode::Cli {
+        if let Some(threshold_millis) = utils::hard_lockup_threshold_millis() {
+            if max_off_millis >= threshold_millis / 2 {
+                error!("Keeping interrupts disabled for {}ms at a time would trip the hard-lockup watchdog ({}ms), lower --lapic-max-off-millis", max_off_millis, threshold_millis);
+                exit(1);
+            }
+        }
+    }
+
+    max_off_millis
+}
+
+
+fn configure_mode(matches: &ArgMatches) -> Mode {
+    let mode = matches.get_one::<String>("mode").map_or(Some(Mode::Busy), |mode| parse_mode(mode)).unwrap_or_else(|| {
+        error!("Unrecognized mode: {}", matches.get_one::<String>("mode").unwrap());
+        exit(1);
+    });
+    if let Err(err) = mode_allows_interrupt_mode(mode, configure_interrupt_mode(matches)) {
+        error!("{}", err);
+        exit(1);
+    }
+    mode
+}
+
+
+/// Only the busy loop re-enables local interrupts often enough (see --lapic-max-off-millis) to keep the host from locking up.
+fn mode_allows_interrupt_mode(mode: Mode, interrupt_mode: InterruptMode) -> Result<(), String> {
+    if mode != Mode::Busy && interrupt_mode == InterruptMode::Cli {
+        return Err(format!("Only busy mode can be used with local APIC interrupts disabled, not {:?} mode", mode));
+    }
+    Ok(())
+}
+
+
+fn parse_mode(mode: &str) -> Option<Mode> {
+    match mode {
+        "busy" => Some(Mode::Busy),
+        "wakeup" => Some(Mode::Wakeup),
+        "ping-pong" => Some(Mode::PingPong),
+        "memory" => Some(Mode::Memory),
+        "tick" => Some(Mode::Tick),
+        _ => None,
+    }
+}
+
+
+fn configure_workload(matches: &ArgMatches) -> Workload {
+    matches.get_one::<String>("workload").map_or(Some(Workload::Empty), |workload| parse_workload(workload)).unwrap_or_else(|| {
+        error!("Unrecognized workload: {}", matches.get_one::<String>("workload").unwrap());
+        exit(1);
+    })
+}
+
+
+fn parse_workload(workload: &str) -> Option<Workload> {
+    match workload {
+        "empty" => Some(Workload::Empty),
+        "int-chain" => Some(Workload::IntChain),
+        "pointer-chase" => Some(Workload::PointerChase),
+        "cacheline" => Some(Workload::CacheLine),
+        _ => None,
+    }
+}
+
+
+/// Overrides given as `<cpus>:<key>=<value>,...;<cpus>:...`, where cpus take the syntax of --cpus and the keys are:
+/// mode, rt, wakeup-interval, workload and working-set.
+fn configure_cpu_overrides(matches: &ArgMatches) -> Vec<CpuOverride> {
+    let cpu_config = match matches.get_one::<String>("cpu_config") {
+        Some(cpu_config) => cpu_config,
+        None => return Vec::default(),
     };
-    
-    if time_func != clock_realtime {
-        unsafe {
-            TIME_OFFSET = clock_realtime() - time_func();
+    parse_cpu_overrides(cpu_config, &configure_cpus(matches), configure_interrupt_mode(matches)).unwrap_or_else(|err| {
+        error!("{}", err);
+        exit(1);
+    })
+}
+
+
+fn parse_cpu_overrides(cpu_config: &str, cpus: &[u32], interrupt_mode: InterruptMode) -> Result<Vec<CpuOverride>, String> {
+    let mut cpu_overrides = Vec::default();
+    for entry in cpu_config.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let cpu_override = parse_cpu_override(entry).map_err(|err| format!("Invalid --cpu-config entry {}: {}", entry, err))?;
+        let (cpu_list, _) = entry.rsplit_once(':').unwrap();
+        let override_cpus = cpulist::parse_cpu_list(cpu_list).map_err(|err| format!("Invalid cpu list {} of --cpu-config: {}", cpu_list, err))?;
+        for cpu in override_cpus {
+            if !cpus.contains(&cpu) {
+                return Err(format!("--cpu-config configures cpu: {}, which is not sampled (see --cpus)", cpu));
+            }
+            if cpu_overrides.iter().any(|cpu_override: &CpuOverride| cpu_override.cpu == cpu) {
+                return Err(format!("--cpu-config configures cpu: {} more than once", cpu));
+            }
+            if let Some(mode) = cpu_override.mode {
+                mode_allows_interrupt_mode(mode, interrupt_mode).map_err(|err| format!("{} (--cpu-config of cpu: {})", err, cpu))?;
+            }
+            cpu_overrides.push(CpuOverride { cpu, ..cpu_override.clone() });
+        }
+    }
+
+    Ok(cpu_overrides)
+}
+
+
+/// Settings of one `<cpus>:<key>=<value>,...` entry of --cpu-config, leaving its cpu to the caller.
+fn parse_cpu_override(entry: &str) -> Result<CpuOverride, String> {
+    let (_, settings) = entry.rsplit_once(':').ok_or("expected <cpus>:<key>=<value>,...")?;
+    let mut cpu_override = CpuOverride::default();
+    for setting in settings.split(',').map(str::trim) {
+        let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected <key>=<value>, got: {}", setting))?;
+        match key.trim() {
+            "mode" => cpu_override.mode = Some(parse_mode(value).filter(|&mode| mode != Mode::PingPong).ok_or_else(|| format!("unrecognized mode: {} (expected busy, wakeup, memory or tick)", value))?),
+            "rt" => cpu_override.rt_priority = Some(value.parse::<i32>().ok().filter(|priority| (1..=99).contains(priority)).ok_or_else(|| format!("rt priority has to be within 1-99, got: {}", value))?),
+            "wakeup-interval" => cpu_override.wakeup_interval_micros = Some(value.parse::<i64>().ok().filter(|&micros| micros > 0).ok_or_else(|| format!("wakeup interval has to be a positive number of microseconds, got: {}", value))?),
+            "workload" => cpu_override.workload = Some(parse_workload(value).ok_or_else(|| format!("unrecognized workload: {}", value))?),
+            "working-set" => cpu_override.working_set_kib = Some(value.parse::<usize>().ok().filter(|&kib| kib > 0).ok_or_else(|| format!("working set has to be a positive number of KiB, got: {}", value))?),
+            key => return Err(format!("unrecognized setting: {} (expected mode, rt, wakeup-interval, workload or working-set)", key)),
+        }
+    }
+    Ok(cpu_override)
+}
+
+
+/// Outputs that are enabled just by giving the address (or file) to publish to.
+const ADDRESSED_OUTPUTS: [(&str, &str, Output); 10] = [
+    ("graphite", "graphite_address", Output::Graphite),
+    ("statsd", "statsd_address", Output::Statsd),
+    ("kafka", "kafka_rest_url", Output::Kafka),
+    ("kafka", "kafka_brokers", Output::Kafka),
+    ("mqtt", "mqtt_url", Output::Mqtt),
+    ("socket", "line_protocol_socket", Output::LineProtocolSocket),
+    ("postgres", "postgres_url", Output::Postgres),
+    ("clickhouse", "clickhouse_url", Output::Clickhouse),
+    ("sqlite", "sqlite_path", Output::Sqlite),
+    ("grpc", "grpc_url", Output::Grpc),
+];
+
+
+fn configure_outputs(matches: &ArgMatches) -> Vec<Output> {
+    let mut outputs: Vec<Output> = Vec::default();
+    let addressed: Vec<Output> = ADDRESSED_OUTPUTS.iter().filter(|(_, id, _)| matches.contains_id(id)).map(|&(_, _, output)| output).collect();
+    let output_list = match matches.value_source("output") {
+        // an address on its own replaces the default influx output
+        Some(ValueSource::DefaultValue) if !addressed.is_empty() => "",
+        _ => matches.get_one::<String>("output").expect("Unable to extract output list from arg: output").as_str(),
+    };
+    for output in output_list.split(',').map(str::trim).filter(|output| !output.is_empty()) {
+        let output = match output {
+            "influx" => configure_influx(matches),
+            "csv" => Output::Csv,
+            "jsonl" => Output::JsonLines,
+            output => match ADDRESSED_OUTPUTS.iter().find(|(name, _, _)| *name == output) {
+                Some(&(name, _, _)) if !ADDRESSED_OUTPUTS.iter().any(|&(other, id, _)| other == name && matches.contains_id(id)) => {
+                    let destinations: Vec<String> = ADDRESSED_OUTPUTS.iter()
+                        .filter(|&&(other, _, _)| other == name)
+                        .filter_map(|&(_, id, _)| output_args().into_iter().find(|arg| arg.get_id() == id).and_then(|arg| arg.get_long().map(|long| format!("--{}", long))))
+                        .collect();
+                    error!("No destination ({}) given for {} output", destinations.join(" or "), name);
+                    exit(1);
+                },
+                Some(&(_, _, output)) => output,
+                None => {
+                    error!("Unrecognized output: {}", output);
+                    exit(1);
+                }
+            },
+        };
+        if !outputs.contains(&output) {
+            outputs.push(output);
+        }
+    }
+
+    for output in addressed {
+        if !outputs.contains(&output) {
+            outputs.push(output);
+        }
+    }
+
+    if outputs.contains(&Output::Csv) && outputs.contains(&Output::JsonLines) {
+        error!("Csv and jsonl outputs cannot be combined, both would write to --output-path");
+        exit(1);
+    }
+
+    outputs
+}
+
+
+fn configure_influx(matches: &ArgMatches) -> Output {
+    if !matches.contains_id("influx_url") {
+        error!("Influx database url is required when publishing to influx");
+        exit(1);
+    }
+    if matches.contains_id("influx_bucket") {
+        if !matches.contains_id("influx_org") {
+            error!("Influx organization is required when publishing to an InfluxDB 2.x bucket");
+            exit(1);
+        }
+    } else if !matches.contains_id("influx_db") {
+        error!("Either Influx database name (1.x) or bucket (2.x) is required when publishing to influx");
+        exit(1);
+    }
+
+    Output::Influx
+}
+
+
+fn configure_raw_format(matches: &ArgMatches) -> RawFormat {
+    match matches.get_one::<String>("raw_format").map(|s| { s.as_str() }) {
+        Some("binary") | None => RawFormat::Binary,
+        Some("csv") => RawFormat::Csv,
+        Some("parquet") => RawFormat::Parquet,
+        Some(format) => {
+            error!("Unrecognized raw sample format: {}", format);
+            exit(1);
         }
     }
+}
+
 
-    time_func
+fn configure_kafka_format(matches: &ArgMatches) -> KafkaFormat {
+    match matches.get_one::<String>("kafka_format").map(|s| { s.as_str() }) {
+        Some("json") | None => KafkaFormat::Json,
+        Some("avro") => KafkaFormat::Avro,
+        Some(format) => {
+            error!("Unrecognized Kafka record format: {}", format);
+            exit(1);
+        }
+    }
+}
+
+
+fn configure_kafka_sasl_mechanism(matches: &ArgMatches) -> KafkaSaslMechanism {
+    match matches.get_one::<String>("kafka_sasl_mechanism").map(|s| { s.as_str() }) {
+        Some("plain") | None => KafkaSaslMechanism::Plain,
+        Some("scram-sha-256") => KafkaSaslMechanism::ScramSha256,
+        Some("scram-sha-512") => KafkaSaslMechanism::ScramSha512,
+        Some(mechanism) => {
+            error!("Unrecognized Kafka SASL mechanism: {}", mechanism);
+            exit(1);
+        }
+    }
+}
+
+
+fn configure_clickhouse_format(matches: &ArgMatches) -> ClickhouseFormat {
+    match matches.get_one::<String>("clickhouse_format").map(|s| { s.as_str() }) {
+        Some("rowbinary") | None => ClickhouseFormat::RowBinary,
+        Some("jsoneachrow") => ClickhouseFormat::JsonEachRow,
+        Some(format) => {
+            error!("Unrecognized ClickHouse insert format: {}", format);
+            exit(1);
+        }
+    }
+}
+
+
+fn configure_log_format(matches: &ArgMatches) -> LogFormat {
+    match matches.get_one::<String>("log_format").map(|s| { s.as_str() }) {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    }
+}
+
+
+fn configure_summary_format(matches: &ArgMatches) -> SummaryFormat {
+    match matches.get_one::<String>("summary_format").map(|s| { s.as_str() }) {
+        Some("table") | None => SummaryFormat::Table,
+        Some("json") => SummaryFormat::Json,
+        Some("none") => SummaryFormat::Off,
+        Some(format) => {
+            error!("Unrecognized summary format: {}", format);
+            exit(1);
+        }
+    }
 }
 
 
 fn match_arguments() -> ArgMatches {
-    let matches = Command::new("Platform jitter sampler")
+    let mut cli_args: Vec<OsString> = std::env::args_os().collect();
+    let names_subcommand = cli_args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        command().find_subcommand(arg).is_some() || matches!(arg, "help" | "-h" | "--help" | "-V" | "--version")
+    });
+    if !names_subcommand {
+        // plain option lists keep working as they did before subcommands were introduced
+        cli_args.insert(1, OsString::from("run"));
+    }
+
+    let matches = command().get_matches_from(&cli_args);
+    let (subcommand, subcommand_matches) = matches.subcommand().expect("Subcommand is required");
+    // before the config file is read, so that problems with it get logged; the format can not be set from the file
+    logging::init(configure_log_format(subcommand_matches));
+    match subcommand_matches.get_one::<String>("config") {
+        Some(path) => {
+            // file values go first so that the ones given on the command line override them
+            let mut args = cli_args[..2].to_vec();
+            args.extend(config_file_args(&command(), subcommand, path).into_iter().map(OsString::from));
+            args.extend(cli_args[2..].iter().cloned());
+            command().get_matches_from(args)
+        },
+        None => matches,
+    }
+}
+
+
+/// Turns the `option = value` pairs of a TOML config file into command line arguments of the given subcommand.
+/// Keys are the long option names, arrays are joined with commas and flags are set with `true`. Options of other
+/// subcommands are ignored, so that one file can serve them all. Options also set through their `JITTER_*`
+/// environment variable are skipped, giving the precedence: command line, environment, file.
+fn config_file_args(command: &Command, subcommand: &str, path: &str) -> Vec<String> {
+    let table = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|content| content.parse::<toml::Table>().map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            error!("Unable to read config file {}: {}", path, err);
+            exit(1);
+        });
+
+    let find_arg = |command: &Command, key: &str| command.get_arguments()
+        .find(|arg| arg.get_long() == Some(key) || arg.get_id() == key || arg.get_all_aliases().is_some_and(|aliases| aliases.contains(&key)))
+        .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "log_format") && arg.get_long().is_some())
+        .cloned();
+
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let arg = match find_arg(command.find_subcommand(subcommand).unwrap(), &key) {
+            Some(arg) => arg,
+            None if command.get_subcommands().any(|other| find_arg(other, &key).is_some()) => continue,
+            None => {
+                error!("Unrecognized option in config file {}: {}", path, key);
+                exit(1);
+            }
+        };
+        let long = arg.get_long().unwrap();
+        if arg.get_env().is_some_and(|env| std::env::var_os(env).is_some()) {
+            // environment overrides the file
+            continue;
+        }
+
+        match (arg.get_action().takes_values(), config_value(&value)) {
+            (true, Some(value)) => args.push(format!("--{}={}", long, value)),
+            (false, Some(flag)) if flag == "true" => args.push(format!("--{}", long)),
+            (false, Some(flag)) if flag == "false" => {},
+            _ => {
+                error!("Invalid value for {} in config file {}: {}", key, path, value);
+                exit(1);
+            }
+        }
+    }
+
+    args
+}
+
+
+fn config_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(values) => values.iter().map(config_value).collect::<Option<Vec<_>>>().map(|values| values.join(",")),
+        _ => None,
+    }
+}
+
+
+fn command() -> Command {
+    Command::new("Platform jitter sampler")
         .term_width(250)
         .version("1.0.1")
         .author("Wojciech Kudla")
-        .about("Runs for <duration> seconds on select <cpus> and for each <report-interval> stores worst instruction execution latency along with its associated timestamp. At the end of program execution it publishes all data points to InfluxDB")
-        .arg(
-            Arg::new("duration_seconds")
-                .short('d')
-                .long("duration")
-                .value_name("seconds")
-                .help("How long to keep running for")
-                .default_value("10")
-                .value_parser(clap::value_parser!(i64))
-        )
-        .arg(
-            Arg::new("report_interval_millis")
-                .short('r')
-                .long("report-interval")
-                .value_name("milliseconds")
-                .help("Sampling interval")
-                .default_value("100")
-                .value_parser(clap::value_parser!(i64))
-        )
-        .arg(
-            Arg::new("mlock")
-                .short('m')
-                .long("mlock")
-                .help("Mlock jitter data pages to RAM")
-                .required(false)
-                .action(ArgAction::SetTrue)
-                .default_value("false")
-        )
-        .arg(
-            Arg::new("lapic")
-                .short('l')
-                .long("lapic")
-                .help("Disable local APIC interrupts (requires superuser privileges).")
-                .required(false)
-                .action(ArgAction::SetTrue)
-                .default_value("false")
-        )
-        .arg(
-            Arg::new("cpus")
-                .short('c')
-                .long("cpus")
-                .value_name("target cpus")
-                .help("CPU to affinitise the program thread(s) to; can be passed as list of ranges, eg: '1,4-6,8-12,15'")
-                .default_value("0")
-        )
-        .arg(
-            Arg::new("tsc_frequency")
-                .short('f')
-                .long("tsc-frequency")
-                .value_name("GHz")
-                .help("Frequency of TSC as a decimal number")
-                .value_parser(clap::value_parser!(f64))
-        )
-        .arg(
-            Arg::new("time_source")
-                .short('t')
-                .long("time-source")
-                .help("Implementation to use for measuring elapsed time: clock_realtime | clock_monotonic | rdtsc")
-                .default_value("clock_realtime")
-        )
+        .about("Measures platform induced latencies (jitter) experienced by code running on select cpus")
+        .subcommand_required(true)
+        .subcommand(subcommand("run")
+            .about("Runs for <duration> seconds on select <cpus> and for each <report-interval> stores worst instruction execution latency along with its associated timestamp. At the end of program execution it publishes all data points to InfluxDB")
+            .arg(cpus_arg())
+            .arg(report_interval_arg())
+            .args(sampling_args())
+            .args(clock_args())
+            .args(output_args()))
+        .subcommand(subcommand("calibrate")
+            .about("Detects the cycle counter frequency and measures the cost and realtime offset of reading the clock on select <cpus>")
+            .arg(cpus_arg())
+            .args(clock_args()))
+        .subcommand(subcommand("check")
+            .about("Audits system configuration for likely sources of jitter on select <cpus>")
+            .arg(cpus_arg()))
+        .subcommand(subcommand("compare")
+            .about("Compares the per-cpu percentiles of a candidate run against a baseline run, eg: before and after a kernel or BIOS change, and fails (exit status 3) if any of p50, p99 or p99.99 regressed by more than <max-regression>")
+            .arg(
+                Arg::new("baseline")
+                    .value_name("baseline")
+                    .help("Baseline run: a summary saved from --summary-format json, a csv or jsonl results file, sqlite:<path>#<run id> or influx:<run id>")
+                    .required(true)
+            )
+            .arg(
+                Arg::new("candidate")
+                    .value_name("candidate")
+                    .help("Candidate run, in any of the forms of the baseline")
+                    .required(true)
+            )
+            .arg(
+                Arg::new("max_regression_percent")
+                    .long("max-regression")
+                    .value_name("percent")
+                    .help("How much worse than the baseline any gated percentile of the candidate may get")
+                    .default_value("10")
+                    .value_parser(clap::value_parser!(f64))
+            )
+            .args(output_args().into_iter().filter(|arg| matches!(arg.get_id().as_str(), "influx_url" | "influx_db" | "influx_bucket" | "influx_token" | "influx_user" | "influx_password" | "influx_ca_cert" | "insecure_skip_verify" | "influx_timeout_seconds" | "influx_measurement"))))
+        .subcommand(subcommand("export")
+            .about("Replays binary or csv raw sample files recorded with --raw-output (eg: on an isolated machine) into the configured output, reporting the worst latency of every <report-interval>, or resends line protocol spilled by a failed Influx write")
+            .arg(
+                Arg::new("raw_files")
+                    .value_name("raw file")
+                    .help("Raw sample files, one per cpu (eg: samples.cpu2 or samples.cpu2.csv)")
+                    .required(true)
+                    .num_args(1..)
+            )
+            .arg(
+                Arg::new("line_protocol")
+                    .long("line-protocol")
+                    .help("The files are Influx line protocol spilled with --influx-spill-path, to be resent to Influx as they are")
+                    .required(false)
+                    .action(ArgAction::SetTrue)
+                    .default_value("false")
+            )
+            .arg(report_interval_arg())
+            .args(output_args()))
+        .mut_subcommands(|subcommand| subcommand.mut_args(|arg| {
+            let env = format!("JITTER_{}", arg.get_long().unwrap_or(arg.get_id().as_str()).replace('-', "_").to_uppercase());
+            let secret = matches!(arg.get_id().as_str(), "influx_token" | "influx_password" | "kafka_password" | "mqtt_password" | "clickhouse_password");
+            arg.env(env).hide_env_values(secret)
+        }))
+}
+
+
+fn subcommand(name: &'static str) -> Command {
+    Command::new(name)
+        .args_override_self(true)
         .arg(
-            Arg::new("influx_url")
-                .short('i')
-                .long("influx-url")
-                .value_name("URL")
-                .help("Influx database url (eg: http://foo.bar.com:8086)")
-                .required(true),
+            Arg::new("config")
+                .long("config")
+                .value_name("file")
+                .help("TOML file with default values for any of the long options, eg: cpus = \"1-3\"; options given on the command line take precedence")
         )
         .arg(
-            Arg::new("influx_db")
-                .short('b')
-                .long("influx-db")
-                .help("Influx database name")
-                .required(true),
+            Arg::new("log_format")
+                .long("log-format")
+                .value_name("format")
+                .help("Log as human readable text or as one json object per line, with cpu, phase and error fields, for log pipelines")
+                .value_parser(["text", "json"])
+                .default_value("text")
         )
-        .get_matches();
-    
-    matches
-}
-
-
-fn parse_cpu_list(cpu_list_str: &str) -> Vec<u32> {
-    let mut result: Vec<u32> = Vec::default();
-    let elements = cpu_list_str.trim().split(',');
-    for element in elements {
-        if element.contains('-') {
-            let range = Vec::from_iter(element.split('-'));
-            let begin = range[0].parse::<u32>().expect(format!("Unable to parse cpu: {}", range[0]).as_str());
-            let end = range[1].parse::<u32>().expect(format!("Unable to parse cpu: {}", range[0]).as_str());
-            for cpu in begin..end + 1 {
-                result.push(cpu);
-            }
-        } else {
-            result.push(element.parse::<u32>().expect(format!("Unable to parse cpu: {}", element).as_str()));
+}
+
+
+fn cpus_arg() -> Arg {
+    Arg::new("cpus")
+        .short('c')
+        .long("cpus")
+        .value_name("target cpus")
+        .help("CPU to affinitise the program thread(s) to; can be passed as list of ranges, eg: '1,4-6,8-12,15', with an optional stride ('0-31:2'), as 'all' or 'isolated' (isolcpus) and with exclusions ('all,!0'). Offline cpus are skipped")
+        .default_value("0")
+}
+
+
+fn report_interval_arg() -> Arg {
+    Arg::new("report_interval_micros")
+        .short('r')
+        .long("report-interval")
+        .value_name("interval")
+        .help("Sampling interval, in milliseconds or with a unit: us | ms | s (eg: 500us)")
+        .default_value("100")
+        .value_parser(parse_report_interval)
+}
+
+
+fn sampling_args() -> Vec<Arg> {
+    vec![
+        Arg::new("duration_seconds")
+            .short('d')
+            .long("duration")
+            .value_name("duration")
+            .help("How long to keep running for, in seconds or with a unit: s | m | h | d (eg: 15m); 0 or 'forever' keeps sampling until SIGINT/SIGTERM, publishing every --flush-intervals")
+            .default_value("10")
+            .value_parser(parse_duration),
+        Arg::new("mode")
+            .long("mode")
+            .help("What to measure: busy (latency between consecutive reads of the clock in a busy loop) | wakeup (lateness of absolute timer wakeups) | ping-pong (round trips of a cache line between the two cpus given with --cpus, published as core_to_core_latency) | memory (average latency of random accesses to the --working-set, to catch DRAM refresh, patrol scrubbing and throttling) | tick (busy mode publishing timer_interrupts_per_sec, to verify that nohz_full stopped the scheduler tick)")
+            .default_value("busy"),
+        Arg::new("wakeup_interval_micros")
+            .long("wakeup-interval")
+            .value_name("microseconds")
+            .help("How often to wake up in wakeup mode")
+            .default_value("1000")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("workload")
+            .short('w')
+            .long("workload")
+            .help("Work executed between consecutive clock reads in busy mode: empty | int-chain (dependent integer arithmetic) | pointer-chase (random walk over the working set) | cacheline (flush and reload a single cache line)")
+            .default_value("empty"),
+        Arg::new("working_set_kib")
+            .long("working-set")
+            .value_name("KiB")
+            .help("Size of the memory walked by the pointer-chase workload and in memory mode")
+            .default_value("32768")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("cpu_config")
+            .long("cpu-config")
+            .value_name("cpus:key=value,...;...")
+            .help("Per cpu settings overriding --mode, --rt-priority, --wakeup-interval, --workload and --working-set, eg: '4:mode=busy,rt=99;5-7:mode=wakeup' (keys: mode, rt, wakeup-interval, workload, working-set)"),
+        Arg::new("rt_priority")
+            .long("rt-priority")
+            .value_name("1-99")
+            .help("Run sampler threads as SCHED_FIFO with the given priority (requires CAP_SYS_NICE)")
+            .value_parser(clap::value_parser!(i32).range(1..=99)),
+        Arg::new("mlock")
+            .short('m')
+            .long("mlock")
+            .alias("mlock-buffers")
+            .help("Mlock the sample buffers and the code pages to RAM")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("mlock_all")
+            .long("mlock-all")
+            .help("Mlock the whole address space to RAM, including the memory allocated later on for publishing (may exhaust RLIMIT_MEMLOCK)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false")
+            .conflicts_with("mlock"),
+        Arg::new("numa_strict")
+            .long("numa-strict")
+            .help("Fail instead of warning when sample buffers cannot be allocated on the numa node of the sampled cpu")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("require_idle_siblings")
+            .long("require-idle-siblings")
+            .help("Fail instead of warning when a hyperthread sibling of a sampled cpu is online and not isolated")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("offline_siblings")
+            .long("offline-siblings")
+            .help("Take the hyperthread siblings of the sampled cpus offline for the run and bring them back online afterwards (requires root)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("performance_governor")
+            .long("performance-governor")
+            .help("Switch the sampled cpus to the performance cpufreq governor for the run and restore their governors afterwards (requires root)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("disable_turbo")
+            .long("disable-turbo")
+            .help("Disable turbo/boost (intel_pstate no_turbo or cpufreq boost) for the run and re-enable it afterwards (requires root)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("move_irqs")
+            .long("move-irqs")
+            .help("Route the interrupts that may fire on the sampled cpus to the housekeeping cpus for the run and restore their affinity afterwards (requires root)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("run_as")
+            .long("run-as")
+            .value_name("user")
+            .help("Switch to the given user once the privileged setup (rt priority, mlock, iopl, msr, ...) is done, before sampling and publishing start. Settings that need root to be restored after the run cannot be combined with it")
+            .conflicts_with_all(["offline_siblings", "performance_governor", "disable_turbo", "move_irqs", "trace_on_outlier"]),
+        Arg::new("huge_pages")
+            .long("huge-pages")
+            .help("Back sample buffers with transparent huge pages to keep dTLB misses out of the measurement, falling back to regular pages when unavailable. With --mlock-all, buffers are faulted in before the advice and only get collapsed into huge pages by khugepaged")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("lapic")
+            .short('l')
+            .long("lapic")
+            .help("Disable local interrupts (cli) on the sampling cpus for the run, bar brief windows every --lapic-max-off-millis (requires superuser privileges, x86 only, busy mode only)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("lapic_max_off_millis")
+            .long("lapic-max-off-millis")
+            .value_name("millis")
+            .help("With --lapic, briefly re-enable interrupts at least this often so that the host does not lock up; must stay well below the hard-lockup watchdog threshold")
+            .default_value("1000")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("subtract_overhead")
+            .long("subtract-overhead")
+            .help("Calibrate the cost of reading the clock on every target cpu before the run, subtract it from measured latencies and publish it as clock_overhead")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("histogram")
+            .long("histogram")
+            .help("Record every latency into a histogram and report percentiles alongside the max for each interval")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("percentiles")
+            .long("percentiles")
+            .value_name("list")
+            .help("Percentiles to report for each interval when histograms are enabled, eg: '50,99,99.9,max'; implies --histogram")
+            .default_value("50,90,99,99.99"),
+        Arg::new("histogram_digits")
+            .long("histogram-digits")
+            .value_name("digits")
+            .help("Significant decimal digits histograms keep of every latency, each one more multiplies their memory footprint by ten")
+            .default_value("3")
+            .value_parser(clap::value_parser!(u8).range(1..=5)),
+        Arg::new("histogram_max_nanos")
+            .long("histogram-max-nanos")
+            .value_name("nanos")
+            .help("Largest latency histograms track, anything above is recorded as this")
+            .default_value("60000000000")
+            .value_parser(clap::value_parser!(i64).range(2..)),
+        Arg::new("histogram_buckets")
+            .long("histogram-buckets")
+            .help("Publish the number of latencies of each interval falling into log-linear buckets (1, 2, .. 9, 10, 20, .. 90, 100, .. nanos) as jitter_histogram events with the bucket upper bound as latency and a count field, to build heatmaps from; implies --histogram")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("interrupts")
+            .long("interrupts")
+            .help("Publish how many times every interrupt listed in /proc/interrupts fired on the sampled cpu during each interval, as irq_<name> fields")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("iterations")
+            .long("iterations")
+            .help("Publish how many latencies got measured during each interval as iterations, and their rate as iterations_per_sec; a drop in the rate gives away throttling or stolen time even when the max looks fine")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("context_switches")
+            .long("context-switches")
+            .help("Publish how many times the sampler thread got preempted during each interval as involuntary_context_switches")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("cpu_frequency")
+            .long("cpu-frequency")
+            .help("Publish the sampled cpu's frequency with every interval: cpu_frequency_khz from cpufreq and effective_frequency_khz from APERF/MPERF (x86, requires the msr driver and root)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("cstates")
+            .long("cstates")
+            .help("Publish how often and for how long the sampled cpu entered each idle state during every interval, as cstate_<name>_usage and cstate_<name>_time_us fields")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("steal_time")
+            .long("steal-time")
+            .help("Publish how long the hypervisor kept the sampled cpu from running during every interval as steal_time_us, taken from /proc/stat, to attribute jitter in virtual machines to hypervisor preemption")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("cgroup_throttling")
+            .long("cgroup-throttling")
+            .help("Publish how many times and for how long the cgroup of the process got throttled by its CFS quota (cpu.max) during every interval, as cgroup_nr_throttled and cgroup_throttled_us")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("perf_counters")
+            .long("perf-counters")
+            .value_name("list")
+            .help("Publish hardware counter deltas of the sampler thread with every interval as perf_<counter> fields: any of cycles, instructions, cache-misses, llc-misses, eg: 'cycles,llc-misses'"),
+        Arg::new("attribute_stalls")
+            .long("attribute-stalls")
+            .help("Count hardirqs, softirqs and scheduler switches hitting the sampled cpu during each interval as stall_<event> fields, and log which of them the worst intervals had in excess of the run median. This counts per interval, it does not attribute causes: an event counted in an interval need not have overlapped its worst latency (requires tracefs and perf_event_paranoid <= 0)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("raw_output")
+            .long("raw-output")
+            .value_name("path")
+            .help("Record every single loop delta into a compact binary file per cpu named <path>.cpu<N>"),
+        Arg::new("raw_format")
+            .long("raw-format")
+            .help("Format of the raw sample files: binary (replayable with export) | csv (<path>.cpu<N>.csv with timestamp, cpu and latency columns, also replayable with export) | parquet (<path>.cpu<N>.parquet with cpu, ts and latency columns, for pandas/polars)")
+            .default_value("binary")
+            .requires("raw_output"),
+        Arg::new("outlier_threshold_nanos")
+            .long("outlier-threshold-ns")
+            .value_name("nanoseconds")
+            .help("Record every single latency above this threshold with its exact timestamp and publish it as a jitter_outlier measurement")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("discontinuity_threshold_micros")
+            .long("discontinuity-threshold")
+            .value_name("duration")
+            .help("Deltas longer than this, or going backwards, are taken for clock steps (NTP, suspend/resume, ...) and published as jitter_discontinuity events instead of latencies (eg: 10s, 500ms)")
+            .default_value("10s")
+            .value_parser(|value: &str| parse_time_span(value, MICROS_IN_SEC).filter(|&micros| micros > 0).ok_or_else(|| format!("expected a positive duration such as 10s or 500ms, got: {}", value))),
+        Arg::new("trace_on_outlier")
+            .long("trace-on-outlier")
+            .help("Trace sched_switch, irq and timer events with ftrace and save the events of the affected cpu next to the results whenever a latency exceeds --outlier-threshold-ns (requires root and tracefs)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false")
+            .requires("outlier_threshold_nanos"),
+        Arg::new("top_latencies")
+            .long("top-n")
+            .value_name("count")
+            .help("Keep the <count> worst latencies of every interval with their timestamps and publish them as a jitter_top measurement")
+            .default_value("0")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("flush_intervals")
+            .long("flush-intervals")
+            .value_name("count")
+            .help("Publish results in the background every <count> reporting intervals while running instead of only at the end (0 disables)")
+            .default_value("0")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("prometheus_listen")
+            .long("prometheus-listen")
+            .value_name("address:port")
+            .help("Serve live per-cpu jitter metrics for Prometheus scraping on this address (eg: 0.0.0.0:9300)"),
+        Arg::new("tui")
+            .long("tui")
+            .help("Show a live per-cpu dashboard of interval maxima, percentiles and a sparkline of recent intervals in the terminal")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false")
+            .conflicts_with("daemon"),
+        Arg::new("summary_format")
+            .long("summary-format")
+            .value_name("format")
+            .help("How to print the per-cpu summary of the run at the end: table | json | none. Goes to stderr when jsonl results are written to stdout")
+            .default_value("table"),
+        Arg::new("fail_if_max_above_nanos")
+            .long("fail-if-max-above")
+            .value_name("nanoseconds")
+            .help("Exit with status 3 if the worst latency of the run on any cpu exceeds this budget")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("fail_if_p99_above_nanos")
+            .long("fail-if-p99-above")
+            .value_name("nanoseconds")
+            .help("Exit with status 3 if the 99th percentile of all latencies of the run on any cpu exceeds this budget; implies --histogram")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("daemon")
+            .long("daemon")
+            .help("Detach and keep sampling in the background until SIGTERM (unless --duration is given), publishing every --flush-intervals; SIGHUP reopens output files. Logs still go to stderr")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("pid_file")
+            .long("pid-file")
+            .value_name("path")
+            .help("Where to write the pid of the daemon")
+            .default_value("/run/jitter.pid"),
+        Arg::new("start_at")
+            .long("start-at")
+            .value_name("timestamp")
+            .help("Wall clock instant every sampler thread starts measuring at, as RFC 3339 (eg: 2024-05-01T12:00:00Z), so that runs started on several hosts line up in time; their clocks should be synchronized with NTP or, better, PTP")
+            .value_parser(parse_rfc3339),
+    ]
+}
+
+
+fn clock_args() -> Vec<Arg> {
+    vec![
+        Arg::new("tsc_frequency")
+            .short('f')
+            .long("tsc-frequency")
+            .value_name("GHz")
+            .help("Frequency of TSC (or another cycle counter) as a decimal number; detected automatically when omitted")
+            .value_parser(clap::value_parser!(f64)),
+        Arg::new("time_source")
+            .short('t')
+            .long("time-source")
+            .help("Implementation to use for measuring elapsed time: clock_realtime | clock_monotonic | clock_monotonic_raw (not slewed by NTP) | rdtsc | rdtsc_lfence (serialized with lfence) | rdtscp (x86) | cntvct (aarch64) | rdtime | rdcycle (riscv64) | instant (portable fallback, used when the requested counter is unavailable)")
+            .default_value("clock_realtime"),
+        Arg::new("allow_unstable_tsc")
+            .long("allow-unstable-tsc")
+            .help("Use rdtsc even if the TSC is not invariant on this machine")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+    ]
+}
+
+
+fn output_args() -> Vec<Arg> {
+    vec![
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .help("Where to publish results, as a comma separated list of: influx | csv | jsonl | graphite | statsd | kafka | mqtt | socket | postgres | clickhouse | sqlite | grpc (eg: influx,csv to keep a local copy)")
+            .default_value("influx"),
+        Arg::new("output_path")
+            .short('p')
+            .long("output-path")
+            .value_name("file")
+            .help("File to write results to when using a file based output (jsonl writes to stdout if omitted)"),
+        Arg::new("graphite_address")
+            .long("graphite")
+            .value_name("host:port")
+            .help("Carbon plaintext listener (eg: graphite.foo.com:2003) to publish jitter.<host>.cpu<N>.max metrics to; implies the graphite output"),
+        Arg::new("statsd_address")
+            .long("statsd")
+            .value_name("host:port")
+            .help("StatsD/DogStatsD agent (eg: localhost:8125) to send interval maxima, percentiles and probe fields to over UDP, tagged with host, cpu and --tag; implies the statsd output"),
+        Arg::new("kafka_rest_url")
+            .long("kafka")
+            .value_name("URL")
+            .help("Kafka REST proxy (eg: https://kafka-rest.foo.com:8082) to produce every interval to as a record; implies the kafka output"),
+        Arg::new("kafka_brokers")
+            .long("kafka-brokers")
+            .value_name("host:port,...")
+            .help("Kafka brokers (eg: kafka1.foo.com:9092,kafka2.foo.com:9092) to produce every interval to as a record directly, without a REST proxy; implies the kafka output")
+            .conflicts_with("kafka_rest_url"),
+        Arg::new("kafka_topic")
+            .long("kafka-topic")
+            .help("Kafka topic to produce records to")
+            .default_value("jitter"),
+        Arg::new("kafka_format")
+            .long("kafka-format")
+            .help("Kafka record format: json | avro (registered by the REST proxy with its schema registry, not available with --kafka-brokers)")
+            .default_value("json"),
+        Arg::new("kafka_user")
+            .long("kafka-user")
+            .help("User name for HTTP basic authentication with the Kafka REST proxy, or SASL authentication with the brokers"),
+        Arg::new("kafka_password")
+            .long("kafka-password")
+            .help("Password for HTTP basic authentication with the Kafka REST proxy, or SASL authentication with the brokers")
+            .requires("kafka_user"),
+        Arg::new("kafka_sasl_mechanism")
+            .long("kafka-sasl-mechanism")
+            .help("SASL mechanism authenticating --kafka-user with the brokers: plain | scram-sha-256 | scram-sha-512")
+            .default_value("plain")
+            .requires("kafka_brokers"),
+        Arg::new("kafka_tls")
+            .long("kafka-tls")
+            .action(ArgAction::SetTrue)
+            .default_value("false")
+            .help("Connect to the brokers over TLS, verifying their certificates against the system trust store (or --kafka-ca-cert)")
+            .requires("kafka_brokers"),
+        Arg::new("kafka_ca_cert")
+            .long("kafka-ca-cert")
+            .value_name("path")
+            .help("PEM file with the CA certificate(s) to verify the brokers against; implies --kafka-tls")
+            .requires("kafka_brokers"),
+        Arg::new("line_protocol_socket")
+            .long("line-protocol-socket")
+            .value_name("unix://path | tcp://host:port")
+            .help("Stream Influx line protocol over a socket (eg: unix:///run/telegraf.sock for a telegraf socket_listener) instead of posting it over HTTP; implies the socket output"),
+        Arg::new("postgres_url")
+            .long("postgres")
+            .value_name("URL")
+            .help("Postgres/TimescaleDB connection url (eg: postgresql://jitter@db.foo.com/perf) to copy samples into with psql; implies the postgres output"),
+        Arg::new("postgres_table")
+            .long("postgres-table")
+            .value_name("[schema.]table")
+            .help("Table with columns time timestamptz, host text, cpu integer, measurement text, latency bigint, fields jsonb")
+            .default_value("jitter")
+            .value_parser(parse_postgres_table),
+        Arg::new("postgres_create_table")
+            .long("postgres-create-table")
+            .help("Create the table if it does not exist, as a hypertable when the timescaledb extension is installed")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("clickhouse_url")
+            .long("clickhouse")
+            .value_name("URL")
+            .help("ClickHouse HTTP interface (eg: http://clickhouse.foo.com:8123) to bulk-insert samples into; implies the clickhouse output"),
+        Arg::new("clickhouse_table")
+            .long("clickhouse-table")
+            .value_name("[database.]table")
+            .help("Table with columns ts Int64, host String, cpu UInt32, measurement String, latency Int64, fields Map(String, Int64)")
+            .default_value("jitter"),
+        Arg::new("clickhouse_format")
+            .long("clickhouse-format")
+            .help("Format to insert rows in: rowbinary | jsoneachrow")
+            .default_value("rowbinary"),
+        Arg::new("clickhouse_create_table")
+            .long("clickhouse-create-table")
+            .help("Create the table (MergeTree ordered by host, cpu and ts) if it does not exist")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("clickhouse_user")
+            .long("clickhouse-user")
+            .help("User to insert into ClickHouse as"),
+        Arg::new("clickhouse_password")
+            .long("clickhouse-password")
+            .help("Password of the ClickHouse user")
+            .requires("clickhouse_user"),
+        Arg::new("sqlite_path")
+            .long("sqlite")
+            .value_name("file")
+            .help("SQLite database to record the run (args, kernel version, counter frequency) and its samples into, created if missing; implies the sqlite output"),
+        Arg::new("grpc_url")
+            .long("grpc-url")
+            .value_name("URL")
+            .help("gRPC collector (eg: http://collector.foo.com:50051) to stream every sample to through the JitterCollector service of proto/jitter.proto; implies the grpc output"),
+        Arg::new("mqtt_url")
+            .long("mqtt-url")
+            .value_name("URL")
+            .help("MQTT broker (eg: mqtt://broker.foo.com:1883) to publish every interval to as a json message; implies the mqtt output"),
+        Arg::new("mqtt_topic")
+            .long("mqtt-topic")
+            .help("Topic prefix; messages go to <topic>/<host>/cpu<N>")
+            .default_value("jitter"),
+        Arg::new("mqtt_qos")
+            .long("mqtt-qos")
+            .help("MQTT quality of service: 0 (at most once) | 1 (at least once) | 2 (exactly once)")
+            .default_value("0")
+            .value_parser(clap::value_parser!(u8).range(0..=2)),
+        Arg::new("mqtt_user")
+            .long("mqtt-user")
+            .help("User name to connect to the MQTT broker with"),
+        Arg::new("mqtt_password")
+            .long("mqtt-password")
+            .help("Password to connect to the MQTT broker with")
+            .requires("mqtt_user"),
+        Arg::new("influx_url")
+            .short('i')
+            .long("influx-url")
+            .value_name("URL")
+            .help("Influx database url (eg: http://foo.bar.com:8086)")
+            .required(false),
+        Arg::new("influx_db")
+            .short('b')
+            .long("influx-db")
+            .help("Influx database name (InfluxDB 1.x)")
+            .required(false),
+        Arg::new("influx_org")
+            .long("influx-org")
+            .help("Influx organization (InfluxDB 2.x)"),
+        Arg::new("influx_bucket")
+            .long("influx-bucket")
+            .help("Influx bucket; when given results are written through the InfluxDB 2.x api instead of the 1.x /write endpoint"),
+        Arg::new("influx_token")
+            .long("influx-token")
+            .help("Influx api token (InfluxDB 2.x)"),
+        Arg::new("influx_user")
+            .long("influx-user")
+            .help("User name for Influx HTTP basic authentication"),
+        Arg::new("influx_password")
+            .long("influx-password")
+            .help("Password for Influx HTTP basic authentication")
+            .requires("influx_user"),
+        Arg::new("influx_ca_cert")
+            .long("influx-ca-cert")
+            .value_name("file")
+            .help("CA certificate bundle (PEM) used to verify an https Influx url"),
+        Arg::new("insecure_skip_verify")
+            .long("insecure-skip-verify")
+            .help("Do not verify Influx TLS certificate and host name")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("influx_timeout_seconds")
+            .long("influx-timeout")
+            .value_name("seconds")
+            .help("How long a single Influx write may take, including connecting, before it is abandoned and retried")
+            .default_value("30")
+            .value_parser(clap::value_parser!(u64).range(1..)),
+        Arg::new("influx_no_gzip")
+            .long("influx-no-gzip")
+            .help("Post Influx batches uncompressed instead of gzipped (eg: for proxies that reject Content-Encoding: gzip)")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false"),
+        Arg::new("influx_batch_max_bytes")
+            .long("batch-max-bytes")
+            .value_name("size")
+            .help("Post line protocol to Influx in batches of about this many (uncompressed) bytes, eg: 512KiB")
+            .default_value("768KiB")
+            .value_parser(parse_size),
+        Arg::new("influx_batch_max_points")
+            .long("batch-max-points")
+            .value_name("count")
+            .help("Also cap every Influx batch at this many points")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+        Arg::new("influx_retries")
+            .long("influx-retries")
+            .value_name("count")
+            .help("How many times to retry a failed Influx write")
+            .default_value("3")
+            .value_parser(clap::value_parser!(u32)),
+        Arg::new("influx_retry_backoff_millis")
+            .long("influx-retry-backoff")
+            .value_name("milliseconds")
+            .help("Delay before the first retry of a failed Influx write; doubles (plus random jitter) with every attempt")
+            .default_value("500")
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("influx_spill_path")
+            .long("influx-spill-path")
+            .value_name("file")
+            .help("File to append line protocol batches to when they could not be delivered to Influx; resend it later with: export --line-protocol <file> [default: jitter.spill.<unix time>]"),
+        Arg::new("influx_measurement")
+            .long("measurement")
+            .value_name("name")
+            .help("Influx measurement the jitter samples are written to")
+            .default_value("jitter"),
+        Arg::new("influx_tags")
+            .long("tag")
+            .value_name("key=value")
+            .help("Extra tag added to every published point (eg: run=baseline); may be repeated")
+            .action(ArgAction::Append)
+            .value_parser(parse_tag),
+        Arg::new("hostname")
+            .long("hostname")
+            .value_name("name")
+            .help("Host name to publish results under instead of the kernel hostname"),
+        Arg::new("fqdn")
+            .long("fqdn")
+            .help("Publish results under the fully qualified domain name of this host")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .default_value("false")
+            .conflicts_with("hostname"),
+        Arg::new("run_id")
+            .long("run-id")
+            .value_name("id")
+            .help("Id tagging every sample of this run and its metadata record [default: a random UUID]"),
+    ]
+}
+
+
+fn configure_cpus(matches: &ArgMatches) -> Vec<u32> {
+    let cpu_list = matches.get_one::<String>("cpus").expect("Unable to extract cpu list from arg: cpus");
+    cpulist::parse_cpu_list(cpu_list).unwrap_or_else(|err| {
+        error!("Invalid cpu list {}: {}", cpu_list, err);
+        exit(1);
+    })
+}
+
+
+fn parse_percentile_list(percentile_list_str: &str) -> Vec<f64> {
+    let mut result: Vec<f64> = Vec::default();
+    for element in percentile_list_str.trim().split(',').map(str::trim) {
+        let percentile = match element {
+            "max" => 100.0,
+            _ => element.parse::<f64>().unwrap_or_else(|_| {
+                error!("Unable to parse percentile: {}", element);
+                exit(1);
+            }),
+        };
+        if percentile <= 0.0 || percentile > 100.0 {
+            error!("Percentile out of range (0, 100]: {}", element);
+            exit(1);
         }
+        result.push(percentile);
     }
-    
+
     result
 }
+
+
+fn parse_perf_counter_list(counter_list_str: &str) -> Vec<PerfCounter> {
+    counter_list_str.trim().split(',').map(str::trim)
+        .map(|counter| match counter {
+            "cycles" => PerfCounter::Cycles,
+            "instructions" => PerfCounter::Instructions,
+            "cache-misses" => PerfCounter::CacheMisses,
+            "llc-misses" => PerfCounter::LlcMisses,
+            _ => {
+                error!("Unrecognized perf counter: {}", counter);
+                exit(1);
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_matches(args: &[&str]) -> ArgMatches {
+        let matches = command().try_get_matches_from(["jitter", "run", "-c", "0", "-d", "1"].iter().chain(args)).unwrap();
+        matches.subcommand_matches("run").unwrap().clone()
+    }
+
+    #[test]
+    fn interrupts_stay_enabled_without_lapic() {
+        assert_eq!(configure_interrupt_mode(&run_matches(&[])), InterruptMode::Normal);
+        assert_eq!(configure_interrupt_mode(&run_matches(&["--lapic"])), InterruptMode::Cli);
+    }
+
+    #[test]
+    fn only_busy_mode_runs_with_interrupts_disabled() {
+        assert!(mode_allows_interrupt_mode(Mode::Busy, InterruptMode::Cli).is_ok());
+        for mode in [Mode::Wakeup, Mode::PingPong, Mode::Memory, Mode::Tick] {
+            assert!(mode_allows_interrupt_mode(mode, InterruptMode::Cli).is_err(), "{:?}", mode);
+            assert!(mode_allows_interrupt_mode(mode, InterruptMode::Normal).is_ok(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn cpu_overrides_are_rejected_unless_busy_with_interrupts_disabled() {
+        for mode in ["wakeup", "memory", "tick"] {
+            let cpu_config = format!("0:mode={}", mode);
+            assert!(parse_cpu_overrides(&cpu_config, &[0], InterruptMode::Cli).is_err(), "{}", mode);
+            assert_eq!(parse_cpu_overrides(&cpu_config, &[0], InterruptMode::Normal).unwrap()[0].mode, parse_mode(mode));
+        }
+        let cpu_overrides = parse_cpu_overrides("0:mode=busy,rt=50", &[0], InterruptMode::Cli).unwrap();
+        assert_eq!(cpu_overrides[0].mode, Some(Mode::Busy));
+        assert_eq!(cpu_overrides[0].rt_priority, Some(50));
+        assert!(parse_cpu_overrides("0:rt=50", &[0], InterruptMode::Cli).is_ok());
+    }
+
+    #[test]
+    fn cpu_overrides_are_validated() {
+        assert!(parse_cpu_override("0:mode=ping-pong").is_err());
+        assert!(parse_cpu_override("0:rt=100").is_err());
+        assert!(parse_cpu_override("0:colour=blue").is_err());
+        assert!(parse_cpu_override("mode=busy").is_err());
+        assert!(parse_cpu_overrides("0:rt=10", &[1], InterruptMode::Normal).is_err());
+        assert!(parse_cpu_overrides("0:rt=10;0:rt=20", &[0], InterruptMode::Normal).is_err());
+    }
+
+    #[test]
+    fn time_spans_take_units() {
+        assert_eq!(parse_time_span("500us", 1_000), Some(500));
+        assert_eq!(parse_time_span("10ms", 1_000), Some(10_000));
+        assert_eq!(parse_time_span("1s", 1_000), Some(MICROS_IN_SEC));
+        assert_eq!(parse_time_span("15m", 1_000), Some(900 * MICROS_IN_SEC));
+        assert_eq!(parse_time_span("2h", 1_000), Some(7_200 * MICROS_IN_SEC));
+        assert_eq!(parse_time_span("1d", 1_000), Some(86_400 * MICROS_IN_SEC));
+        assert_eq!(parse_time_span(" 100 ", 1_000), Some(100_000));
+        assert_eq!(parse_time_span("-1", 1_000), None);
+        assert_eq!(parse_time_span("1.5s", 1_000), None);
+        assert_eq!(parse_time_span("9223372036854775807d", 1_000), None);
+    }
+
+    #[test]
+    fn durations_are_whole_seconds() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("15m"), Ok(900));
+        assert_eq!(parse_duration("forever"), Ok(0));
+        assert_eq!(parse_duration("0"), Ok(0));
+        assert!(parse_duration("1500ms").is_err());
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn report_intervals_default_to_millis() {
+        assert_eq!(parse_report_interval("100"), Ok(100_000));
+        assert_eq!(parse_report_interval("500us"), Ok(500));
+        assert!(parse_report_interval("0").is_err());
+    }
+
+    #[test]
+    fn postgres_tables_are_plain_identifiers() {
+        for table in ["jitter", "_jitter2", "metrics.jitter_samples"] {
+            assert_eq!(parse_postgres_table(table), Ok(table.to_string()));
+        }
+        for table in ["", "2jitter", "jitter; DROP TABLE runs", "\"jitter\"", "a.b.c", ".jitter", "jitter.", "jit-ter"] {
+            assert!(parse_postgres_table(table).is_err(), "{}", table);
+        }
+    }
+
+    #[test]
+    fn rfc3339_timestamps_are_nanos_since_the_epoch() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Ok(951_782_400 * utils::NANOS_IN_SEC));
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:00.5+02:00"), Ok(1_714_557_600 * utils::NANOS_IN_SEC + 500_000_000));
+        assert_eq!(parse_rfc3339("2024-05-01t05:00:00.5-05:00"), Ok(1_714_557_600 * utils::NANOS_IN_SEC + 500_000_000));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59.000000001Z"), Ok(-utils::NANOS_IN_SEC + 1));
+    }
+
+    #[test]
+    fn malformed_rfc3339_timestamps_are_rejected() {
+        for value in ["2024-05-01", "2024-05-01T12:00:00", "2024-13-01T12:00:00Z", "2024-05-32T12:00:00Z", "2024-05-01T24:00:00Z",
+                      "2024-05-01T12:00Z", "2024-05-01T12:00:00.1234567891Z", "2024-05-01T12:00:00+0200", "24-5-1T12:00:00Z x"] {
+            assert!(parse_rfc3339(value).is_err(), "{}", value);
+        }
+    }
+}
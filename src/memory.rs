@@ -0,0 +1,37 @@
+use crate::{recorder::IntervalRecorder, utils::{self, Clock, ProgramArgs, NANOS_IN_SEC}, workload::{Workload, WorkloadKernel}};
+
+// enough hops for reading the clock to be cheap next to them, few enough that a single stalled access still stands out
+const HOPS_PER_READ: i64 = 8;
+
+
+/// Random-access memory latency: chases pointers through a random cycle over the working set and records the average
+/// latency of one access over every `HOPS_PER_READ` dependent loads. With a working set well beyond the last level
+/// cache, DRAM refresh, patrol scrubbing and memory throttling show up as periodic spikes.
+pub fn memory_loop(program_args: &ProgramArgs, clock: &Clock, recorder: &mut IntervalRecorder) {
+    let mut chase = WorkloadKernel::new(Workload::PointerChase, program_args.working_set_kib);
+    let mut previous = clock.ticks();
+    let deadline = if program_args.duration_seconds == 0 { i64::MAX } else { previous + clock.nanos_to_ticks(program_args.duration_seconds * NANOS_IN_SEC) };
+    let report_interval = clock.nanos_to_ticks(program_args.report_interval_micros * 1_000);
+    let mut next_report = previous + report_interval;
+    recorder.resync(previous);
+
+    while previous < deadline && !utils::stop_requested() {
+        for _ in 0..HOPS_PER_READ {
+            chase.step();
+        }
+        let mut now = clock.ticks();
+        if recorder.record((now - previous) / HOPS_PER_READ, now) {
+            now = clock.ticks();
+            recorder.resync(now);
+        }
+
+        if now > next_report {
+            next_report += report_interval * ((now - next_report) / report_interval + 1);
+            recorder.report(now);
+            now = clock.ticks();
+            recorder.resync(now);
+        }
+
+        previous = now;
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs;
+
+use crate::{utils::ProgramArgs, virt};
+
+
+/// Describes the machine and configuration of a run, published once at its start so that runs can be told apart and compared later.
+#[derive(Debug, Clone)]
+pub struct RunMetadata {
+    pub run_id: String,
+    pub kernel: Option<String>,
+    pub cmdline: Option<String>,
+    pub cpu_model: Option<String>,
+    pub microcode: Option<String>,
+    /// Vendor of the hypervisor when running in a virtual machine.
+    pub hypervisor: Option<String>,
+    pub time_source: &'static str,
+    pub counter_frequency_ghz: Option<f64>,
+    /// `<cpu>:<governor>` for every sampled cpu with cpufreq.
+    pub governors: Vec<String>,
+    /// Every program argument, secrets redacted.
+    pub args: String,
+}
+
+impl RunMetadata {
+    pub fn collect(program_args: &ProgramArgs) -> RunMetadata {
+        let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let cpuinfo_value = |key: &str| cpuinfo.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim() == key)
+            .map(|(_, value)| value.trim().to_string());
+
+        RunMetadata {
+            run_id: program_args.run_id.clone(),
+            kernel: read_trimmed("/proc/sys/kernel/osrelease"),
+            cmdline: read_trimmed("/proc/cmdline"),
+            cpu_model: cpuinfo_value("model name").or_else(|| cpuinfo_value("uarch")).or_else(|| cpuinfo_value("CPU part")),
+            microcode: cpuinfo_value("microcode"),
+            hypervisor: virt::hypervisor(),
+            time_source: program_args.clock.source().name(),
+            counter_frequency_ghz: counter_frequency(program_args),
+            governors: program_args.cpus.iter()
+                .filter_map(|&cpu| read_trimmed(&format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor", cpu)).map(|governor| format!("{}:{}", cpu, governor)))
+                .collect(),
+            args: format!("{:?}", program_args.redacted()),
+        }
+    }
+
+    /// Name and value of every known property, in a fixed order, for sinks that publish them as fields.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("time_source", self.time_source.to_string())];
+        let optional = [("kernel", &self.kernel), ("cmdline", &self.cmdline), ("cpu_model", &self.cpu_model), ("microcode", &self.microcode), ("hypervisor", &self.hypervisor)];
+        fields.extend(optional.iter().filter_map(|&(name, value)| value.clone().map(|value| (name, value))));
+        if let Some(frequency) = self.counter_frequency_ghz {
+            fields.push(("counter_frequency_ghz", format!("{:.6}", frequency)));
+        }
+        if !self.governors.is_empty() {
+            fields.push(("governors", self.governors.join(",")));
+        }
+        fields.push(("args", self.args.clone()));
+        fields
+    }
+}
+
+
+/// Random (version 4) UUID identifying a run.
+pub fn new_run_id() -> String {
+    let mut bytes = fastrand::u128(..).to_be_bytes();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+
+/// Frequency of the counter read by cycle counter time sources, even when the run reads another clock.
+fn counter_frequency(program_args: &ProgramArgs) -> Option<f64> {
+    if program_args.clock.source().is_cycle_counter() {
+        return Some(program_args.clock.frequency());
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+    return crate::utils::detect_counter_frequency().ok();
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    return None;
+}
+
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_string())
+}
@@ -0,0 +1,199 @@
+use std::{io::{self, Read, Write}, net::TcpStream, sync::Mutex, time::Duration};
+
+use log::info;
+use serde_json::json;
+
+use crate::{jitter::Jitter, sink::Sink, utils::ProgramArgs};
+
+const DEFAULT_PORT: u16 = 1883;
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const DISCONNECT: u8 = 0xe0;
+
+
+/// Publishes every interval as a json message to `<topic>/<host>/cpu<N>` (events to `.../cpu<N>/<measurement>`)
+/// speaking MQTT 3.1.1 over plain TCP, at the requested QoS.
+pub struct MqttSink {
+    address: String,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    topic: String,
+    qos: u8,
+    local_hostname: String,
+    run_id: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    next_packet_id: u16,
+}
+
+impl MqttSink {
+    pub fn new(url: &str, program_args: &ProgramArgs) -> MqttSink {
+        let address = url.strip_prefix("mqtt://").unwrap_or(url).trim_end_matches('/');
+        MqttSink {
+            address: if address.contains(':') { address.to_string() } else { format!("{}:{}", address, DEFAULT_PORT) },
+            client_id: format!("jitter-{}-{}", program_args.local_hostname, std::process::id()),
+            credentials: program_args.mqtt_user.as_ref().map(|user| {
+                (user.clone(), program_args.mqtt_password.as_ref().map(|password| password.0.clone()).unwrap_or_default())
+            }),
+            topic: program_args.mqtt_topic.trim_end_matches('/').to_string(),
+            qos: program_args.mqtt_qos,
+            local_hostname: program_args.local_hostname.clone(),
+            run_id: program_args.run_id.clone(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn publish_messages(&self, topic: &str, record_type: &str, cpu: u32, data_points: &[Jitter]) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(self.connect()?);
+        }
+
+        let result = data_points.iter().try_for_each(|data_point| {
+            let mut message = json!({ "type": record_type, "host": self.local_hostname, "run_id": self.run_id, "cpu": cpu, "ts": data_point.ts, "latency": data_point.latency });
+            for field in &data_point.fields {
+                message[field.name.as_ref()] = json!(field.value);
+            }
+            connection.as_mut().unwrap().publish(topic, message.to_string().as_bytes(), self.qos)
+        });
+        if result.is_err() {
+            // the broker drops clients it considers broken, start over with a fresh connection next time
+            *connection = None;
+        }
+        result
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let mut stream = TcpStream::connect(self.address.as_str())?;
+        stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+
+        let mut flags = 0x02; // clean session
+        let mut payload = encode_string(&self.client_id);
+        if let Some((user, password)) = &self.credentials {
+            flags |= 0x80 | 0x40;
+            payload.extend(encode_string(user));
+            payload.extend(encode_string(password));
+        }
+        // protocol name, level 4 (3.1.1), flags and a keep alive of 0, as the sink may stay quiet for longer than any sensible interval
+        let mut packet = encode_string("MQTT");
+        packet.extend([4, flags, 0, 0]);
+        packet.extend(payload);
+        write_packet(&mut stream, CONNECT, &packet)?;
+
+        match read_packet(&mut stream)? {
+            (CONNACK, body) if body.get(1) == Some(&0) => {
+                info!("Connected to MQTT broker at: {}", self.address);
+                Ok(Connection { stream, next_packet_id: 1 })
+            },
+            (CONNACK, body) => Err(io::Error::other(format!("MQTT broker refused connection with return code: {:?}", body.get(1)))),
+            (packet_type, _) => Err(unexpected(packet_type)),
+        }
+    }
+}
+
+impl Connection {
+    fn publish(&mut self, topic: &str, payload: &[u8], qos: u8) -> io::Result<()> {
+        let mut packet = encode_string(topic);
+        let packet_id = self.next_packet_id;
+        if qos > 0 {
+            packet.extend(packet_id.to_be_bytes());
+            self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        }
+        packet.extend(payload);
+        write_packet(&mut self.stream, PUBLISH | (qos << 1), &packet)?;
+
+        match qos {
+            0 => Ok(()),
+            1 => self.expect(PUBACK, packet_id),
+            _ => {
+                self.expect(PUBREC, packet_id)?;
+                write_packet(&mut self.stream, PUBREL, &packet_id.to_be_bytes())?;
+                self.expect(PUBCOMP, packet_id)
+            },
+        }
+    }
+
+    fn expect(&mut self, expected_type: u8, packet_id: u16) -> io::Result<()> {
+        match read_packet(&mut self.stream)? {
+            (packet_type, body) if packet_type == expected_type && body.get(..2) == Some(&packet_id.to_be_bytes()[..]) => Ok(()),
+            (packet_type, _) => Err(unexpected(packet_type)),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = write_packet(&mut self.stream, DISCONNECT, &[]);
+    }
+}
+
+
+fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+    // remaining length, 7 bits at a time, least significant first
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        packet.push(if length > 0 { byte | 0x80 } else { byte });
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)
+}
+
+
+fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    let packet_type = byte[0] & 0xf0;
+
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body)?;
+    Ok((packet_type, body))
+}
+
+
+fn encode_string(value: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(value.len() + 2);
+    encoded.extend((value.len() as u16).to_be_bytes());
+    encoded.extend(value.as_bytes());
+    encoded
+}
+
+
+fn unexpected(packet_type: u8) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected MQTT packet type: {:#x}", packet_type))
+}
+
+impl Sink for MqttSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.publish_messages(&format!("{}/{}/cpu{}", self.topic, self.local_hostname, cpu), "jitter", cpu, samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.publish_messages(&format!("{}/{}/cpu{}/{}", self.topic, self.local_hostname, cpu, measurement), measurement, cpu, events)
+    }
+}
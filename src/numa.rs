@@ -0,0 +1,60 @@
+use std::{fs, io};
+
+use nix::libc;
+
+const MPOL_MF_STRICT: libc::c_uint = 1;
+const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+const NODE_MASK_BITS: usize = 64 * 16;
+
+
+/// NUMA node the cpu belongs to, as linked from its sysfs directory.
+pub fn cpu_node(cpu: u32) -> io::Result<u32> {
+    fs::read_dir(format!("/sys/devices/system/cpu/cpu{}", cpu))?
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| entry.file_name().to_str().and_then(|name| name.strip_prefix("node")).and_then(|node| node.parse::<u32>().ok()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("cpu{} is not linked to any numa node", cpu)))
+}
+
+
+/// Restricts every further allocation of the calling thread to the given node.
+pub fn bind_thread_to_node(node: u32) -> io::Result<()> {
+    let node_mask = node_mask(node)?;
+    let result = unsafe { libc::syscall(libc::SYS_set_mempolicy, libc::MPOL_BIND, node_mask.as_ptr(), NODE_MASK_BITS) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+
+/// Moves the pages backing `buffer` to the given node, failing if any of them cannot be moved.
+pub fn bind_buffer_to_node<T>(buffer: &[T], node: u32) -> io::Result<()> {
+    let length = std::mem::size_of_val(buffer);
+    if length == 0 {
+        return Ok(());
+    }
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let start = buffer.as_ptr() as usize & !(page_size - 1);
+    let end = buffer.as_ptr() as usize + length;
+    let node_mask = node_mask(node)?;
+    let result = unsafe { libc::syscall(libc::SYS_mbind, start, end - start, libc::MPOL_BIND, node_mask.as_ptr(), NODE_MASK_BITS, MPOL_MF_MOVE | MPOL_MF_STRICT) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+
+fn node_mask(node: u32) -> io::Result<[u64; NODE_MASK_BITS / 64]> {
+    let node = node as usize;
+    if node >= NODE_MASK_BITS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("numa node {} is out of range", node)));
+    }
+
+    let mut mask = [0u64; NODE_MASK_BITS / 64];
+    mask[node / 64] |= 1 << (node % 64);
+    Ok(mask)
+}
@@ -0,0 +1,31 @@
+use std::{io, sync::Arc, time::Duration};
+
+use crate::{jitter::Jitter, prometheus::PrometheusExporter, tui::Dashboard, utils::ProgramArgs};
+
+
+/// Gets notified from the sampler threads about every completed reporting interval while the run is in progress.
+/// Called outside of the measured window, but still on the measuring cpu, so implementations must be cheap and non-blocking.
+pub trait IntervalObserver: Send + Sync {
+    fn on_interval(&self, cpu: u32, sample: &Jitter);
+
+    /// Individual latencies captured since the previous interval, eg: outliers or the worst latencies of the interval.
+    fn on_events(&self, _cpu: u32, _measurement: &'static str, _events: &[Jitter]) {}
+
+    /// Called once all sampler threads are done.
+    fn on_finish(&self) {}
+}
+
+
+pub fn configure_observers(program_args: &ProgramArgs) -> io::Result<Vec<Arc<dyn IntervalObserver>>> {
+    let mut observers: Vec<Arc<dyn IntervalObserver>> = Vec::default();
+
+    if let Some(listen_address) = &program_args.prometheus_listen {
+        observers.push(PrometheusExporter::start(listen_address, program_args)?);
+    }
+
+    if program_args.tui_enabled {
+        observers.push(Dashboard::start(&program_args.cpus, Duration::from_micros(program_args.report_interval_micros as u64))?);
+    }
+
+    Ok(observers)
+}
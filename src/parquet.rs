@@ -0,0 +1,415 @@
+use std::{fs::File, io::{self, BufWriter, Write}};
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+// parquet.thrift enums
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const REQUIRED: i32 = 0;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const UNCOMPRESSED: i32 = 0;
+const DATA_PAGE: i32 = 0;
+
+// thrift compact protocol field types
+const BOOLEAN_TRUE: u8 = 1;
+const BOOLEAN_FALSE: u8 = 2;
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+
+/// Writes raw samples as a Parquet file with the required columns `cpu` (int32), `ts` (int64 timestamp, ns since the epoch,
+/// UTC) and `latency` (int64, ns), one row group per `write_row_group` call. Pages are PLAIN encoded and uncompressed, which
+/// every reader supports and which keeps this free of dependencies; `finish()` has to be called to make the file readable.
+pub struct ParquetWriter {
+    writer: BufWriter<File>,
+    offset: i64,
+    row_groups: Vec<RowGroup>,
+}
+
+struct RowGroup {
+    rows: i64,
+    columns: Vec<ColumnChunk>,
+}
+
+struct ColumnChunk {
+    name: &'static str,
+    physical_type: i32,
+    offset: i64,
+    size: i64,
+}
+
+impl ParquetWriter {
+    pub fn create(path: &str) -> io::Result<ParquetWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(ParquetWriter { writer, offset: MAGIC.len() as i64, row_groups: Vec::new() })
+    }
+
+    pub fn write_row_group(&mut self, cpu: u32, ts: &[i64], latency: &[i64]) -> io::Result<()> {
+        debug_assert_eq!(ts.len(), latency.len());
+        if ts.is_empty() {
+            return Ok(());
+        }
+
+        let cpus: Vec<u8> = std::iter::repeat_n((cpu as i32).to_le_bytes(), ts.len()).flatten().collect();
+        let columns = vec![
+            self.write_column("cpu", INT32, ts.len(), &cpus)?,
+            self.write_column("ts", INT64, ts.len(), &ts.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>())?,
+            self.write_column("latency", INT64, ts.len(), &latency.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>())?,
+        ];
+        self.row_groups.push(RowGroup { rows: ts.len() as i64, columns });
+        Ok(())
+    }
+
+    /// Writes the file footer; the file is not valid Parquet before this.
+    pub fn finish(mut self) -> io::Result<()> {
+        let footer = self.file_metadata();
+        self.writer.write_all(&footer)?;
+        self.writer.write_all(&(footer.len() as u32).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()
+    }
+
+    /// A column chunk made of a single data page. Columns are required, so the page holds no definition or repetition levels.
+    fn write_column(&mut self, name: &'static str, physical_type: i32, values: usize, data: &[u8]) -> io::Result<ColumnChunk> {
+        let mut header = Thrift::default();
+        header.i32(1, DATA_PAGE);
+        header.i32(2, data.len() as i32);
+        header.i32(3, data.len() as i32);
+        header.begin_struct(5);
+        header.i32(1, values as i32);
+        header.i32(2, PLAIN);
+        header.i32(3, RLE);
+        header.i32(4, RLE);
+        header.end_struct();
+        header.end_struct();
+
+        self.writer.write_all(&header.bytes)?;
+        self.writer.write_all(data)?;
+        let chunk = ColumnChunk { name, physical_type, offset: self.offset, size: (header.bytes.len() + data.len()) as i64 };
+        self.offset += chunk.size;
+        Ok(chunk)
+    }
+
+    fn file_metadata(&self) -> Vec<u8> {
+        let mut metadata = Thrift::default();
+        metadata.i32(1, 1);
+
+        metadata.begin_list(2, STRUCT, 4);
+        metadata.begin_element();
+        metadata.binary(4, "jitter");
+        metadata.i32(5, 3);
+        metadata.end_struct();
+        for (name, physical_type) in [("cpu", INT32), ("ts", INT64), ("latency", INT64)] {
+            metadata.begin_element();
+            metadata.i32(1, physical_type);
+            metadata.i32(3, REQUIRED);
+            metadata.binary(4, name);
+            if name == "ts" {
+                // LogicalType.TIMESTAMP { isAdjustedToUTC: true, unit: TimeUnit.NANOS }
+                metadata.begin_struct(10);
+                metadata.begin_struct(8);
+                metadata.bool(1, true);
+                metadata.begin_struct(2);
+                metadata.begin_struct(3);
+                metadata.end_struct();
+                metadata.end_struct();
+                metadata.end_struct();
+                metadata.end_struct();
+            }
+            metadata.end_struct();
+        }
+
+        metadata.i64(3, self.row_groups.iter().map(|row_group| row_group.rows).sum());
+
+        metadata.begin_list(4, STRUCT, self.row_groups.len());
+        for row_group in &self.row_groups {
+            metadata.begin_element();
+            metadata.begin_list(1, STRUCT, row_group.columns.len());
+            for column in &row_group.columns {
+                metadata.begin_element();
+                metadata.i64(2, column.offset);
+                metadata.begin_struct(3);
+                metadata.i32(1, column.physical_type);
+                metadata.begin_list(2, I32, 1);
+                metadata.element_i32(PLAIN);
+                metadata.begin_list(3, BINARY, 1);
+                metadata.element_binary(column.name);
+                metadata.i32(4, UNCOMPRESSED);
+                metadata.i64(5, row_group.rows);
+                metadata.i64(6, column.size);
+                metadata.i64(7, column.size);
+                metadata.i64(9, column.offset);
+                metadata.end_struct();
+                metadata.end_struct();
+            }
+            metadata.i64(2, row_group.columns.iter().map(|column| column.size).sum());
+            metadata.i64(3, row_group.rows);
+            metadata.end_struct();
+        }
+
+        metadata.binary(6, concat!("jitter version ", env!("CARGO_PKG_VERSION")));
+        metadata.end_struct();
+        metadata.bytes
+    }
+}
+
+
+/// Just enough of the thrift compact protocol to write parquet metadata. Every struct, including the outermost one,
+/// is closed with `end_struct()`; list elements that are structs are opened with `begin_element()`.
+#[derive(Default)]
+struct Thrift {
+    bytes: Vec<u8>,
+    last_field_ids: Vec<i16>,
+    last_field_id: i16,
+}
+
+impl Thrift {
+    fn field(&mut self, id: i16, field_type: u8) {
+        let delta = id - self.last_field_id;
+        if delta > 0 && delta <= 15 {
+            self.bytes.push((delta as u8) << 4 | field_type);
+        } else {
+            self.bytes.push(field_type);
+            self.varint(zigzag(id as i64));
+        }
+        self.last_field_id = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.varint(zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.varint(zigzag(value));
+    }
+
+    fn bool(&mut self, id: i16, value: bool) {
+        self.field(id, if value { BOOLEAN_TRUE } else { BOOLEAN_FALSE });
+    }
+
+    fn binary(&mut self, id: i16, value: &str) {
+        self.field(id, BINARY);
+        self.element_binary(value);
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.begin_element();
+    }
+
+    fn begin_element(&mut self) {
+        self.last_field_ids.push(self.last_field_id);
+        self.last_field_id = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.bytes.push(0);
+        self.last_field_id = self.last_field_ids.pop().unwrap_or_default();
+    }
+
+    fn begin_list(&mut self, id: i16, element_type: u8, size: usize) {
+        self.field(id, LIST);
+        if size < 15 {
+            self.bytes.push((size as u8) << 4 | element_type);
+        } else {
+            self.bytes.push(0xf0 | element_type);
+            self.varint(size as u64);
+        }
+    }
+
+    fn element_i32(&mut self, value: i32) {
+        self.varint(zigzag(value as i64));
+    }
+
+    fn element_binary(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend(value.as_bytes());
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+}
+
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Int(i64),
+        Bool(bool),
+        Binary(Vec<u8>),
+        List(Vec<Value>),
+        Struct(Vec<(i16, Value)>),
+    }
+
+    impl Value {
+        fn field(&self, id: i16) -> &Value {
+            match self {
+                Value::Struct(fields) => &fields.iter().find(|(field_id, _)| *field_id == id).unwrap_or_else(|| panic!("no field {} in {:?}", id, self)).1,
+                _ => panic!("{:?} is no struct", self),
+            }
+        }
+
+        fn int(&self) -> i64 {
+            match self {
+                Value::Int(value) => *value,
+                _ => panic!("{:?} is no integer", self),
+            }
+        }
+
+        fn list(&self) -> &[Value] {
+            match self {
+                Value::List(elements) => elements,
+                _ => panic!("{:?} is no list", self),
+            }
+        }
+    }
+
+    /// Decodes the thrift compact protocol, independently of the writer above.
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.position += 1;
+            self.bytes[self.position - 1]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let (mut value, mut shift) = (0, 0);
+            loop {
+                let byte = self.byte();
+                value |= ((byte & 0x7f) as u64) << shift;
+                if byte < 0x80 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let value = self.varint();
+            (value >> 1) as i64 ^ -((value & 1) as i64)
+        }
+
+        fn value(&mut self, value_type: u8) -> Value {
+            match value_type {
+                I32 | I64 => Value::Int(self.zigzag()),
+                BINARY => {
+                    let length = self.varint() as usize;
+                    self.position += length;
+                    Value::Binary(self.bytes[self.position - length..self.position].to_vec())
+                },
+                LIST => {
+                    let header = self.byte();
+                    let size = if header >> 4 == 15 { self.varint() as usize } else { (header >> 4) as usize };
+                    Value::List((0..size).map(|_| self.value(header & 0x0f)).collect())
+                },
+                STRUCT => {
+                    let (mut fields, mut last_id) = (Vec::new(), 0);
+                    loop {
+                        let header = self.byte();
+                        if header == 0 {
+                            return Value::Struct(fields);
+                        }
+                        let id = if header >> 4 == 0 { self.zigzag() as i16 } else { last_id + (header >> 4) as i16 };
+                        let value = match header & 0x0f {
+                            BOOLEAN_TRUE => Value::Bool(true),
+                            BOOLEAN_FALSE => Value::Bool(false),
+                            field_type => self.value(field_type),
+                        };
+                        fields.push((id, value));
+                        last_id = id;
+                    }
+                },
+                other => panic!("unexpected thrift type {}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn footer_points_at_the_column_chunks() {
+        let path = std::env::temp_dir().join(format!("jitter-parquet-{}.parquet", std::process::id()));
+        let mut writer = ParquetWriter::create(path.to_str().unwrap()).unwrap();
+        let row_groups: [(u32, Vec<i64>, Vec<i64>); 3] = [(3, vec![1_000, 2_000, 3_000], vec![45, 46, 9_000_000_000]), (7, vec![], vec![]), (5, vec![-1, 1 << 40], vec![0, 1])];
+        for (cpu, ts, latency) in &row_groups {
+            writer.write_row_group(*cpu, ts, latency).unwrap();
+        }
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(&bytes[bytes.len() - 4..], MAGIC);
+        let footer_length = u32::from_le_bytes(bytes[bytes.len() - 8..bytes.len() - 4].try_into().unwrap()) as usize;
+        let footer_start = bytes.len() - 8 - footer_length;
+        let mut footer = Reader { bytes: &bytes[..bytes.len() - 8], position: footer_start };
+        let metadata = footer.value(STRUCT);
+        assert_eq!(footer.position, bytes.len() - 8, "footer length");
+
+        assert_eq!(metadata.field(1).int(), 1);
+        let names: Vec<&Value> = metadata.field(2).list().iter().map(|element| element.field(4)).collect();
+        assert_eq!(names, [&Value::Binary(b"jitter".to_vec()), &Value::Binary(b"cpu".to_vec()), &Value::Binary(b"ts".to_vec()), &Value::Binary(b"latency".to_vec())]);
+        assert_eq!(metadata.field(3).int(), 5);
+
+        let written: Vec<&(u32, Vec<i64>, Vec<i64>)> = row_groups.iter().filter(|(_, ts, _)| !ts.is_empty()).collect();
+        assert_eq!(metadata.field(4).list().len(), written.len());
+        let mut next_offset = MAGIC.len() as i64;
+        for (row_group, (cpu, ts, latency)) in metadata.field(4).list().iter().zip(written) {
+            let rows = ts.len() as i64;
+            assert_eq!(row_group.field(3).int(), rows);
+            let columns = row_group.field(1).list();
+            assert_eq!(row_group.field(2).int(), columns.iter().map(|column| column.field(3).field(7).int()).sum::<i64>());
+
+            let expected: [(&str, Vec<u8>); 3] = [
+                ("cpu", ts.iter().flat_map(|_| (*cpu as i32).to_le_bytes()).collect()),
+                ("ts", ts.iter().flat_map(|value| value.to_le_bytes()).collect()),
+                ("latency", latency.iter().flat_map(|value| value.to_le_bytes()).collect()),
+            ];
+            for (column, (name, values)) in columns.iter().zip(expected) {
+                let column_metadata = column.field(3);
+                assert_eq!(column_metadata.field(3).list(), [Value::Binary(name.as_bytes().to_vec())]);
+                assert_eq!(column_metadata.field(5).int(), rows);
+                assert_eq!(column.field(2).int(), next_offset, "{}", name);
+                assert_eq!(column_metadata.field(9).int(), next_offset, "{}", name);
+
+                let mut page = Reader { bytes: &bytes, position: next_offset as usize };
+                let page_header = page.value(STRUCT);
+                assert_eq!(page_header.field(1).int(), DATA_PAGE as i64);
+                assert_eq!(page_header.field(2).int(), values.len() as i64);
+                assert_eq!(page_header.field(3).int(), values.len() as i64);
+                assert_eq!(page_header.field(5).field(1).int(), rows);
+                assert_eq!(&bytes[page.position..page.position + values.len()], &values[..], "{}", name);
+
+                let size = (page.position + values.len()) as i64 - next_offset;
+                assert_eq!(column_metadata.field(6).int(), size);
+                assert_eq!(column_metadata.field(7).int(), size);
+                next_offset += size;
+            }
+        }
+        assert_eq!(next_offset as usize, footer_start);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{fs::File, io::{self, Read}, os::unix::io::FromRawFd, sync::Arc};
+
+use nix::libc;
+
+use crate::{jitter::Field, probe::IntervalProbe};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+pub(crate) const PERF_TYPE_TRACEPOINT: u32 = 2;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+// PERF_COUNT_HW_CACHE_LL | PERF_COUNT_HW_CACHE_OP_READ << 8 | PERF_COUNT_HW_CACHE_RESULT_MISS << 16
+const PERF_COUNT_HW_CACHE_LL_READ_MISS: u64 = 2 | 1 << 16;
+const PERF_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const PERF_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfCounter {
+    Cycles,
+    Instructions,
+    CacheMisses,
+    LlcMisses,
+}
+
+impl PerfCounter {
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfCounter::Cycles => "cycles",
+            PerfCounter::Instructions => "instructions",
+            PerfCounter::CacheMisses => "cache-misses",
+            PerfCounter::LlcMisses => "llc-misses",
+        }
+    }
+
+    fn event(self) -> (u32, u64) {
+        match self {
+            PerfCounter::Cycles => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+            PerfCounter::Instructions => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+            PerfCounter::CacheMisses => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES),
+            PerfCounter::LlcMisses => (PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_LL_READ_MISS),
+        }
+    }
+}
+
+
+/// The leading, version 0 part of the kernel's perf_event_attr, which is all that counting needs.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+
+/// Publishes hardware counter deltas of the sampling thread for every interval as `perf_<counter>` fields
+/// (eg: perf_cycles, perf_llc_misses).
+pub struct PerfProbe {
+    counters: Vec<(Arc<str>, File, u64)>,
+}
+
+impl PerfProbe {
+    pub fn new(counters: &[PerfCounter]) -> io::Result<PerfProbe> {
+        let mut opened = Vec::with_capacity(counters.len());
+        for &counter in counters {
+            let mut file = open_counter(counter)?;
+            let initial = read_counter(&mut file)?;
+            opened.push((Arc::from(format!("perf_{}", counter.name().replace('-', "_"))), file, initial));
+        }
+
+        Ok(PerfProbe { counters: opened })
+    }
+}
+
+impl IntervalProbe for PerfProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        for (name, file, previous) in self.counters.iter_mut() {
+            let current = read_counter(file).unwrap_or(*previous);
+            fields.push(Field { name: name.clone(), value: current.wrapping_sub(*previous) as i64 });
+            *previous = current;
+        }
+    }
+}
+
+
+/// Counts the calling thread on whatever cpu it runs. Kernel mode is only excluded if perf_event_paranoid forbids counting it.
+fn open_counter(counter: PerfCounter) -> io::Result<File> {
+    let (kind, config) = counter.event();
+    let mut attr = PerfEventAttr { kind, size: std::mem::size_of::<PerfEventAttr>() as u32, config, ..PerfEventAttr::default() };
+
+    let mut fd = perf_event_open(&attr, 0, -1);
+    if fd < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EACCES) {
+        attr.flags |= PERF_FLAG_EXCLUDE_KERNEL | PERF_FLAG_EXCLUDE_HV;
+        fd = perf_event_open(&attr, 0, -1);
+    }
+
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return Err(io::Error::new(err.kind(), format!("unable to open {} counter: {}", counter.name(), err)));
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+
+/// Counts every occurrence of the event on `cpu`, whichever task triggers it. Needs perf_event_paranoid <= 0 or CAP_PERFMON.
+pub(crate) fn open_cpu_counter(kind: u32, config: u64, cpu: u32) -> io::Result<File> {
+    let attr = PerfEventAttr { kind, size: std::mem::size_of::<PerfEventAttr>() as u32, config, ..PerfEventAttr::default() };
+    let fd = perf_event_open(&attr, -1, cpu as i32);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+
+fn perf_event_open(attr: &PerfEventAttr, pid: libc::pid_t, cpu: libc::c_int) -> libc::c_long {
+    unsafe { libc::syscall(libc::SYS_perf_event_open, attr as *const PerfEventAttr, pid, cpu, -1, 0) }
+}
+
+
+pub(crate) fn read_counter(file: &mut File) -> io::Result<u64> {
+    let mut value = [0u8; 8];
+    file.read_exact(&mut value)?;
+    Ok(u64::from_ne_bytes(value))
+}
@@ -0,0 +1,185 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+
+use log::info;
+
+use crate::{error::{self, JitterError}, jitter::{Field, Jitter, CORE_TO_CORE_MEASUREMENT}, observer::IntervalObserver, sampler::{CpuJitter, SetupArrival, SetupLatch}, utils::{self, ProgramArgs, NANOS_IN_SEC}};
+
+const STOP: u64 = u64::MAX;
+
+
+/// The cache line bounced between the two cpus. The initiator hands it over by writing an odd turn,
+/// the responder hands it back by writing the next even one.
+#[repr(align(64))]
+struct SharedLine {
+    turn: AtomicU64,
+}
+
+
+/// Tells the peer thread to stop however this one leaves, so that neither ever spins forever waiting for the other.
+struct StopPeer<'a>(&'a SharedLine);
+
+impl Drop for StopPeer<'_> {
+    fn drop(&mut self) {
+        self.0.turn.store(STOP, Ordering::Release);
+    }
+}
+
+
+/// Measures round trips of a cache line between the two sampled cpus: the first one initiates every round trip and
+/// times it, the second one only answers. Every interval is reported as a `core_to_core_latency` event of the
+/// initiating cpu, with the worst round trip as latency.
+pub fn capture_round_trips(program_args: &ProgramArgs, observers: &[Arc<dyn IntervalObserver>], setup_latch: Option<&SetupLatch>) -> error::Result<CpuJitter> {
+    let (initiator, responder) = match program_args.cpus[..] {
+        [initiator, responder] => (initiator, responder),
+        _ => return Err(JitterError::PingPongCpus(program_args.cpus.len())),
+    };
+    let line = SharedLine { turn: AtomicU64::new(0) };
+    let line = &line;
+
+    let (initiated, answered) = crossbeam::scope(|s| {
+        let answering = s.spawn(move |_| answer(responder, program_args, line, setup_latch));
+        let initiating = s.spawn(move |_| initiate(initiator, responder, program_args, observers, line, setup_latch));
+        (initiating.join(), answering.join())
+    }).expect("Panics of ping-pong threads are caught when joining them");
+
+    // a failing responder stops the initiator early, its error is the one worth reporting
+    answered.unwrap_or_else(|_| Err(JitterError::SamplerPanicked { cpu: responder, message: "responder panicked".to_string() }))?;
+    initiated.unwrap_or_else(|_| Err(JitterError::SamplerPanicked { cpu: initiator, message: "initiator panicked".to_string() }))
+}
+
+
+fn setup_thread(cpu: u32, program_args: &ProgramArgs) -> error::Result<()> {
+    let program_args = program_args.for_cpu(cpu);
+    info!("Affinitizing ping-pong thread to cpu: {}", cpu);
+    utils::affinitize_to_cpu(cpu)?;
+    if let Some(priority) = program_args.rt_priority {
+        info!("Setting SCHED_FIFO priority {} for ping-pong thread of cpu: {}", priority, cpu);
+        utils::set_realtime_priority(priority)?;
+    }
+
+    Ok(())
+}
+
+
+fn answer(cpu: u32, program_args: &ProgramArgs, line: &SharedLine, setup_latch: Option<&SetupLatch>) -> error::Result<()> {
+    let _stop_initiator = StopPeer(line);
+    let setup = SetupArrival::new(setup_latch);
+    setup_thread(cpu, program_args)?;
+    setup.wait();
+
+    let mut answered = 0;
+    loop {
+        let turn = line.turn.load(Ordering::Acquire);
+        if turn == STOP {
+            return Ok(());
+        }
+        // never overwrites a STOP of the initiator
+        if turn % 2 == 1 && turn != answered && line.turn.compare_exchange(turn, turn + 1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            answered = turn;
+        }
+        std::hint::spin_loop();
+    }
+}
+
+
+fn initiate(cpu: u32, peer: u32, program_args: &ProgramArgs, observers: &[Arc<dyn IntervalObserver>], line: &SharedLine, setup_latch: Option<&SetupLatch>) -> error::Result<CpuJitter> {
+    let _stop_responder = StopPeer(line);
+    let setup = SetupArrival::new(setup_latch);
+    setup_thread(cpu, program_args)?;
+
+    let mut clock = program_args.clock;
+    if clock.source().is_cycle_counter() {
+        clock.align_with_realtime();
+    }
+    // only fixed length runs keep their intervals, the ones sampling until stopped hand them to the observers
+    let interval_count = if program_args.duration_seconds == 0 { 0 } else { (program_args.duration_seconds * 1_000_000 / program_args.report_interval_micros) as usize };
+    let mut result = CpuJitter {
+        cpu,
+        samples: Vec::new(),
+        outliers: Vec::new(),
+        top_latencies: Vec::new(),
+        discontinuities: Vec::new(),
+        round_trips: Vec::with_capacity(interval_count),
+        histogram_buckets: Vec::new(),
+        histogram: None,
+    };
+    let fields: [Arc<str>; 4] = [Arc::from("peer_cpu"), Arc::from("min"), Arc::from("mean"), Arc::from("round_trips")];
+    setup.wait();
+    if let Some(start_at) = program_args.start_at_nanos {
+        utils::wait_until_realtime(start_at);
+    }
+    info!("Measuring round trips between cpu: {} and cpu: {}", cpu, peer);
+
+    let start = clock.now();
+    let deadline = if program_args.duration_seconds == 0 { i64::MAX } else { start + program_args.duration_seconds * NANOS_IN_SEC };
+    let report_interval = program_args.report_interval_micros * 1_000;
+    let mut next_report = start + report_interval;
+    let (mut min, mut max, mut sum, mut count) = (i64::MAX, i64::MIN, 0, 0);
+    let mut turn = 1;
+
+    loop {
+        let sent = clock.ticks();
+        // publishing the turn over the last answer never overwrites a STOP of a responder that failed to set up
+        if line.turn.compare_exchange(turn - 1, turn, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            break;
+        }
+        let answer = loop {
+            match line.turn.load(Ordering::Acquire) {
+                answer if answer == turn + 1 || answer == STOP => break answer,
+                _ if utils::stop_requested() => break STOP,
+                _ => std::hint::spin_loop(),
+            }
+        };
+        let received = clock.ticks();
+        if answer == STOP {
+            break;
+        }
+        turn += 2;
+
+        let round_trip = clock.ticks_to_nanos(received - sent);
+        min = min.min(round_trip);
+        max = max.max(round_trip);
+        sum += round_trip;
+        count += 1;
+
+        let now = clock.timestamp(received);
+        if now > next_report {
+            next_report += report_interval * ((now - next_report) / report_interval + 1);
+            let event = Jitter {
+                ts: now,
+                latency: max,
+                fields: fields.iter().cloned().zip([peer as i64, min, sum / count, count].iter().copied())
+                    .map(|(name, value)| Field { name, value })
+                    .collect(),
+            };
+            for observer in observers {
+                observer.on_events(cpu, CORE_TO_CORE_MEASUREMENT, std::slice::from_ref(&event));
+            }
+            if result.round_trips.len() < interval_count {
+                result.round_trips.push(event);
+            }
+            (min, max, sum, count) = (i64::MAX, i64::MIN, 0, 0);
+        }
+
+        if now >= deadline || utils::stop_requested() {
+            break;
+        }
+    }
+
+    Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initiator_stops_when_the_responder_gave_up_first() {
+        let program_args = ProgramArgs { cpus: vec![0, 0], ..ProgramArgs::default() };
+        let line = SharedLine { turn: AtomicU64::new(STOP) };
+        let result = initiate(0, 0, &program_args, &[], &line, None).unwrap();
+        assert!(result.round_trips.is_empty());
+        assert_eq!(line.turn.load(Ordering::Acquire), STOP);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{io::{self, Write}, process::{Command, Stdio}};
+
+use log::info;
+use serde_json::json;
+
+use crate::{jitter::Jitter, sink::Sink, utils::ProgramArgs};
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+
+/// Copies samples into a Postgres (or TimescaleDB) table through `psql`, which takes care of authentication, TLS and
+/// any libpq settings (~/.pgpass, PGSSLMODE, ...). The table has one row per interval or event:
+/// `time timestamptz, host text, cpu integer, measurement text, latency bigint (ns), fields jsonb`.
+/// A password in the url is handed to psql through PGPASSWORD, keeping it out of its command line (and so out of ps).
+pub struct PostgresSink {
+    url: String,
+    password: Option<String>,
+    table: String,
+    local_hostname: String,
+}
+
+impl PostgresSink {
+    pub fn create(url: &str, program_args: &ProgramArgs) -> io::Result<PostgresSink> {
+        let (url, password) = split_password(url);
+        let sink = PostgresSink { url, password, table: program_args.postgres_table.clone(), local_hostname: program_args.local_hostname.clone() };
+        if program_args.postgres_create_table {
+            sink.create_table()?;
+        }
+
+        Ok(sink)
+    }
+
+    /// Creates the table if missing and, when the timescaledb extension is installed, turns it into a hypertable.
+    fn create_table(&self) -> io::Result<()> {
+        info!("Creating table {} unless it exists", self.table);
+        let statement = format!(
+            "CREATE TABLE IF NOT EXISTS {table} (time timestamptz NOT NULL, host text NOT NULL, cpu integer NOT NULL, measurement text NOT NULL, latency bigint NOT NULL, fields jsonb); \
+             DO $$ BEGIN IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN PERFORM create_hypertable('{table}', 'time', if_not_exists => TRUE); END IF; END $$;",
+            table = self.table);
+        self.psql(&statement, &[])
+    }
+
+    fn copy_rows(&self, cpu: u32, measurement: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let mut rows = Vec::with_capacity(data_points.len() * 96);
+        for data_point in data_points {
+            let fields: serde_json::Map<String, serde_json::Value> = data_point.fields.iter().map(|field| (field.name.to_string(), json!(field.value))).collect();
+            writeln!(rows, "{}\t{}\t{}\t{}\t{}\t{}", timestamp(data_point.ts), copy_text(&self.local_hostname), cpu, copy_text(measurement), data_point.latency,
+                copy_text(&serde_json::Value::Object(fields).to_string()))?;
+        }
+
+        self.psql(&format!("\\copy {} (time, host, cpu, measurement, latency, fields) FROM STDIN", self.table), &rows)
+    }
+
+    fn psql(&self, command: &str, input: &[u8]) -> io::Result<()> {
+        let mut psql = Command::new("psql");
+        if let Some(password) = &self.password {
+            psql.env("PGPASSWORD", password);
+        }
+        let mut psql = psql
+            .args(["--no-psqlrc", "--quiet", "--set", "ON_ERROR_STOP=1", "--dbname", &self.url, "--command", command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("Unable to run psql: {}", err)))?;
+
+        let written = psql.stdin.take().unwrap().write_all(input);
+        let output = psql.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("psql failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        written
+    }
+}
+
+
+/// Connection url without its password (`user:password@` or a `password` parameter), and the password, percent-decoded.
+fn split_password(url: &str) -> (String, Option<String>) {
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) if scheme == "postgres" || scheme == "postgresql" => (scheme, rest),
+        // keyword/value connection strings are passed through as they are
+        _ => return (url.to_string(), None),
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let mut password = None;
+
+    let authority = match authority.rsplit_once('@') {
+        Some((userinfo, host)) => match userinfo.split_once(':') {
+            Some((user, secret)) => {
+                password = Some(percent_decode(secret));
+                format!("{}@{}", user, host)
+            },
+            None => authority.to_string(),
+        },
+        None => authority.to_string(),
+    };
+    let path = match path.split_once('?') {
+        Some((path, query)) => {
+            let (fragment, query) = match query.split_once('#') {
+                Some((query, fragment)) => (format!("#{}", fragment), query),
+                None => (String::new(), query),
+            };
+            let parameters: Vec<&str> = query.split('&')
+                .filter(|parameter| match parameter.strip_prefix("password=") {
+                    Some(secret) => {
+                        password = Some(percent_decode(secret));
+                        false
+                    },
+                    None => true,
+                })
+                .collect();
+            if parameters.is_empty() { format!("{}{}", path, fragment) } else { format!("{}?{}{}", path, parameters.join("&"), fragment) }
+        },
+        None => path.to_string(),
+    };
+
+    (format!("{}://{}{}", scheme, authority, path), password)
+}
+
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+
+/// Nanosecond epoch as an ISO 8601 UTC timestamp, truncated to the microseconds Postgres keeps.
+fn timestamp(ts: i64) -> String {
+    let (seconds, nanos) = (ts.div_euclid(NANOS_PER_SECOND), ts.rem_euclid(NANOS_PER_SECOND));
+    let (days, second_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // days since the epoch to a civil date, see: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z", year, month, day, second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60, nanos / 1000)
+}
+
+
+/// Escapes a value for the text format of COPY.
+fn copy_text(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+impl Sink for PostgresSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.copy_rows(cpu, "jitter", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.copy_rows(cpu, measurement, events)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_taken_out_of_the_url() {
+        assert_eq!(split_password("postgres://jitter:s3cr%40t@db:5432/metrics?sslmode=require"),
+                   ("postgres://jitter@db:5432/metrics?sslmode=require".to_string(), Some("s3cr@t".to_string())));
+        assert_eq!(split_password("postgresql://db/metrics?user=jitter&password=s3cret&sslmode=require"),
+                   ("postgresql://db/metrics?user=jitter&sslmode=require".to_string(), Some("s3cret".to_string())));
+        assert_eq!(split_password("postgres://jitter@db/metrics"), ("postgres://jitter@db/metrics".to_string(), None));
+        assert_eq!(split_password("host=db dbname=metrics"), ("host=db dbname=metrics".to_string(), None));
+    }
+}
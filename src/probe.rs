@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use nix::libc;
+
+use crate::{cgroup::CgroupThrottlingProbe, cpufreq::FrequencyProbe, cstates::CStatesProbe, interrupts::{InterruptsProbe, TickProbe}, jitter::Field, perf::PerfProbe, stalls::StallsProbe, utils::{Mode, ProgramArgs}, virt::StealProbe};
+
+
+/// Source of extra per-interval metrics (interrupt counts, context switches, ...) published alongside the jitter max.
+/// Created and polled on the sampling thread, at report boundaries only, so it may be as slow as it needs to be.
+pub trait IntervalProbe {
+    /// Appends the fields describing the interval that just ended. Must append the same fields every time,
+    /// as file based outputs derive their columns from the first sample.
+    fn report(&mut self, fields: &mut Vec<Field>);
+}
+
+
+pub fn configure_probes(program_args: &ProgramArgs, cpu: u32) -> Vec<Box<dyn IntervalProbe>> {
+    let mut probes: Vec<Box<dyn IntervalProbe>> = Vec::default();
+
+    if program_args.interrupts_enabled {
+        match InterruptsProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track interrupts of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.mode == Mode::Tick {
+        match TickProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to count timer interrupts of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.context_switches_enabled {
+        probes.push(Box::new(ContextSwitchesProbe::new()));
+    }
+
+    if program_args.cpu_frequency_enabled {
+        match FrequencyProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track frequency of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.cstates_enabled {
+        match CStatesProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track idle states of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.steal_time_enabled {
+        match StealProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track steal time of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.cgroup_throttling_enabled {
+        match CgroupThrottlingProbe::new() {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track cgroup throttling for cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if !program_args.perf_counters.is_empty() {
+        match PerfProbe::new(&program_args.perf_counters) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to track hardware counters of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    if program_args.stall_attribution_enabled {
+        match StallsProbe::new(cpu) {
+            Ok(probe) => probes.push(Box::new(probe)),
+            Err(err) => log::error!(cpu = cpu, phase = "setup", error:% = err; "Unable to count stall events of cpu: {}: {}", cpu, err),
+        }
+    }
+
+    probes
+}
+
+
+/// Publishes how many times the sampling thread got preempted during each interval (`involuntary_context_switches`);
+/// a non-zero value means the scheduler, not the platform, is behind the interval's spike.
+pub struct ContextSwitchesProbe {
+    name: Arc<str>,
+    previous: i64,
+}
+
+impl ContextSwitchesProbe {
+    pub fn new() -> ContextSwitchesProbe {
+        ContextSwitchesProbe { name: Arc::from("involuntary_context_switches"), previous: involuntary_context_switches() }
+    }
+}
+
+impl Default for ContextSwitchesProbe {
+    fn default() -> ContextSwitchesProbe {
+        ContextSwitchesProbe::new()
+    }
+}
+
+impl IntervalProbe for ContextSwitchesProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        let current = involuntary_context_switches();
+        fields.push(Field { name: self.name.clone(), value: current - self.previous });
+        self.previous = current;
+    }
+}
+
+
+fn involuntary_context_switches() -> i64 {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    unsafe {
+        libc::getrusage(libc::RUSAGE_THREAD, usage.as_mut_ptr());
+        usage.assume_init().ru_nivcsw
+    }
+}
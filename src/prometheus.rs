@@ -0,0 +1,177 @@
+use std::{collections::HashMap, fmt::Write as _, io::{self, BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, sync::{Arc, atomic::{AtomicI64, AtomicU64, Ordering}}, thread, time::Duration};
+
+use log::{info, warn};
+
+use crate::{jitter::Jitter, observer::IntervalObserver, recorder, utils::ProgramArgs};
+
+// connections are served one at a time, a scraper that stalls holds up the others for at most this long
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const HISTOGRAM_BUCKETS_NANOS: [i64; 16] = [
+    500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000,
+    200_000, 500_000, 1_000_000, 2_000_000, 5_000_000, 10_000_000, 50_000_000, 100_000_000,
+];
+
+
+struct CpuMetrics {
+    last_max: AtomicI64,
+    run_max: AtomicI64,
+    /// One more than there are bounds, the last one counting the latencies above all of them.
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS_NANOS.len() + 1],
+    sum: AtomicI64,
+    /// Percentiles of the last completed interval, in the order of `PrometheusExporter::percentiles`.
+    percentiles: Vec<AtomicI64>,
+}
+
+impl CpuMetrics {
+    fn new(percentiles: usize) -> CpuMetrics {
+        CpuMetrics {
+            last_max: AtomicI64::new(0),
+            run_max: AtomicI64::new(0),
+            buckets: Default::default(),
+            sum: AtomicI64::new(0),
+            percentiles: (0..percentiles).map(|_| AtomicI64::new(0)).collect(),
+        }
+    }
+}
+
+
+/// Exposes per-cpu interval max jitter as Prometheus gauges and a histogram, served over plain HTTP while the sampler runs.
+/// With histograms enabled, the percentiles of every interval are exposed as gauges too.
+pub struct PrometheusExporter {
+    metrics: HashMap<u32, CpuMetrics>,
+    /// Field name and quantile label of every percentile below 100 the recorder reports.
+    percentiles: Vec<(String, String)>,
+}
+
+impl PrometheusExporter {
+    pub fn start(listen_address: &str, program_args: &ProgramArgs) -> io::Result<Arc<PrometheusExporter>> {
+        let listener = TcpListener::bind(listen_address)?;
+        let percentiles: Vec<(String, String)> = if program_args.histogram_enabled {
+            program_args.percentiles.iter()
+                .filter(|&&percentile| percentile < 100.0)
+                .map(|&percentile| {
+                    let quantile = format!("{:.8}", percentile / 100.0);
+                    (recorder::percentile_field_name(percentile), quantile.trim_end_matches('0').trim_end_matches('.').to_string())
+                })
+                .collect()
+        } else {
+            Vec::default()
+        };
+        let exporter = Arc::new(PrometheusExporter {
+            metrics: program_args.cpus.iter().map(|&cpu| (cpu, CpuMetrics::new(percentiles.len()))).collect(),
+            percentiles,
+        });
+
+        info!("Serving Prometheus metrics on: {}", listener.local_addr()?);
+        let server = Arc::clone(&exporter);
+        thread::Builder::new().name("prometheus".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                if let Err(err) = stream.and_then(|stream| server.serve(stream)) {
+                    warn!(phase = "publish", error:% = err; "Error while serving Prometheus scrape: {}", err);
+                }
+            }
+        })?;
+
+        Ok(exporter)
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+        let mut request_line = String::default();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        if path == "/metrics" || path.starts_with("/metrics?") {
+            let body = self.render();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+        } else {
+            write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut cpus: Vec<&u32> = self.metrics.keys().collect();
+        cpus.sort();
+
+        let mut body = String::default();
+        body.push_str("# HELP jitter_interval_max_nanoseconds Worst latency observed in the last completed reporting interval\n");
+        body.push_str("# TYPE jitter_interval_max_nanoseconds gauge\n");
+        for cpu in &cpus {
+            let _ = writeln!(body, "jitter_interval_max_nanoseconds{{cpu=\"{}\"}} {}", cpu, self.metrics[cpu].last_max.load(Ordering::Relaxed));
+        }
+
+        body.push_str("# HELP jitter_max_nanoseconds Worst latency observed since the start of the run\n");
+        body.push_str("# TYPE jitter_max_nanoseconds gauge\n");
+        for cpu in &cpus {
+            let _ = writeln!(body, "jitter_max_nanoseconds{{cpu=\"{}\"}} {}", cpu, self.metrics[cpu].run_max.load(Ordering::Relaxed));
+        }
+
+        if !self.percentiles.is_empty() {
+            body.push_str("# HELP jitter_interval_quantile_nanoseconds Percentiles of every latency of the last completed reporting interval\n");
+            body.push_str("# TYPE jitter_interval_quantile_nanoseconds gauge\n");
+            for cpu in &cpus {
+                for ((_, quantile), value) in self.percentiles.iter().zip(self.metrics[cpu].percentiles.iter()) {
+                    let _ = writeln!(body, "jitter_interval_quantile_nanoseconds{{cpu=\"{}\",quantile=\"{}\"}} {}", cpu, quantile, value.load(Ordering::Relaxed));
+                }
+            }
+        }
+
+        body.push_str("# HELP jitter_nanoseconds Distribution of per-interval worst latencies\n");
+        body.push_str("# TYPE jitter_nanoseconds histogram\n");
+        for cpu in &cpus {
+            let metrics = &self.metrics[cpu];
+            let mut cumulative = 0;
+            for (bucket, upper_bound) in metrics.buckets.iter().zip(HISTOGRAM_BUCKETS_NANOS.iter()) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(body, "jitter_nanoseconds_bucket{{cpu=\"{}\",le=\"{}\"}} {}", cpu, upper_bound, cumulative);
+            }
+            // +Inf and the count are derived from the same loads, so they never fall below a finite bucket of the scrape
+            let count = cumulative + metrics.buckets[HISTOGRAM_BUCKETS_NANOS.len()].load(Ordering::Relaxed);
+            let _ = writeln!(body, "jitter_nanoseconds_bucket{{cpu=\"{}\",le=\"+Inf\"}} {}", cpu, count);
+            let _ = writeln!(body, "jitter_nanoseconds_sum{{cpu=\"{}\"}} {}", cpu, metrics.sum.load(Ordering::Relaxed));
+            let _ = writeln!(body, "jitter_nanoseconds_count{{cpu=\"{}\"}} {}", cpu, count);
+        }
+
+        body
+    }
+}
+
+impl IntervalObserver for PrometheusExporter {
+    fn on_interval(&self, cpu: u32, sample: &Jitter) {
+        if let Some(metrics) = self.metrics.get(&cpu) {
+            metrics.last_max.store(sample.latency, Ordering::Relaxed);
+            metrics.run_max.fetch_max(sample.latency, Ordering::Relaxed);
+            let bucket = HISTOGRAM_BUCKETS_NANOS.iter().position(|&upper_bound| sample.latency <= upper_bound).unwrap_or(HISTOGRAM_BUCKETS_NANOS.len());
+            metrics.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            metrics.sum.fetch_add(sample.latency, Ordering::Relaxed);
+            for ((name, _), value) in self.percentiles.iter().zip(metrics.percentiles.iter()) {
+                if let Some(field) = sample.fields.iter().find(|field| *field.name == **name) {
+                    value.store(field.value, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_counts_latencies_above_every_bound() {
+        let exporter = PrometheusExporter { metrics: std::iter::once((0, CpuMetrics::new(0))).collect(), percentiles: Vec::new() };
+        for latency in [400, 3_000, 200_000_000] {
+            exporter.on_interval(0, &Jitter { ts: 0, latency, fields: Vec::new() });
+        }
+
+        let body = exporter.render();
+        assert!(body.contains("jitter_nanoseconds_bucket{cpu=\"0\",le=\"500\"} 1\n"), "{}", body);
+        assert!(body.contains("jitter_nanoseconds_bucket{cpu=\"0\",le=\"100000000\"} 2\n"), "{}", body);
+        assert!(body.contains("jitter_nanoseconds_bucket{cpu=\"0\",le=\"+Inf\"} 3\n"), "{}", body);
+        assert!(body.contains("jitter_nanoseconds_count{cpu=\"0\"} 3\n"), "{}", body);
+        assert!(body.contains("jitter_nanoseconds_sum{cpu=\"0\"} 200003400\n"), "{}", body);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use crossbeam::queue::ArrayQueue;
+use log::{error, info, warn};
+
+use crate::{jitter::{Jitter, CORE_TO_CORE_MEASUREMENT, DISCONTINUITY_MEASUREMENT, HISTOGRAM_BUCKETS_MEASUREMENT, OUTLIER_MEASUREMENT}, observer::IntervalObserver, sampler::CpuJitter, sink::{self, Sink}, systemd, utils};
+
+const MIN_QUEUE_CAPACITY: usize = 1024;
+
+
+struct CpuQueues {
+    samples: ArrayQueue<Jitter>,
+    events: ArrayQueue<(&'static str, Jitter)>,
+}
+
+
+/// Publishes samples to the sinks from a background thread while the run is still in progress.
+/// Sampler threads hand over every completed interval through a bounded lock-free queue per cpu.
+pub struct StreamingPublisher {
+    queues: HashMap<u32, CpuQueues>,
+    dropped: AtomicU64,
+    stopped: AtomicBool,
+    publisher_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl StreamingPublisher {
+    pub fn start(sinks: Vec<Box<dyn Sink>>, cpus: &[u32], flush_period: Duration, flush_intervals: usize) -> Arc<StreamingPublisher> {
+        let capacity = (flush_intervals * 4).max(MIN_QUEUE_CAPACITY);
+        let publisher = Arc::new(StreamingPublisher {
+            queues: cpus.iter().map(|&cpu| (cpu, CpuQueues { samples: ArrayQueue::new(capacity), events: ArrayQueue::new(capacity) })).collect(),
+            dropped: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+            publisher_thread: Mutex::new(None),
+        });
+
+        info!("Publishing results every {:?}", flush_period);
+        // pinging from here lets systemd restart the service should publishing ever hang
+        let watchdog_period = systemd::watchdog_interval().map(|interval| interval / 2);
+        if let Some(watchdog_period) = watchdog_period {
+            info!("Pinging the systemd watchdog every {:?}", watchdog_period);
+        }
+
+        let worker = Arc::clone(&publisher);
+        let handle = thread::Builder::new().name("publisher".to_string()).spawn(move || {
+            let mut next_flush = Instant::now() + flush_period;
+            while !worker.stopped.load(Ordering::Acquire) {
+                thread::park_timeout(watchdog_period.map_or(flush_period, |watchdog_period| watchdog_period.min(flush_period)));
+                if watchdog_period.is_some() {
+                    if let Err(err) = systemd::notify("WATCHDOG=1") {
+                        warn!(phase = "publish", error:% = err; "Unable to ping the systemd watchdog: {}", err);
+                    }
+                }
+                if Instant::now() < next_flush && !worker.stopped.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                if utils::take_reopen_request() {
+                    reopen_all(&sinks);
+                }
+                worker.flush(&sinks);
+                next_flush = Instant::now() + flush_period;
+            }
+            // the sampler threads are gone by now, this picks up whatever they handed over during the last flush
+            worker.flush(&sinks);
+        }).expect("Unable to start publisher thread");
+
+        *publisher.publisher_thread.lock().unwrap() = Some(handle);
+        publisher
+    }
+
+    /// Stops the background publisher after it has flushed everything handed over so far.
+    pub fn finish(&self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(handle) = self.publisher_thread.lock().unwrap().take() {
+            handle.thread().unpark();
+            handle.join().expect("Publisher thread panicked");
+        }
+    }
+
+    fn flush(&self, sinks: &[Box<dyn Sink>]) {
+        let mut cpus: Vec<&u32> = self.queues.keys().collect();
+        cpus.sort();
+
+        let results: Vec<CpuJitter> = cpus.into_iter()
+            .map(|&cpu| {
+                let queues = &self.queues[&cpu];
+                let mut result = CpuJitter { cpu, samples: std::iter::from_fn(|| queues.samples.pop()).collect(), outliers: Vec::new(), top_latencies: Vec::new(), discontinuities: Vec::new(), round_trips: Vec::new(), histogram_buckets: Vec::new(), histogram: None };
+                while let Some((measurement, event)) = queues.events.pop() {
+                    match measurement {
+                        OUTLIER_MEASUREMENT => result.outliers.push(event),
+                        DISCONTINUITY_MEASUREMENT => result.discontinuities.push(event),
+                        CORE_TO_CORE_MEASUREMENT => result.round_trips.push(event),
+                        HISTOGRAM_BUCKETS_MEASUREMENT => result.histogram_buckets.push(event),
+                        _ => result.top_latencies.push(event),
+                    }
+                }
+                result
+            })
+            .collect();
+        sink::publish_all(sinks, &results);
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(phase = "publish"; "Publisher fell behind, dropped {} samples", dropped);
+        }
+    }
+}
+
+
+fn reopen_all(sinks: &[Box<dyn Sink>]) {
+    info!("Reopening outputs");
+    for sink in sinks {
+        if let Err(err) = sink.reopen() {
+            error!(phase = "publish", error:% = err; "Unable to reopen output: {}", err);
+        }
+    }
+}
+
+
+impl IntervalObserver for StreamingPublisher {
+    fn on_interval(&self, cpu: u32, sample: &Jitter) {
+        if let Some(queues) = self.queues.get(&cpu) {
+            if queues.samples.push(sample.clone()).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn on_events(&self, cpu: u32, measurement: &'static str, events: &[Jitter]) {
+        if let Some(queues) = self.queues.get(&cpu) {
+            for event in events {
+                if queues.events.push((measurement, event.clone())).is_err() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::mpsc::{self, Receiver, Sender}};
+
+    use super::*;
+
+    /// Holds up the first publish until released, recording the latencies of every sample published.
+    struct BlockingSink {
+        entered: Mutex<Sender<()>>,
+        release: Mutex<Receiver<()>>,
+        published: Arc<Mutex<Vec<i64>>>,
+    }
+
+    impl Sink for BlockingSink {
+        fn publish(&self, _cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+            if self.entered.lock().unwrap().send(()).is_ok() {
+                let _ = self.release.lock().unwrap().recv();
+            }
+            self.published.lock().unwrap().extend(samples.iter().map(|sample| sample.latency));
+            Ok(())
+        }
+
+        fn publish_events(&self, _cpu: u32, _measurement: &str, _events: &[Jitter]) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn samples_handed_over_during_the_last_flush_are_published() {
+        let (entered_sender, entered) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = BlockingSink { entered: Mutex::new(entered_sender), release: Mutex::new(release_receiver), published: Arc::clone(&published) };
+        let publisher = StreamingPublisher::start(vec![Box::new(sink)], &[0], Duration::from_millis(1), 1);
+
+        entered.recv().unwrap();
+        drop(entered);
+        publisher.on_interval(0, &Jitter { ts: 1, latency: 42, fields: Vec::new() });
+        publisher.stopped.store(true, Ordering::Release);
+        release.send(()).unwrap();
+        publisher.finish();
+
+        assert_eq!(*published.lock().unwrap(), vec![42]);
+    }
+}
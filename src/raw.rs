@@ -0,0 +1,314 @@
+use std::{fs::File, io::{self, BufRead, BufReader, BufWriter, Read, Write}};
+
+use crate::{jitter::Jitter, parquet::ParquetWriter, sampler::CpuJitter, utils::{Clock, RawFormat}};
+
+pub const RAW_MAGIC: &[u8; 8] = b"JITTRAW1";
+pub const RESYNC_MARKER: u32 = u32::MAX;
+const CSV_HEADER: &str = "timestamp,cpu,latency";
+const BUFFER_CAPACITY: usize = 1 << 20;
+// room left for the resync markers written between two deltas (after reporting, after an interrupt window, ...)
+const BUFFER_HEADROOM: usize = 16;
+
+
+/// Records every single loop delta of one cpu into a compact binary file.
+///
+/// Layout (little endian): magic, cpu (u32), clock source name (u8 length + bytes), tsc frequency in GHz (f64),
+/// followed by one u32 delta in nanoseconds per loop iteration. Deltas are saturated below `RESYNC_MARKER`; the marker
+/// itself is followed by an i64 timestamp the next delta is measured from. One is written at the start of the run and
+/// whenever the sampler had to step out of the measured path (interval reporting, flushing this buffer).
+///
+/// Alternatively writes a csv file or a Parquet file with one row (ts, cpu, latency) per loop iteration, Parquet with one row group per flush.
+pub struct RawRecorder {
+    writer: RawWriter,
+    buffer: Vec<u32>,
+    clock: Clock,
+    cpu: u32,
+    // timestamp (ns) the next delta ends at, for csv and Parquet rows
+    ts: i64,
+}
+
+enum RawWriter {
+    Binary(BufWriter<File>),
+    Csv(BufWriter<File>),
+    Parquet(ParquetWriter),
+}
+
+impl RawRecorder {
+    pub fn create(path: &str, cpu: u32, clock: &Clock, format: RawFormat) -> io::Result<RawRecorder> {
+        let writer = match format {
+            RawFormat::Binary => {
+                let name = clock.source().name();
+                let mut writer = BufWriter::new(File::create(path)?);
+                writer.write_all(RAW_MAGIC)?;
+                writer.write_all(&cpu.to_le_bytes())?;
+                writer.write_all(&[name.len() as u8])?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(&clock.frequency().to_le_bytes())?;
+                RawWriter::Binary(writer)
+            },
+            RawFormat::Csv => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                writeln!(writer, "{}", CSV_HEADER)?;
+                RawWriter::Csv(writer)
+            },
+            RawFormat::Parquet => RawWriter::Parquet(ParquetWriter::create(path)?),
+        };
+
+        // fill with non-zero values so that every page gets faulted in now rather than in the measured path
+        let mut buffer = vec![RESYNC_MARKER; BUFFER_CAPACITY];
+        buffer.clear();
+
+        Ok(RawRecorder { writer, buffer, clock: *clock, cpu, ts: 0 })
+    }
+
+    /// Buffers the delta as read from the clock, in ticks for cycle counters; converting it to nanoseconds
+    /// is left to `flush()`. Returns true once the buffer is full and has to be flushed before recording any more deltas.
+    #[inline(always)]
+    pub fn record(&mut self, latency: i64) -> bool {
+        let len = self.buffer.len();
+        let delta = latency.clamp(0, RESYNC_MARKER as i64 - 1) as u32;
+        if len >= BUFFER_CAPACITY - BUFFER_HEADROOM {
+            self.record_past_limit(delta);
+            return true;
+        }
+        // SAFETY: len is below the headroom limit, so below the capacity allocated up front
+        unsafe {
+            self.buffer.as_mut_ptr().add(len).write(delta);
+            self.buffer.set_len(len + 1);
+        }
+        len + 1 >= BUFFER_CAPACITY - BUFFER_HEADROOM
+    }
+
+    // only reached when a caller kept recording without flushing a full buffer
+    #[cold]
+    #[inline(never)]
+    fn record_past_limit(&mut self, delta: u32) {
+        self.buffer.push(delta);
+    }
+
+    /// Deltas recorded since the last flush.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// `ts` is a raw reading of the clock.
+    pub fn resync(&mut self, ts: i64) {
+        self.buffer.push(RESYNC_MARKER);
+        self.buffer.push(ts as u32);
+        self.buffer.push((ts >> 32) as u32);
+    }
+
+    /// Writes out the buffered deltas. The buffer gets emptied even when writing fails, dropping them, so that it
+    /// never outgrows its capacity; the file picks up again at the next resync marker.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let written = self.write_buffer();
+        self.buffer.clear();
+        written
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            RawWriter::Binary(writer) => write_binary(writer, &self.buffer, &self.clock)?,
+            RawWriter::Csv(writer) => {
+                let (ts, latency) = decode(&self.buffer, &self.clock, &mut self.ts);
+                for (ts, latency) in ts.iter().zip(latency.iter()) {
+                    writeln!(writer, "{},{},{}", ts, self.cpu, latency)?;
+                }
+            },
+            RawWriter::Parquet(writer) => {
+                let (ts, latency) = decode(&self.buffer, &self.clock, &mut self.ts);
+                writer.write_row_group(self.cpu, &ts, &latency)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Flushes what is left and, for Parquet, writes the footer that makes the file readable.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        match self.writer {
+            RawWriter::Binary(mut writer) | RawWriter::Csv(mut writer) => writer.flush(),
+            RawWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+
+/// Timestamps and latencies in nanoseconds of the buffered deltas, `ts` carrying the timestamp over from one flush to the next.
+fn decode(buffer: &[u32], clock: &Clock, ts: &mut i64) -> (Vec<i64>, Vec<i64>) {
+    let mut timestamps = Vec::with_capacity(buffer.len());
+    let mut latencies = Vec::with_capacity(buffer.len());
+    let mut idx = 0;
+    while idx < buffer.len() {
+        if buffer[idx] == RESYNC_MARKER {
+            *ts = clock.timestamp((buffer[idx + 2] as i64) << 32 | buffer[idx + 1] as i64);
+            idx += 3;
+        } else {
+            let delta = clock.ticks_to_nanos(buffer[idx] as i64);
+            *ts += delta;
+            timestamps.push(*ts);
+            latencies.push(delta);
+            idx += 1;
+        }
+    }
+
+    (timestamps, latencies)
+}
+
+
+fn write_binary(writer: &mut BufWriter<File>, buffer: &[u32], clock: &Clock) -> io::Result<()> {
+    let mut idx = 0;
+    while idx < buffer.len() {
+        let delta = buffer[idx];
+        if delta == RESYNC_MARKER {
+            let ticks = (buffer[idx + 2] as i64) << 32 | buffer[idx + 1] as i64;
+            let ts = clock.timestamp(ticks);
+            writer.write_all(&RESYNC_MARKER.to_le_bytes())?;
+            writer.write_all(&(ts as u32).to_le_bytes())?;
+            writer.write_all(&((ts >> 32) as u32).to_le_bytes())?;
+            idx += 3;
+        } else {
+            let delta = clock.ticks_to_nanos(delta as i64).clamp(0, RESYNC_MARKER as i64 - 1) as u32;
+            writer.write_all(&delta.to_le_bytes())?;
+            idx += 1;
+        }
+    }
+
+    writer.flush()
+}
+
+
+pub fn raw_output_path(path: &str, cpu: u32, format: RawFormat) -> String {
+    match format {
+        RawFormat::Binary => format!("{}.cpu{}", path, cpu),
+        RawFormat::Csv => format!("{}.cpu{}.csv", path, cpu),
+        RawFormat::Parquet => format!("{}.cpu{}.parquet", path, cpu),
+    }
+}
+
+
+/// Rebuilds the samples of a binary or csv raw sample file: the worst latency of every report interval, the way a live run reports it.
+pub fn replay(path: &str, report_interval_micros: i64) -> io::Result<CpuJitter> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::InvalidData, "not a raw sample file"),
+        _ => err,
+    })?;
+    let mut maxima = IntervalMaxima::new(report_interval_micros * 1_000);
+    let cpu = if &magic == RAW_MAGIC {
+        replay_binary(reader, &mut maxima)?
+    } else if CSV_HEADER.as_bytes().starts_with(&magic) {
+        let mut header = String::from_utf8_lossy(&magic).into_owned();
+        reader.read_line(&mut header)?;
+        if header.trim_end() != CSV_HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a raw sample file"));
+        }
+        replay_csv(reader, &mut maxima)?
+    } else if magic.starts_with(b"PAR1") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Parquet raw sample files are not replayable, read them with a Parquet reader instead"));
+    } else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a raw sample file"));
+    };
+
+    Ok(CpuJitter { cpu, samples: maxima.finish(), outliers: Vec::new(), top_latencies: Vec::new(), discontinuities: Vec::new(), round_trips: Vec::new(), histogram_buckets: Vec::new(), histogram: None })
+}
+
+
+fn replay_binary(mut reader: impl Read, maxima: &mut IntervalMaxima) -> io::Result<u32> {
+    let cpu = read_u32(&mut reader)?;
+    let mut name_len = [0u8; 1];
+    reader.read_exact(&mut name_len)?;
+    // clock source name and frequency are informational only, deltas are already in nanoseconds
+    let mut clock_info = vec![0u8; name_len[0] as usize + 8];
+    reader.read_exact(&mut clock_info)?;
+
+    let mut ts = 0;
+    loop {
+        let delta = match read_u32(&mut reader) {
+            Ok(delta) => delta,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+
+        if delta == RESYNC_MARKER {
+            let lower = read_u32(&mut reader)? as i64;
+            let upper = read_u32(&mut reader)? as i64;
+            ts = upper << 32 | lower;
+            maxima.start(ts);
+            continue;
+        }
+
+        ts += delta as i64;
+        maxima.record(ts, delta as i64);
+    }
+
+    Ok(cpu)
+}
+
+
+fn replay_csv(reader: impl BufRead, maxima: &mut IntervalMaxima) -> io::Result<u32> {
+    let mut cpu = None;
+    for line in reader.lines() {
+        let line = line?;
+        let columns: Vec<i64> = line.split(',').filter_map(|column| column.trim().parse().ok()).collect();
+        let (ts, row_cpu, latency) = match columns[..] {
+            [ts, cpu, latency] => (ts, cpu as u32, latency),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid raw sample row: {}", line))),
+        };
+        if *cpu.get_or_insert(row_cpu) != row_cpu {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Raw sample file mixes cpu {} and cpu {}", cpu.unwrap(), row_cpu)));
+        }
+        maxima.start(ts - latency);
+        maxima.record(ts, latency);
+    }
+
+    cpu.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no raw samples"))
+}
+
+
+/// Worst latency of every report interval, intervals starting from the first timestamp of the file.
+struct IntervalMaxima {
+    report_interval: i64,
+    next_report: i64,
+    max: i64,
+    samples: Vec<Jitter>,
+    ts: i64,
+}
+
+impl IntervalMaxima {
+    fn new(report_interval: i64) -> IntervalMaxima {
+        IntervalMaxima { report_interval, next_report: i64::MAX, max: i64::MIN, samples: Vec::new(), ts: 0 }
+    }
+
+    fn start(&mut self, ts: i64) {
+        if self.next_report == i64::MAX {
+            self.next_report = ts + self.report_interval;
+        }
+    }
+
+    fn record(&mut self, ts: i64, latency: i64) {
+        self.ts = ts;
+        self.max = self.max.max(latency);
+        if ts > self.next_report {
+            self.samples.push(Jitter { ts, latency: self.max, fields: Vec::new() });
+            self.next_report = ts + self.report_interval;
+            self.max = i64::MIN;
+        }
+    }
+
+    fn finish(mut self) -> Vec<Jitter> {
+        if self.max > i64::MIN {
+            self.samples.push(Jitter { ts: self.ts, latency: self.max, fields: Vec::new() });
+        }
+        self.samples
+    }
+}
+
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use hdrhistogram::Histogram;
+use log::{error, info};
+
+use crate::{ftrace::Ftrace, jitter::{Field, Jitter, DISCONTINUITY_MEASUREMENT, HISTOGRAM_BUCKETS_MEASUREMENT, OUTLIER_MEASUREMENT, TOP_LATENCIES_MEASUREMENT}, observer::IntervalObserver, probe::IntervalProbe, raw::RawRecorder, sampler::CpuJitter, topn::TopLatencies, utils::{Clock, ProgramArgs, NANOS_IN_SEC}};
+
+const MAX_TRACE_DUMPS_PER_CPU: usize = 16;
+
+
+/// Accumulates individual latencies measured by any of the sampling modes and turns them into per-interval reports.
+/// `record()` sits in the measured path; everything else is only meant to be called outside of it.
+/// Latencies and timestamps are handed over as raw readings of `clock` and only converted when reported.
+pub struct IntervalRecorder<'a> {
+    program_args: &'a ProgramArgs,
+    observers: &'a [Arc<dyn IntervalObserver>],
+    result: &'a mut CpuJitter,
+    raw_recorder: Option<RawRecorder>,
+    probes: Vec<Box<dyn IntervalProbe>>,
+    tracer: Option<&'a Ftrace>,
+    traced_outlier: Option<i64>,
+    trace_dumps: usize,
+    clock: Clock,
+    clock_overhead: Option<i64>,
+    latency_correction: i64,
+    clock_overhead_field: Arc<str>,
+    max: i64,
+    idx: usize,
+    iterations: i64,
+    // raw clock reading of the last report, the first interval is taken to be as long as configured
+    last_report: Option<i64>,
+    iterations_fields: [Arc<str>; 2],
+    outlier_threshold: i64,
+    reported_outliers: usize,
+    discontinuity_threshold: i64,
+    reported_discontinuities: usize,
+    worst: Option<TopLatencies>,
+    histogram: Option<Histogram<u64>>,
+    percentile_names: Vec<Arc<str>>,
+    // upper bounds in nanos of the published buckets, and the latencies of the interval counted into them
+    bucket_bounds: Vec<i64>,
+    bucket_counts: Vec<u64>,
+    count_field: Arc<str>,
+    rank_field: Arc<str>,
+}
+
+impl<'a> IntervalRecorder<'a> {
+    pub fn new(program_args: &'a ProgramArgs, observers: &'a [Arc<dyn IntervalObserver>], result: &'a mut CpuJitter, raw_recorder: Option<RawRecorder>, probes: Vec<Box<dyn IntervalProbe>>, clock: &Clock, clock_overhead: Option<i64>) -> IntervalRecorder<'a> {
+        let histogram = if program_args.histogram_enabled {
+            let digits = program_args.histogram_significant_digits;
+            let max_trackable_ticks = clock.nanos_to_ticks(program_args.histogram_max_nanos).max(2) as u64;
+            result.histogram = Some(Histogram::<u64>::new_with_bounds(1, program_args.histogram_max_nanos as u64, digits).expect("Unable to create latency histogram"));
+            Some(Histogram::<u64>::new_with_bounds(1, max_trackable_ticks, digits).expect("Unable to create latency histogram"))
+        } else {
+            None
+        };
+        let bucket_bounds = if program_args.histogram_buckets_enabled { log_linear_bounds(program_args.histogram_max_nanos) } else { Vec::new() };
+
+        IntervalRecorder {
+            program_args,
+            observers,
+            result,
+            raw_recorder,
+            probes,
+            tracer: None,
+            traced_outlier: None,
+            trace_dumps: 0,
+            clock: *clock,
+            clock_overhead,
+            latency_correction: clock.nanos_to_ticks(clock_overhead.unwrap_or(0)),
+            clock_overhead_field: Arc::from("clock_overhead"),
+            max: i64::MIN,
+            idx: 0,
+            iterations: 0,
+            last_report: None,
+            iterations_fields: [Arc::from("iterations"), Arc::from("iterations_per_sec")],
+            outlier_threshold: program_args.outlier_threshold_nanos.map_or(i64::MAX, |threshold| clock.nanos_to_ticks(threshold)),
+            reported_outliers: 0,
+            discontinuity_threshold: clock.nanos_to_ticks(program_args.discontinuity_threshold_nanos),
+            reported_discontinuities: 0,
+            worst: if program_args.top_latencies > 0 { Some(TopLatencies::new(program_args.top_latencies)) } else { None },
+            histogram,
+            percentile_names: program_args.percentiles.iter().map(|&percentile| Arc::from(percentile_field_name(percentile))).collect(),
+            rank_field: Arc::from("rank"),
+            bucket_counts: vec![0; bucket_bounds.len()],
+            bucket_bounds,
+            count_field: Arc::from("count"),
+        }
+    }
+
+    /// Freezes the trace whenever an outlier is recorded and dumps it at the next report.
+    pub fn with_tracer(mut self, tracer: Option<&'a Ftrace>) -> IntervalRecorder<'a> {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Returns true if recording had to step out of the measured path (flushing raw samples, freezing the trace),
+    /// in which case the caller should re-read its clock and `resync()`.
+    #[inline(always)]
+    pub fn record(&mut self, latency: i64, now: i64) -> bool {
+        self.iterations += 1;
+        if latency < 0 || latency > self.discontinuity_threshold {
+            self.record_discontinuity(latency, now);
+            // the raw file resyncs to the stepped clock rather than keeping the delta
+            return true;
+        }
+        let latency = latency - self.latency_correction;
+        let mut stepped_out = false;
+        self.max = self.max.max(latency);
+        if latency > self.outlier_threshold {
+            stepped_out = self.record_outlier(latency, now);
+        }
+        if let Some(worst) = self.worst.as_mut() {
+            worst.record(latency, now);
+        }
+        if let Some(histogram) = self.histogram.as_mut() {
+            histogram.saturating_record(latency.max(0) as u64);
+        }
+        if let Some(raw_recorder) = self.raw_recorder.as_mut() {
+            if raw_recorder.record(latency) {
+                self.flush_raw(now);
+                return true;
+            }
+        }
+
+        stepped_out
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn record_outlier(&mut self, latency: i64, now: i64) -> bool {
+        if self.result.outliers.len() < self.result.outliers.capacity() {
+            self.result.outliers.push(Jitter { ts: self.clock.timestamp(now), latency: self.clock.ticks_to_nanos(latency), fields: Vec::new() });
+        }
+        if let Some(tracer) = self.tracer {
+            if self.traced_outlier.is_none() && self.trace_dumps < MAX_TRACE_DUMPS_PER_CPU {
+                tracer.freeze();
+                self.traced_outlier = Some(self.clock.timestamp(now));
+                return true;
+            }
+        }
+
+        false
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn record_discontinuity(&mut self, latency: i64, now: i64) {
+        let discontinuities = &mut self.result.discontinuities;
+        if discontinuities.len() < discontinuities.capacity() {
+            discontinuities.push(Jitter { ts: self.clock.timestamp(now), latency: self.clock.ticks_to_nanos(latency), fields: Vec::new() });
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn flush_raw(&mut self, now: i64) {
+        if let Some(raw_recorder) = self.raw_recorder.as_mut() {
+            let buffered = raw_recorder.buffered();
+            if let Err(err) = raw_recorder.flush() {
+                error!(cpu = self.result.cpu, phase = "record", error:% = err; "Unable to write raw samples of cpu: {}, dropped {} of them: {}", self.result.cpu, buffered, err);
+                // the raw file has a gap from here on, have it show up next to the clock steps
+                let discontinuities = &mut self.result.discontinuities;
+                if discontinuities.len() < discontinuities.capacity() {
+                    discontinuities.push(Jitter { ts: self.clock.timestamp(now), latency: 0, fields: vec![Field { name: Arc::from("raw_samples_dropped"), value: buffered as i64 }] });
+                }
+            }
+        }
+    }
+
+    /// Marks the point (a raw clock reading) the next recorded latency is measured from after stepping out of the measured path.
+    pub fn resync(&mut self, now: i64) {
+        if let Some(raw_recorder) = self.raw_recorder.as_mut() {
+            raw_recorder.resync(now);
+        }
+    }
+
+    /// Publishes the interval ending at `now`, unless nothing got recorded in it (eg: wakeups less frequent than reports);
+    /// only intervals that recorded a latency get a sample, events and probe readings carry over to the next one.
+    #[cold]
+    #[inline(never)]
+    pub fn report(&mut self, now: i64) {
+        if self.max == i64::MIN {
+            return;
+        }
+        let clock = self.clock;
+        let max = clock.ticks_to_nanos(self.max);
+        let cpu = self.result.cpu;
+        let CpuJitter { samples, outliers, top_latencies, discontinuities, histogram_buckets, histogram: run_histogram, .. } = &mut *self.result;
+
+        // samples are a rolling buffer when sampling until stopped, a run of fixed duration that drifted past
+        // the intervals it expected grows its buffer instead of overwriting its first intervals
+        if self.program_args.duration_seconds != 0 && self.idx >= samples.len() {
+            samples.push(Jitter::default());
+        }
+        let slot = self.idx % samples.len();
+        let sample = &mut samples[slot];
+        sample.ts = clock.timestamp(now);
+        sample.latency = max;
+        // the slot may hold an interval from before the rolling buffer wrapped around
+        sample.fields.clear();
+        if let Some(histogram) = self.histogram.as_mut() {
+            sample.fields.extend(self.percentile_names.iter().zip(self.program_args.percentiles.iter())
+                .map(|(name, &percentile)| {
+                    let value = if percentile >= 100.0 { max } else { clock.ticks_to_nanos(histogram.value_at_percentile(percentile) as i64) };
+                    Field { name: name.clone(), value }
+                }));
+            if let Some(run_histogram) = run_histogram.as_mut() {
+                for value in histogram.iter_recorded() {
+                    run_histogram.saturating_record_n(clock.ticks_to_nanos(value.value_iterated_to() as i64).max(1) as u64, value.count_at_value());
+                }
+            }
+            if !self.bucket_bounds.is_empty() {
+                for value in histogram.iter_recorded() {
+                    let nanos = clock.ticks_to_nanos(value.value_iterated_to() as i64);
+                    let bucket = self.bucket_bounds.partition_point(|&bound| bound < nanos).min(self.bucket_bounds.len() - 1);
+                    self.bucket_counts[bucket] += value.count_at_value();
+                }
+            }
+            histogram.reset();
+        }
+        if self.program_args.iterations_enabled {
+            let elapsed = self.last_report.map_or(self.program_args.report_interval_micros * 1_000, |last_report| clock.ticks_to_nanos(now - last_report));
+            let per_sec = if elapsed > 0 { (self.iterations as f64 * NANOS_IN_SEC as f64 / elapsed as f64) as i64 } else { 0 };
+            sample.fields.push(Field { name: self.iterations_fields[0].clone(), value: self.iterations });
+            sample.fields.push(Field { name: self.iterations_fields[1].clone(), value: per_sec });
+        }
+        if let Some(clock_overhead) = self.clock_overhead {
+            sample.fields.push(Field { name: self.clock_overhead_field.clone(), value: clock_overhead });
+        }
+        for probe in self.probes.iter_mut() {
+            probe.report(&mut sample.fields);
+        }
+        if let (Some(tracer), Some(outlier_ts)) = (self.tracer, self.traced_outlier.take()) {
+            let path = format!("{}.trace.cpu{}.{}", self.program_args.output_path.as_deref().unwrap_or("jitter"), cpu, outlier_ts);
+            match tracer.dump(cpu, &path) {
+                Ok(()) => info!("Saved trace of outlier on cpu: {} to: {}", cpu, path),
+                Err(err) => error!(cpu = cpu, phase = "trace", error:% = err; "Unable to save trace of outlier on cpu: {} to: {}: {}", cpu, path, err),
+            }
+            self.trace_dumps += 1;
+        }
+
+        let reported_histogram_buckets = histogram_buckets.len();
+        for (&bound, count) in self.bucket_bounds.iter().zip(self.bucket_counts.iter_mut()).filter(|(_, count)| **count > 0) {
+            histogram_buckets.push(Jitter { ts: sample.ts, latency: bound, fields: vec![Field { name: self.count_field.clone(), value: *count as i64 }] });
+            *count = 0;
+        }
+
+        let reported_top_latencies = top_latencies.len();
+        if let Some(worst) = self.worst.as_mut() {
+            let rank_field = &self.rank_field;
+            worst.drain(|rank, latency, ts| {
+                top_latencies.push(Jitter { ts: clock.timestamp(ts), latency: clock.ticks_to_nanos(latency), fields: vec![Field { name: rank_field.clone(), value: rank as i64 }] });
+            });
+        }
+
+        for observer in self.observers {
+            observer.on_interval(cpu, sample);
+            if outliers.len() > self.reported_outliers {
+                observer.on_events(cpu, OUTLIER_MEASUREMENT, &outliers[self.reported_outliers..]);
+            }
+            if top_latencies.len() > reported_top_latencies {
+                observer.on_events(cpu, TOP_LATENCIES_MEASUREMENT, &top_latencies[reported_top_latencies..]);
+            }
+            if histogram_buckets.len() > reported_histogram_buckets {
+                observer.on_events(cpu, HISTOGRAM_BUCKETS_MEASUREMENT, &histogram_buckets[reported_histogram_buckets..]);
+            }
+            if discontinuities.len() > self.reported_discontinuities {
+                observer.on_events(cpu, DISCONTINUITY_MEASUREMENT, &discontinuities[self.reported_discontinuities..]);
+            }
+        }
+
+        if self.program_args.duration_seconds == 0 {
+            // nothing but the observers gets to see them when sampling until stopped
+            outliers.clear();
+            top_latencies.clear();
+            histogram_buckets.clear();
+            discontinuities.clear();
+        }
+        self.reported_outliers = outliers.len();
+        self.reported_discontinuities = discontinuities.len();
+        self.max = i64::MIN;
+        self.iterations = 0;
+        self.last_report = Some(now);
+        self.idx += 1;
+    }
+
+    /// Drops the slots of intervals that never got reported, e.g. when the run was stopped early,
+    /// and puts the intervals of a rolling buffer that wrapped around back into order.
+    pub fn finish(&mut self) {
+        let samples = &mut self.result.samples;
+        if self.program_args.duration_seconds == 0 && self.idx > samples.len() {
+            let oldest = self.idx % samples.len();
+            samples.rotate_left(oldest);
+        } else {
+            samples.truncate(self.idx);
+        }
+        if let Some(raw_recorder) = self.raw_recorder.take() {
+            if let Err(err) = raw_recorder.finish() {
+                error!(cpu = self.result.cpu, phase = "record", error:% = err; "Unable to write raw samples of cpu: {}: {}", self.result.cpu, err);
+            }
+        }
+    }
+}
+
+
+/// 1, 2, .. 9, 10, 20, .. 90, 100, 200, .. up to and including `max`: a fixed number of buckets per decade,
+/// so that heatmaps keep the same relative resolution from nanoseconds to seconds.
+fn log_linear_bounds(max: i64) -> Vec<i64> {
+    let mut bounds = Vec::new();
+    let mut decade = 1;
+    while bounds.last().is_none_or(|&bound| bound < max) {
+        bounds.extend((1..10).map(|step| step * decade).take_while(|&bound| bound < max));
+        if decade > max / 10 {
+            bounds.push(max);
+        }
+        decade *= 10;
+    }
+
+    bounds
+}
+
+
+pub(crate) fn percentile_field_name(percentile: f64) -> String {
+    if percentile >= 100.0 {
+        "jitter_max".to_string()
+    } else {
+        format!("jitter_p{}", percentile)
+    }
+}
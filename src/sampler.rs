@@ -0,0 +1,243 @@
+use std::{any::Any, sync::{Arc, Condvar, Mutex}};
+
+use hdrhistogram::Histogram;
+use log::{info, warn};
+
+use crate::{cgroup, cpufreq::FrequencyTuning, cpulist, interrupts::MovedIrqs, error::{self, JitterError}, ftrace::Ftrace, jitter::{self, Jitter, capture_jitter}, observer::IntervalObserver, smt::OfflineSiblings, pingpong, utils::{self, InterruptMode, MlockMode, Mode, ProgramArgs}, virt};
+
+
+#[derive(Debug, Clone)]
+pub struct CpuJitter {
+    pub cpu: u32,
+    pub samples: Vec<Jitter>,
+    pub outliers: Vec<Jitter>,
+    pub top_latencies: Vec<Jitter>,
+    /// Clock steps (NTP, suspend/resume, ...) seen as negative or implausibly long deltas, kept out of the latencies.
+    pub discontinuities: Vec<Jitter>,
+    /// Per interval round trips to the peer cpu in ping-pong mode.
+    pub round_trips: Vec<Jitter>,
+    /// Per interval latency counts of the non-empty histogram buckets, with the bucket upper bound as latency.
+    pub histogram_buckets: Vec<Jitter>,
+    /// Every latency of the run in nanoseconds, when histograms are enabled.
+    pub histogram: Option<Histogram<u64>>,
+}
+
+
+/// Holds the sampler threads back once their privileged setup is over, until the process has switched to the
+/// unprivileged user, so that none of them measures (or publishes) as root.
+pub struct SetupLatch {
+    // threads yet to arrive, and whether they may go on
+    state: Mutex<(usize, bool)>,
+    changed: Condvar,
+}
+
+impl SetupLatch {
+    fn new(threads: usize) -> SetupLatch {
+        SetupLatch { state: Mutex::new((threads, false)), changed: Condvar::new() }
+    }
+
+    fn arrive(&self) {
+        self.state.lock().unwrap().0 -= 1;
+        self.changed.notify_all();
+    }
+
+    fn wait_for_arrivals(&self) {
+        let _state = self.changed.wait_while(self.state.lock().unwrap(), |(pending, _)| *pending > 0).unwrap();
+    }
+
+    fn release(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+}
+
+
+/// Arrival of one sampler thread at the `SetupLatch`. A thread that fails its setup arrives when this is dropped,
+/// without waiting for the others.
+pub struct SetupArrival<'a> {
+    latch: Option<&'a SetupLatch>,
+}
+
+impl<'a> SetupArrival<'a> {
+    pub fn new(latch: Option<&'a SetupLatch>) -> SetupArrival<'a> {
+        SetupArrival { latch }
+    }
+
+    pub fn wait(mut self) {
+        if let Some(latch) = self.latch.take() {
+            latch.arrive();
+            let _state = latch.changed.wait_while(latch.state.lock().unwrap(), |(_, released)| !*released).unwrap();
+        }
+    }
+}
+
+impl Drop for SetupArrival<'_> {
+    fn drop(&mut self) {
+        if let Some(latch) = self.latch.take() {
+            latch.arrive();
+        }
+    }
+}
+
+
+/// Entry point for embedding the jitter sampler: configure it with `ProgramArgs`,
+/// `run()` it and get back the samples captured on every requested cpu.
+pub struct Sampler {
+    program_args: ProgramArgs,
+    observers: Vec<Arc<dyn IntervalObserver>>,
+}
+
+impl Sampler {
+    pub fn new(program_args: ProgramArgs) -> Sampler {
+        Sampler { program_args, observers: Vec::default() }
+    }
+
+    pub fn with_observers(mut self, observers: Vec<Arc<dyn IntervalObserver>>) -> Sampler {
+        self.observers.extend(observers);
+        self
+    }
+
+    pub fn program_args(&self) -> &ProgramArgs {
+        &self.program_args
+    }
+
+    /// Samples every requested cpu on its own thread. Failing to set up the run as a whole is an error, while a cpu
+    /// that fails (or whose thread panics) only gets its own entry set to the error and the others carry on.
+    pub fn run(&self) -> error::Result<Vec<error::Result<CpuJitter>>> {
+        let args = &self.program_args;
+        let observers = &self.observers;
+
+        if args.mode == Mode::PingPong && args.cpus.len() != 2 {
+            return Err(JitterError::PingPongCpus(args.cpus.len()));
+        }
+
+        let offline_siblings = if args.offline_siblings { Some(OfflineSiblings::offline(&args.cpus)?) } else { None };
+        let frequency_tuning = FrequencyTuning::apply(&args.cpus, args.performance_governor, args.disable_turbo)?;
+        let moved_irqs = if args.move_irqs { Some(MovedIrqs::move_away_from(&args.cpus)?) } else { None };
+        check_siblings(args)?;
+        check_cgroup(args);
+        if let Some(hypervisor) = virt::hypervisor() {
+            if args.steal_time_enabled {
+                info!("Running under hypervisor: {}, publishing steal time of the sampled cpus", hypervisor);
+            } else {
+                warn!(phase = "setup"; "Running under hypervisor: {}, latencies include preemption by the host, --steal-time attributes them", hypervisor);
+            }
+        }
+
+        let buffer_bytes = args.cpus.iter().map(|&cpu| jitter::locked_buffer_bytes(args, cpu) as u64).sum::<u64>();
+        match args.mlock {
+            MlockMode::Off => {},
+            MlockMode::Targeted => {
+                let code_bytes = utils::code_bytes().map_err(|source| JitterError::Mlock { what: "code pages", source })?;
+                utils::ensure_memlock_limit(code_bytes as u64 + buffer_bytes)?;
+                info!("Mlocked {}KiB of code pages to RAM", utils::mlock_code()? / 1024);
+            },
+            MlockMode::All => {
+                utils::ensure_memlock_limit(utils::mapped_bytes() + buffer_bytes)?;
+                utils::mlock_all()?;
+            },
+        }
+
+        if args.interrupt_mode == InterruptMode::Cli {
+            utils::raise_io_privilege_level()?;
+        }
+
+        let tracer = if args.trace_on_outlier {
+            Some(Ftrace::arm().map_err(JitterError::Ftrace)?)
+        } else {
+            None
+        };
+        let tracer = tracer.as_ref();
+
+        let setup_latch = args.run_as.as_ref().map(|_| SetupLatch::new(args.cpus.len()));
+        let setup_latch = setup_latch.as_ref();
+        let mut privileges_dropped = Ok(());
+
+        info!("Sampling jitter on cpus: {:?}", args.cpus);
+        let results = crossbeam::scope(|s| {
+            // the one result of ping-pong mode is the one of the initiating (first) cpu
+            let handles: Vec<_> = if args.mode == Mode::PingPong {
+                vec![s.spawn(move |_| pingpong::capture_round_trips(args, observers, setup_latch))]
+            } else {
+                args.cpus.iter()
+                    .map(|&cpu| s.spawn(move |_| capture_jitter(cpu, args, observers, tracer, setup_latch)))
+                    .collect()
+            };
+
+            if let (Some(user), Some(setup_latch)) = (&args.run_as, setup_latch) {
+                setup_latch.wait_for_arrivals();
+                privileges_dropped = utils::drop_privileges(user);
+                if privileges_dropped.is_err() {
+                    utils::request_stop();
+                }
+                setup_latch.release();
+            }
+
+            handles.into_iter()
+                .zip(&args.cpus)
+                .map(|(handle, &cpu)| handle.join().unwrap_or_else(|payload| Err(JitterError::SamplerPanicked { cpu, message: panic_message(payload) })))
+                .collect()
+        }).expect("Panics of sampler threads are caught when joining them");
+
+        for observer in observers {
+            observer.on_finish();
+        }
+        drop(moved_irqs);
+        drop(frequency_tuning);
+        drop(offline_siblings);
+
+        privileges_dropped.map(|()| results)
+    }
+}
+
+
+/// Warns about cgroup limits of the process that get in the way of sampling: sampled cpus outside of its cpuset
+/// (the sampler threads cannot be pinned to them) and a CFS quota, which stops every thread for the rest of a period once used up.
+fn check_cgroup(args: &ProgramArgs) {
+    if let Some(allowed) = cgroup::allowed_cpus() {
+        let outside: Vec<u32> = args.cpus.iter().copied().filter(|cpu| !allowed.contains(cpu)).collect();
+        if !outside.is_empty() {
+            warn!(phase = "setup"; "Cpus {} are not in the cpuset of the process ({}), sampler threads cannot run on them", cpulist::format_cpu_list(&outside), cpulist::format_cpu_list(&allowed));
+        }
+    }
+    if let Some((quota, period)) = cgroup::cpu_quota() {
+        let hint = if args.cgroup_throttling_enabled { "" } else { "; --cgroup-throttling publishes how often" };
+        warn!(phase = "setup"; "The cgroup of the process is limited to {}us of cpu time every {}us, each busy sampler thread uses up a whole cpu and gets throttled{}", quota, period, hint);
+    }
+}
+
+
+/// Warns about (or with `require_idle_siblings`, fails on) sampled cpus sharing their core with a hyperthread that the
+/// scheduler may run anything on.
+fn check_siblings(args: &ProgramArgs) -> error::Result<()> {
+    let isolated = cpulist::isolated_cpus().unwrap_or_default();
+    for &cpu in &args.cpus {
+        let siblings = match cpulist::smt_siblings(cpu) {
+            Ok(siblings) => siblings,
+            Err(err) => {
+                warn!(cpu = cpu, phase = "setup", error:% = err; "Unable to find hyperthread siblings of cpu: {}: {}", cpu, err);
+                continue;
+            },
+        };
+        let busy: Vec<u32> = siblings.into_iter().filter(|sibling| !isolated.contains(sibling)).collect();
+        if busy.is_empty() {
+            continue;
+        }
+
+        let err = JitterError::BusySiblings { cpu, siblings: cpulist::format_cpu_list(&busy) };
+        if args.require_idle_siblings {
+            return Err(err);
+        }
+        warn!(cpu = cpu, phase = "setup"; "{}", err);
+    }
+
+    Ok(())
+}
+
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(|| "unknown cause".to_string(), |message| message.to_string()),
+    }
+}
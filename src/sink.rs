@@ -0,0 +1,117 @@
+use std::io;
+
+use log::error;
+
+use crate::{clickhouse::ClickhouseSink, csv::CsvSink, graphite::GraphiteSink, grpc::GrpcSink, influx::InfluxSink, kafka::KafkaSink, kafka_producer::KafkaProducerSink, mqtt::MqttSink, postgres::PostgresSink, jsonl::JsonLinesSink, jitter::{Jitter, CORE_TO_CORE_MEASUREMENT, DISCONTINUITY_MEASUREMENT, HISTOGRAM_BUCKETS_MEASUREMENT, OUTLIER_MEASUREMENT, TOP_LATENCIES_MEASUREMENT}, metadata::RunMetadata, sampler::CpuJitter, socket::LineProtocolSocketSink, sqlite::SqliteSink, statsd::StatsdSink, utils::{Output, ProgramArgs}};
+
+
+pub trait Sink: Send {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()>;
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()>;
+
+    /// Publishes the record describing the run, once before any sample; sinks without a place for it skip it.
+    fn publish_metadata(&self, _metadata: &RunMetadata) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reopens output files, appending to them, so that they can be rotated while a daemon keeps running.
+    fn reopen(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/// One sink per configured output; every sink is published to independently, so one failing does not hold back the others.
+pub fn configure_sinks(program_args: &ProgramArgs) -> io::Result<Vec<Box<dyn Sink>>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::with_capacity(program_args.outputs.len());
+    for &output in &program_args.outputs {
+        sinks.push(match output {
+            Output::Influx => Box::new(InfluxSink::create(program_args)?),
+            Output::Csv => Box::new(CsvSink::create(output_path(program_args, output)?)?),
+            Output::JsonLines => Box::new(JsonLinesSink::create(program_args.output_path.as_deref(), &program_args.local_hostname, &program_args.run_id)?),
+            Output::Graphite => {
+                let address = program_args.graphite_address.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address given for Graphite output"))?;
+                Box::new(GraphiteSink::new(address, &program_args.local_hostname))
+            },
+            Output::Statsd => {
+                let address = program_args.statsd_address.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address given for StatsD output"))?;
+                Box::new(StatsdSink::create(address, program_args)?)
+            },
+            Output::Kafka if !program_args.kafka_brokers.is_empty() => Box::new(KafkaProducerSink::create(&program_args.kafka_brokers, program_args)?),
+            Output::Kafka => {
+                let rest_url = program_args.kafka_rest_url.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No REST proxy url or brokers given for Kafka output"))?;
+                Box::new(KafkaSink::create(rest_url, program_args)?)
+            },
+            Output::Mqtt => {
+                let url = program_args.mqtt_url.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No broker url given for MQTT output"))?;
+                Box::new(MqttSink::new(url, program_args))
+            },
+            Output::LineProtocolSocket => {
+                let address = program_args.line_protocol_socket.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No socket given for line protocol output"))?;
+                Box::new(LineProtocolSocketSink::create(address, program_args)?)
+            },
+            Output::Postgres => {
+                let url = program_args.postgres_url.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No connection url given for Postgres output"))?;
+                Box::new(PostgresSink::create(url, program_args)?)
+            },
+            Output::Clickhouse => {
+                let url = program_args.clickhouse_url.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No url given for ClickHouse output"))?;
+                Box::new(ClickhouseSink::create(url, program_args)?)
+            },
+            Output::Sqlite => {
+                let path = program_args.sqlite_path.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No database file given for SQLite output"))?;
+                Box::new(SqliteSink::create(path, program_args)?)
+            },
+            Output::Grpc => {
+                let url = program_args.grpc_url.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No collector url given for gRPC output"))?;
+                Box::new(GrpcSink::create(url, program_args)?)
+            },
+        });
+    }
+
+    Ok(sinks)
+}
+
+
+fn output_path(program_args: &ProgramArgs, output: Output) -> io::Result<&str> {
+    program_args.output_path.as_deref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No output path given for {:?} output", output)))
+}
+
+
+pub fn publish_all(sinks: &[Box<dyn Sink>], results: &[CpuJitter]) {
+    for result in results {
+        for sink in sinks {
+            if let Err(err) = sink.publish(result.cpu, &result.samples) {
+                error!(cpu = result.cpu, phase = "publish", error:% = err; "Unable to publish jitter samples for cpu: {}: {}", result.cpu, err);
+            }
+            for (measurement, events) in [(OUTLIER_MEASUREMENT, &result.outliers), (TOP_LATENCIES_MEASUREMENT, &result.top_latencies), (DISCONTINUITY_MEASUREMENT, &result.discontinuities), (CORE_TO_CORE_MEASUREMENT, &result.round_trips), (HISTOGRAM_BUCKETS_MEASUREMENT, &result.histogram_buckets)] {
+                if events.is_empty() {
+                    continue;
+                }
+                if let Err(err) = sink.publish_events(result.cpu, measurement, events) {
+                    error!(cpu = result.cpu, phase = "publish", error:% = err; "Unable to publish {} for cpu: {}: {}", measurement, result.cpu, err);
+                }
+            }
+        }
+    }
+}
+
+
+pub fn publish_metadata(sinks: &[Box<dyn Sink>], metadata: &RunMetadata) {
+    for sink in sinks {
+        if let Err(err) = sink.publish_metadata(metadata) {
+            error!(phase = "publish", error:% = err; "Unable to publish metadata of run: {}: {}", metadata.run_id, err);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::{fs, io, sync::Mutex};
+
+use log::{info, warn};
+
+use crate::{cpulist, error::{self, JitterError}};
+
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+static OFFLINED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+
+/// Keeps the hyperthread siblings of the sampled cpus offline for as long as it lives, bringing them back online
+/// when dropped, including while unwinding. Siblings that are sampled themselves stay online.
+pub struct OfflineSiblings {
+    cpus: Vec<u32>,
+}
+
+impl OfflineSiblings {
+    pub fn offline(sampled_cpus: &[u32]) -> error::Result<OfflineSiblings> {
+        // without unwinding the guard never gets dropped, bring the siblings back from the panic hook instead
+        #[cfg(panic = "abort")]
+        {
+            static PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+            PANIC_HOOK.call_once(|| {
+                let previous_hook = std::panic::take_hook();
+                std::panic::set_hook(Box::new(move |info| {
+                    if let Ok(mut offlined) = OFFLINED.try_lock() {
+                        offlined.drain(..).for_each(bring_online);
+                    }
+                    previous_hook(info);
+                }));
+            });
+        }
+
+        let mut guard = OfflineSiblings { cpus: Vec::new() };
+        for &cpu in sampled_cpus {
+            let siblings = cpulist::smt_siblings(cpu).map_err(|source| JitterError::Topology { cpu, source })?;
+            for sibling in siblings.into_iter().filter(|sibling| !sampled_cpus.contains(sibling)) {
+                info!("Taking cpu: {}, hyperthread sibling of cpu: {}, offline for the run", sibling, cpu);
+                set_online(sibling, false).map_err(|source| JitterError::CpuOnline { cpu: sibling, source })?;
+                guard.cpus.push(sibling);
+                OFFLINED.lock().unwrap().push(sibling);
+            }
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for OfflineSiblings {
+    fn drop(&mut self) {
+        OFFLINED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).retain(|cpu| !self.cpus.contains(cpu));
+        self.cpus.drain(..).for_each(bring_online);
+    }
+}
+
+
+fn bring_online(cpu: u32) {
+    match set_online(cpu, true) {
+        Ok(()) => info!("Brought cpu: {} back online", cpu),
+        Err(err) => warn!(cpu = cpu, phase = "setup", error:% = err; "Unable to bring cpu: {} back online: {}", cpu, err),
+    }
+}
+
+
+fn set_online(cpu: u32, online: bool) -> io::Result<()> {
+    fs::write(format!("{}/cpu{}/online", CPU_SYSFS, cpu), if online { "1" } else { "0" })
+}
@@ -0,0 +1,87 @@
+use std::{io::{self, BufWriter, Write}, net::TcpStream, os::unix::net::UnixStream, sync::Mutex};
+
+use log::info;
+
+use crate::{influx, jitter::Jitter, metadata::RunMetadata, sink::Sink, utils::ProgramArgs};
+
+
+/// Streams the same line protocol the Influx sink posts over a plain Unix or TCP socket, eg: into a telegraf socket_listener.
+pub struct LineProtocolSocketSink {
+    endpoint: Endpoint,
+    measurement: String,
+    tags: String,
+    connection: Mutex<Option<BufWriter<Box<dyn Write + Send>>>>,
+}
+
+enum Endpoint {
+    Unix(String),
+    Tcp(String),
+}
+
+impl LineProtocolSocketSink {
+    pub fn create(address: &str, program_args: &ProgramArgs) -> io::Result<LineProtocolSocketSink> {
+        let endpoint = match address.split_once("://") {
+            Some(("unix", path)) => Endpoint::Unix(path.to_string()),
+            Some(("tcp", address)) => Endpoint::Tcp(address.to_string()),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Expected unix://<path> or tcp://<host:port>, got: {}", address))),
+        };
+
+        Ok(LineProtocolSocketSink {
+            endpoint,
+            measurement: program_args.influx_measurement.clone(),
+            tags: influx::series_tags(program_args),
+            connection: Mutex::new(None),
+        })
+    }
+
+    fn write_points(&self, measurement: &str, value_field: &str, cpu: u32, data_points: &[Jitter]) -> io::Result<()> {
+        let measurement = influx::escape(measurement, " ,");
+        self.write_lines(data_points.iter().map(|data_point| influx::line(&measurement, &self.tags, value_field, cpu, data_point)))
+    }
+
+    fn write_lines<I: Iterator<Item = String>>(&self, mut lines: I) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(BufWriter::new(self.connect()?));
+        }
+
+        let writer = connection.as_mut().unwrap();
+        let result = lines
+            .try_for_each(|line| writeln!(writer, "{}", line))
+            .and_then(|_| writer.flush());
+        if result.is_err() {
+            // the listener went away (eg: telegraf restarted), reconnect on the next publish
+            *connection = None;
+        }
+        result
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Write + Send>> {
+        match &self.endpoint {
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                info!("Streaming line protocol to: {}", path);
+                Ok(Box::new(stream))
+            },
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address.as_str())?;
+                info!("Streaming line protocol to: {}", address);
+                Ok(Box::new(stream))
+            },
+        }
+    }
+}
+
+impl Sink for LineProtocolSocketSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.write_points(&self.measurement, "jitter", cpu, samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.write_points(measurement, "latency", cpu, events)
+    }
+
+    fn publish_metadata(&self, metadata: &RunMetadata) -> io::Result<()> {
+        self.write_lines(std::iter::once(influx::metadata_line(&self.tags, metadata)))
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fs, io::{self, Write}, process::{Command, Stdio}};
+
+use log::info;
+use serde_json::json;
+
+use crate::{jitter::Jitter, metadata::RunMetadata, sink::Sink, utils::{self, ProgramArgs}};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (run_id TEXT PRIMARY KEY, started_at INTEGER NOT NULL, host TEXT NOT NULL, kernel TEXT, time_source TEXT NOT NULL, counter_frequency_ghz REAL, args TEXT NOT NULL, metadata TEXT);
+CREATE TABLE IF NOT EXISTS samples (run_id TEXT NOT NULL REFERENCES runs (run_id), cpu INTEGER NOT NULL, measurement TEXT NOT NULL, ts INTEGER NOT NULL, latency INTEGER NOT NULL, fields TEXT);
+CREATE INDEX IF NOT EXISTS samples_by_run ON samples (run_id, cpu, ts);
+";
+
+/// Databases written before runs were keyed by their run id numbered them instead; their runs keep that number as id.
+const MIGRATE_NUMBERED_RUNS: &str = "
+BEGIN;
+ALTER TABLE runs RENAME TO numbered_runs;
+ALTER TABLE samples RENAME TO numbered_samples;
+DROP INDEX IF EXISTS samples_by_run;
+CREATE TABLE runs (run_id TEXT PRIMARY KEY, started_at INTEGER NOT NULL, host TEXT NOT NULL, kernel TEXT, time_source TEXT NOT NULL, counter_frequency_ghz REAL, args TEXT NOT NULL, metadata TEXT);
+CREATE TABLE samples (run_id TEXT NOT NULL REFERENCES runs (run_id), cpu INTEGER NOT NULL, measurement TEXT NOT NULL, ts INTEGER NOT NULL, latency INTEGER NOT NULL, fields TEXT);
+INSERT INTO runs SELECT CAST(id AS TEXT), started_at, host, kernel, time_source, counter_frequency_ghz, args, NULL FROM numbered_runs;
+INSERT INTO samples SELECT CAST(run_id AS TEXT), cpu, measurement, ts, latency, fields FROM numbered_samples;
+DROP TABLE numbered_samples;
+DROP TABLE numbered_runs;
+COMMIT;
+";
+
+
+/// Self-contained results database: a `runs` row describing the run, keyed by its run id, with its configuration and
+/// metadata (as json), and one `samples` row per interval or event, with timestamps and latencies in nanoseconds.
+/// Written through the `sqlite3` command line shell.
+pub struct SqliteSink {
+    path: String,
+    run_id: String,
+}
+
+impl SqliteSink {
+    pub fn create(path: &str, program_args: &ProgramArgs) -> io::Result<SqliteSink> {
+        if sqlite3(path, "SELECT count(*) FROM pragma_table_info('runs') WHERE name = 'id';\n")?.trim() == "1" {
+            info!("Migrating the runs of {} to run ids", path);
+            sqlite3(path, MIGRATE_NUMBERED_RUNS)?;
+        }
+
+        let kernel = fs::read_to_string("/proc/sys/kernel/osrelease").map(|release| release.trim().to_string()).ok();
+        let run = format!("{}INSERT INTO runs (run_id, started_at, host, kernel, time_source, counter_frequency_ghz, args) VALUES ({}, {}, {}, {}, {}, {}, {});\n",
+            SCHEMA, quote(&program_args.run_id), utils::clock_realtime(), quote(&program_args.local_hostname), kernel.as_deref().map_or_else(|| "NULL".to_string(), quote),
+            quote(program_args.clock.source().name()), program_args.clock.frequency(), quote(&format!("{:#?}", program_args.redacted())));
+
+        sqlite3(path, &run)?;
+        info!("Recording run {} into: {}", program_args.run_id, path);
+
+        Ok(SqliteSink { path: path.to_string(), run_id: program_args.run_id.clone() })
+    }
+
+    fn insert(&self, cpu: u32, measurement: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let mut statements = String::from("BEGIN;\n");
+        for data_point in data_points {
+            let fields: serde_json::Map<String, serde_json::Value> = data_point.fields.iter().map(|field| (field.name.to_string(), json!(field.value))).collect();
+            statements.push_str(&format!("INSERT INTO samples VALUES ({}, {}, {}, {}, {}, {});\n",
+                quote(&self.run_id), cpu, quote(measurement), data_point.ts, data_point.latency, quote(&serde_json::Value::Object(fields).to_string())));
+        }
+        statements.push_str("COMMIT;\n");
+
+        sqlite3(&self.path, &statements).map(|_| ())
+    }
+}
+
+
+/// Interval samples of a run recorded earlier, by cpu, in the order they were taken.
+pub fn load_run(path: &str, run_id: &str) -> io::Result<Vec<(u32, Jitter)>> {
+    let output = sqlite3(path, &format!("SELECT cpu, ts, latency FROM samples WHERE run_id = {} AND measurement = 'jitter' ORDER BY cpu, ts;\n", quote(run_id)))?;
+    output.lines()
+        .map(|row| {
+            let columns: Vec<i64> = row.split('|').filter_map(|column| column.parse().ok()).collect();
+            match columns[..] {
+                [cpu, ts, latency] => Ok((cpu as u32, Jitter { ts, latency, fields: Vec::new() })),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected row from sqlite3: {}", row))),
+            }
+        })
+        .collect()
+}
+
+
+/// Runs `statements` against the database at `path`, returning whatever they printed.
+fn sqlite3(path: &str, statements: &str) -> io::Result<String> {
+    let mut sqlite3 = Command::new("sqlite3")
+        .args(["-bail", "-batch", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| io::Error::new(err.kind(), format!("Unable to run sqlite3: {}", err)))?;
+
+    let written = sqlite3.stdin.take().unwrap().write_all(statements.as_bytes());
+    let output = sqlite3.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("sqlite3 failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    written.map(|_| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+impl Sink for SqliteSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.insert(cpu, "jitter", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.insert(cpu, measurement, events)
+    }
+
+    fn publish_metadata(&self, metadata: &RunMetadata) -> io::Result<()> {
+        let fields: serde_json::Map<String, serde_json::Value> = metadata.fields().into_iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+        sqlite3(&self.path, &format!("UPDATE runs SET metadata = {} WHERE run_id = {};\n", quote(&serde_json::Value::Object(fields).to_string()), quote(&metadata.run_id))).map(|_| ())
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fs::{self, File}, io, sync::Arc};
+
+use log::{info, warn};
+
+use crate::{ftrace, jitter::Field, perf::{self, PERF_TYPE_TRACEPOINT}, probe::IntervalProbe, sampler::CpuJitter};
+
+const STALL_EVENTS: [(&str, &str); 3] = [
+    ("stall_irqs", "irq/irq_handler_entry"),
+    ("stall_softirqs", "irq/softirq_entry"),
+    ("stall_sched_switches", "sched/sched_switch"),
+];
+const ATTRIBUTED_INTERVALS: usize = 5;
+
+
+/// Counts hardirq handlers, softirqs and scheduler switches hitting the sampled cpu during each interval, whoever
+/// they were triggered by, and publishes them as `stall_irqs`, `stall_softirqs` and `stall_sched_switches` fields.
+/// The counting happens in the kernel through perf tracepoint events, so nothing runs on the measured path.
+pub struct StallsProbe {
+    counters: Vec<(Arc<str>, File, u64)>,
+}
+
+impl StallsProbe {
+    pub fn new(cpu: u32) -> io::Result<StallsProbe> {
+        let events_dir = ftrace::tracefs_root()?.join("events");
+        let mut counters = Vec::with_capacity(STALL_EVENTS.len());
+        for (name, event) in STALL_EVENTS {
+            let id = fs::read_to_string(events_dir.join(event).join("id"))?.trim().parse::<u64>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("invalid id of tracepoint {}: {}", event, err)))?;
+            let mut file = perf::open_cpu_counter(PERF_TYPE_TRACEPOINT, id, cpu)
+                .map_err(|err| io::Error::new(err.kind(), format!("unable to count tracepoint {}: {}", event, err)))?;
+            let initial = perf::read_counter(&mut file)?;
+            counters.push((Arc::from(name), file, initial));
+        }
+
+        Ok(StallsProbe { counters })
+    }
+}
+
+impl IntervalProbe for StallsProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        for (name, file, previous) in self.counters.iter_mut() {
+            let current = perf::read_counter(file).unwrap_or(*previous);
+            fields.push(Field { name: name.clone(), value: current.wrapping_sub(*previous) as i64 });
+            *previous = current;
+        }
+    }
+}
+
+
+/// Logs the worst intervals of the run along with their stall event counts next to the median of the run,
+/// naming the event that exceeded its median the most. The counts cover the whole interval, not the instant of its
+/// worst latency, so this points at a suspect rather than establishing what caused the stall.
+pub fn attribute_worst_intervals(result: &CpuJitter) {
+    if result.samples.is_empty() {
+        return;
+    }
+
+    let medians: Vec<(Arc<str>, i64)> = STALL_EVENTS.iter()
+        .filter_map(|(name, _)| {
+            let mut values: Vec<i64> = result.samples.iter().filter_map(|sample| field_value(&sample.fields, name)).collect();
+            values.sort_unstable();
+            values.get(values.len() / 2).map(|&median| (Arc::from(*name), median))
+        })
+        .collect();
+    if medians.is_empty() {
+        warn!(cpu = result.cpu, phase = "summary"; "No stall events were counted on cpu: {}, unable to attribute its worst intervals", result.cpu);
+        return;
+    }
+
+    let mut worst: Vec<_> = result.samples.iter().collect();
+    worst.sort_unstable_by_key(|sample| std::cmp::Reverse(sample.latency));
+    for sample in worst.into_iter().take(ATTRIBUTED_INTERVALS) {
+        let counts: Vec<(&str, i64, i64)> = medians.iter()
+            .map(|(name, median)| (&**name, field_value(&sample.fields, name).unwrap_or(0), *median))
+            .collect();
+        let cause = counts.iter()
+            .filter(|(_, count, median)| count > median)
+            .max_by(|(_, a, a_median), (_, b, b_median)| (*a as f64 / (*a_median).max(1) as f64).total_cmp(&(*b as f64 / (*b_median).max(1) as f64)))
+            .map_or("none", |(name, _, _)| name.trim_start_matches("stall_"));
+        let details: Vec<String> = counts.iter().map(|(name, count, median)| format!("{}={} (median {})", name, count, median)).collect();
+        info!("Worst interval on cpu: {} at {}: max {}ns, {}, most in excess of its median: {}", result.cpu, sample.ts, sample.latency, details.join(", "), cause);
+    }
+}
+
+
+fn field_value(fields: &[Field], name: &str) -> Option<i64> {
+    fields.iter().find(|field| &*field.name == name).map(|field| field.value)
+}
@@ -0,0 +1,92 @@
+use std::{collections::HashSet, io, net::UdpSocket};
+
+use crate::{jitter::Jitter, recorder, sink::Sink, utils::ProgramArgs};
+
+/// Fits a datagram into a typical 1500 byte MTU once IP and UDP headers are added.
+const MAX_DATAGRAM_BYTES: usize = 1432;
+const NANOS_PER_MILLI: f64 = 1_000_000.0;
+
+
+/// Publishes every interval over UDP as DogStatsD lines, eg: `jitter.max:0.0213|ms|#host:foo,cpu:2`.
+/// Latencies (the interval maximum, percentiles and events) are timings in milliseconds, any other probe field is a gauge.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    tags: String,
+    latency_fields: HashSet<String>,
+}
+
+impl StatsdSink {
+    pub fn create(address: &str, program_args: &ProgramArgs) -> io::Result<StatsdSink> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+
+        let mut tags = format!("host:{}", program_args.local_hostname);
+        if !program_args.run_id.is_empty() {
+            tags.push_str(format!(",run_id:{}", program_args.run_id).as_str());
+        }
+        for (key, value) in &program_args.influx_tags {
+            tags.push_str(format!(",{}:{}", key, value).as_str());
+        }
+
+        Ok(StatsdSink {
+            socket,
+            tags,
+            latency_fields: program_args.percentiles.iter().map(|&percentile| recorder::percentile_field_name(percentile)).collect(),
+        })
+    }
+
+    fn send_metrics(&self, cpu: u32, metric: &str, data_points: &[Jitter]) -> io::Result<()> {
+        let mut datagram = String::with_capacity(MAX_DATAGRAM_BYTES);
+        for data_point in data_points {
+            self.append(&mut datagram, format!("jitter.{}:{}|ms|#{},cpu:{}", metric, data_point.latency as f64 / NANOS_PER_MILLI, self.tags, cpu))?;
+            // the max percentile repeats the latency sent above
+            for field in data_point.fields.iter().filter(|field| field.name.as_ref() != "jitter_max") {
+                let name = metric_name(field.name.trim_start_matches("jitter_"));
+                let line = if self.latency_fields.contains(field.name.as_ref()) {
+                    format!("jitter.{}:{}|ms|#{},cpu:{}", name, field.value as f64 / NANOS_PER_MILLI, self.tags, cpu)
+                } else {
+                    format!("jitter.{}:{}|g|#{},cpu:{}", name, field.value, self.tags, cpu)
+                };
+                self.append(&mut datagram, line)?;
+            }
+        }
+
+        self.send(&datagram)
+    }
+
+    /// Packs lines into as few datagrams as possible, sending the pending one when `line` would not fit anymore.
+    fn append(&self, datagram: &mut String, line: String) -> io::Result<()> {
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_BYTES {
+            self.send(datagram)?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(&line);
+        Ok(())
+    }
+
+    fn send(&self, datagram: &str) -> io::Result<()> {
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Dots would nest percentiles like p99.9 one level deeper.
+fn metric_name(name: &str) -> String {
+    name.replace('.', "_")
+}
+
+impl Sink for StatsdSink {
+    fn publish(&self, cpu: u32, samples: &[Jitter]) -> io::Result<()> {
+        self.send_metrics(cpu, "max", samples)
+    }
+
+    fn publish_events(&self, cpu: u32, measurement: &str, events: &[Jitter]) -> io::Result<()> {
+        self.send_metrics(cpu, &metric_name(measurement.trim_start_matches("jitter_")), events)
+    }
+}
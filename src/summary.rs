@@ -0,0 +1,108 @@
+use std::io::{self, Write};
+
+use serde_json::json;
+
+use crate::sampler::CpuJitter;
+
+
+/// Run-wide statistics of one cpu. Covers every single latency when histograms are enabled,
+/// otherwise only the worst latency of every interval.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub cpu: u32,
+    pub intervals: usize,
+    pub all_latencies: bool,
+    pub min: i64,
+    pub mean: f64,
+    pub p50: i64,
+    pub p99: i64,
+    pub p9999: i64,
+    pub max: i64,
+    pub worst_ts: i64,
+}
+
+
+/// A cpu whose sampler failed, listed next to the summaries of the cpus that completed their run.
+#[derive(Debug, Clone)]
+pub struct CpuFailure {
+    pub cpu: u32,
+    pub error: String,
+}
+
+
+pub fn summarize(result: &CpuJitter) -> Option<Summary> {
+    // ping-pong runs only have the worst round trip of every interval
+    let samples = if result.samples.is_empty() { &result.round_trips } else { &result.samples };
+    let worst = samples.iter().max_by_key(|sample| sample.latency)?;
+    let summary = match result.histogram.as_ref().filter(|histogram| !histogram.is_empty()) {
+        Some(histogram) => Summary {
+            cpu: result.cpu,
+            intervals: samples.len(),
+            all_latencies: true,
+            min: histogram.min() as i64,
+            mean: histogram.mean(),
+            p50: histogram.value_at_quantile(0.5) as i64,
+            p99: histogram.value_at_quantile(0.99) as i64,
+            p9999: histogram.value_at_quantile(0.9999) as i64,
+            max: histogram.max() as i64,
+            worst_ts: worst.ts,
+        },
+        None => {
+            let mut latencies: Vec<i64> = samples.iter().map(|sample| sample.latency).collect();
+            latencies.sort_unstable();
+            let quantile = |quantile: f64| latencies[((latencies.len() - 1) as f64 * quantile).round() as usize];
+            Summary {
+                cpu: result.cpu,
+                intervals: latencies.len(),
+                all_latencies: false,
+                min: latencies[0],
+                mean: latencies.iter().sum::<i64>() as f64 / latencies.len() as f64,
+                p50: quantile(0.5),
+                p99: quantile(0.99),
+                p9999: quantile(0.9999),
+                max: worst.latency,
+                worst_ts: worst.ts,
+            }
+        },
+    };
+
+    Some(summary)
+}
+
+
+pub fn write_table(writer: &mut impl Write, summaries: &[Summary], failures: &[CpuFailure]) -> io::Result<()> {
+    writeln!(writer, "{:>4} {:>9} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10}  {:<19}  of", "cpu", "intervals", "min", "mean", "p50", "p99", "p99.99", "max", "worst at")?;
+    for summary in summaries {
+        writeln!(writer, "{:>4} {:>9} {:>10} {:>12.1} {:>10} {:>10} {:>10} {:>10}  {:<19}  {}",
+                 summary.cpu, summary.intervals, summary.min, summary.mean, summary.p50, summary.p99, summary.p9999, summary.max, summary.worst_ts,
+                 if summary.all_latencies { "all latencies" } else { "interval maxima" })?;
+    }
+    for failure in failures {
+        writeln!(writer, "{:>4} failed: {}", failure.cpu, failure.error)?;
+    }
+
+    writer.flush()
+}
+
+
+pub fn write_json(writer: &mut impl Write, summaries: &[Summary], failures: &[CpuFailure]) -> io::Result<()> {
+    let failures = failures.iter().map(|failure| json!({"cpu": failure.cpu, "failed": true, "error": failure.error}));
+    let summaries: Vec<_> = summaries.iter()
+        .map(|summary| json!({
+            "cpu": summary.cpu,
+            "intervals": summary.intervals,
+            "of": if summary.all_latencies { "latencies" } else { "interval_maxima" },
+            "min": summary.min,
+            "mean": summary.mean,
+            "p50": summary.p50,
+            "p99": summary.p99,
+            "p99.99": summary.p9999,
+            "max": summary.max,
+            "worst_ts": summary.worst_ts,
+        }))
+        .chain(failures)
+        .collect();
+    writeln!(writer, "{}", serde_json::Value::Array(summaries))?;
+
+    writer.flush()
+}
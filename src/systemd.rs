@@ -0,0 +1,38 @@
+use std::{env, io, os::unix::net::{SocketAddr, UnixDatagram}, time::Duration};
+
+
+/// Sends a state update (eg: `READY=1`, `WATCHDOG=1`) to the service manager, like sd_notify(3).
+/// Does nothing unless running as a systemd service of `Type=notify`.
+pub fn notify(state: &str) -> io::Result<()> {
+    let socket_path = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket_path) => socket_path,
+        None => return Ok(()),
+    };
+
+    let socket_path = socket_path.to_string_lossy();
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())?
+        },
+        None => SocketAddr::from_pathname(socket_path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+
+/// How often systemd expects `WATCHDOG=1`, if the service has `WatchdogSec=` set and the watchdog is meant for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let watchdog_pid = env::var("WATCHDOG_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    if watchdog_pid.is_some_and(|pid| pid != std::process::id()) {
+        return None;
+    }
+
+    env::var("WATCHDOG_USEC").ok()
+        .and_then(|micros| micros.parse::<u64>().ok())
+        .filter(|&micros| micros > 0)
+        .map(Duration::from_micros)
+}
@@ -0,0 +1,66 @@
+use std::cmp::Reverse;
+
+
+/// Fixed-size min-heap keeping the `capacity` largest latencies (with their timestamps) seen since the last `drain`.
+/// Never allocates after construction, so it is safe to feed from the measured path.
+pub struct TopLatencies {
+    heap: Vec<(i64, i64)>,
+    capacity: usize,
+}
+
+impl TopLatencies {
+    pub fn new(capacity: usize) -> TopLatencies {
+        TopLatencies { heap: Vec::with_capacity(capacity), capacity }
+    }
+
+    #[inline(always)]
+    pub fn record(&mut self, latency: i64, ts: i64) {
+        if self.heap.len() < self.capacity {
+            self.heap.push((latency, ts));
+            self.sift_up(self.heap.len() - 1);
+        } else if latency > self.heap[0].0 {
+            self.heap[0] = (latency, ts);
+            self.sift_down(0);
+        }
+    }
+
+    /// Hands out the retained (latency, timestamp) pairs, worst first, and starts over.
+    pub fn drain(&mut self, mut consumer: impl FnMut(usize, i64, i64)) {
+        self.heap.sort_unstable_by_key(|&(latency, _)| Reverse(latency));
+        for (rank, &(latency, ts)) in self.heap.iter().enumerate() {
+            consumer(rank + 1, latency, ts);
+        }
+        self.heap.clear();
+    }
+
+    fn sift_up(&mut self, mut idx: usize) {
+        while idx > 0 {
+            let parent = (idx - 1) / 2;
+            if self.heap[idx].0 >= self.heap[parent].0 {
+                break;
+            }
+            self.heap.swap(idx, parent);
+            idx = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut idx: usize) {
+        let len = self.heap.len();
+        loop {
+            let left = 2 * idx + 1;
+            let right = left + 1;
+            let mut smallest = idx;
+            if left < len && self.heap[left].0 < self.heap[smallest].0 {
+                smallest = left;
+            }
+            if right < len && self.heap[right].0 < self.heap[smallest].0 {
+                smallest = right;
+            }
+            if smallest == idx {
+                break;
+            }
+            self.heap.swap(idx, smallest);
+            idx = smallest;
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::{HashMap, VecDeque}, fmt::Write as _, io::{self, Write}, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::Duration};
+
+use crate::{jitter::Jitter, observer::IntervalObserver};
+
+const SPARKLINE_WIDTH: usize = 60;
+const SPARKLINE_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// sparklines are log scaled from 100ns to 100ms
+const SPARKLINE_FLOOR_NANOS: f64 = 100.0;
+const SPARKLINE_DECADES: f64 = 6.0;
+// room for the percentiles of the recorder before a slot ever has to grow
+const PERCENTILE_SLOTS: usize = 16;
+
+
+/// Raw numbers of the recent intervals of a cpu, allocated up front and only formatted by the render thread.
+struct CpuHistory {
+    maxima: VecDeque<i64>,
+    run_max: i64,
+    /// Percentile fields (`jitter_p99`, ...) of the last interval; the names are shared with the samples, not copied.
+    percentiles: Vec<(Arc<str>, i64)>,
+}
+
+impl CpuHistory {
+    fn new() -> CpuHistory {
+        CpuHistory { maxima: VecDeque::with_capacity(SPARKLINE_WIDTH), run_max: 0, percentiles: Vec::with_capacity(PERCENTILE_SLOTS) }
+    }
+}
+
+
+/// Live terminal view of the run: the latest interval max, percentiles (with histograms enabled), the worst latency
+/// so far and a sparkline of recent interval maxima for every cpu, redrawn once per reporting interval.
+pub struct Dashboard {
+    history: HashMap<u32, Mutex<CpuHistory>>,
+    stopped: AtomicBool,
+    render_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dashboard {
+    pub fn start(cpus: &[u32], refresh_period: Duration) -> io::Result<Arc<Dashboard>> {
+        let dashboard = Arc::new(Dashboard {
+            history: cpus.iter().map(|&cpu| (cpu, Mutex::new(CpuHistory::new()))).collect(),
+            stopped: AtomicBool::new(false),
+            render_thread: Mutex::new(None),
+        });
+
+        // draws on the terminal's alternate screen, leaving whatever was on it before untouched
+        let worker = Arc::clone(&dashboard);
+        let handle = thread::Builder::new().name("dashboard".to_string()).spawn(move || {
+            let mut stdout = io::stdout();
+            let _ = write!(stdout, "\x1b[?1049h\x1b[?25l");
+            while !worker.stopped.load(Ordering::Acquire) {
+                let _ = write!(stdout, "\x1b[H\x1b[2J{}", worker.render());
+                let _ = stdout.flush();
+                thread::park_timeout(refresh_period);
+            }
+            let _ = write!(stdout, "\x1b[?25h\x1b[?1049l");
+            let _ = stdout.flush();
+        })?;
+
+        *dashboard.render_thread.lock().unwrap() = Some(handle);
+        Ok(dashboard)
+    }
+
+    fn render(&self) -> String {
+        let mut cpus: Vec<&u32> = self.history.keys().collect();
+        cpus.sort();
+
+        let mut screen = String::default();
+        let _ = writeln!(screen, "jitter: worst latency per reporting interval in ns, sparkline log scaled 100ns..100ms\n");
+        for cpu in cpus {
+            let history = self.history[cpu].lock().unwrap();
+            let last = history.maxima.back().copied().unwrap_or(0);
+            let _ = write!(screen, "cpu {:<3} last {:>10}  max {:>10}", cpu, last, history.run_max);
+            for (name, value) in &history.percentiles {
+                let _ = write!(screen, "  {} {:>8}", name.trim_start_matches("jitter_"), value);
+            }
+            let sparkline: String = history.maxima.iter().map(|&latency| sparkline_level(latency)).collect();
+            let _ = writeln!(screen, "\n        {}\n", sparkline);
+        }
+
+        screen
+    }
+}
+
+impl IntervalObserver for Dashboard {
+    fn on_interval(&self, cpu: u32, sample: &Jitter) {
+        let mut history = match self.history.get(&cpu).map(|history| history.try_lock()) {
+            Some(Ok(history)) => history,
+            // skip the interval rather than wait for a redraw in progress
+            _ => return,
+        };
+
+        if history.maxima.len() == SPARKLINE_WIDTH {
+            history.maxima.pop_front();
+        }
+        history.maxima.push_back(sample.latency);
+        history.run_max = history.run_max.max(sample.latency);
+        // refills the slots of the previous interval in place rather than allocating on the sampler thread
+        history.percentiles.clear();
+        history.percentiles.extend(sample.fields.iter()
+            .filter(|field| field.name.starts_with("jitter_"))
+            .map(|field| (Arc::clone(&field.name), field.value)));
+    }
+
+    /// Restores the terminal before anything else gets printed on it.
+    fn on_finish(&self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(handle) = self.render_thread.lock().unwrap().take() {
+            handle.thread().unpark();
+            handle.join().expect("Dashboard thread panicked");
+        }
+    }
+}
+
+
+fn sparkline_level(latency: i64) -> char {
+    let decades = (latency.max(1) as f64 / SPARKLINE_FLOOR_NANOS).log10() / SPARKLINE_DECADES;
+    let level = (decades * SPARKLINE_LEVELS.len() as f64).clamp(0.0, (SPARKLINE_LEVELS.len() - 1) as f64);
+    SPARKLINE_LEVELS[level as usize]
+}
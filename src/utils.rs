@@ -1,63 +1,692 @@
-use std::arch::asm;
+use std::{arch::asm, cell::Cell, fmt, marker::PhantomData, panic, sync::{Once, OnceLock, atomic::{AtomicBool, Ordering}}, time::Instant};
 
 use log::*;
-use nix::{time::{clock_gettime, ClockId}, sched::{CpuSet, sched_setaffinity}, sys::mman, unistd::Pid};
+
+pub use crate::workload::Workload;
+pub use crate::perf::PerfCounter;
+use crate::error::{self, JitterError};
+use nix::{libc, time::{clock_gettime, ClockId}, sched::{CpuSet, sched_setaffinity}, sys::{mman, signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal}}, unistd::{self, Pid}};
 
 pub const NANOS_IN_SEC: i64 = 1_000_000_000;
-pub static mut TSC_FREQUENCY: f64 = 0f64;
-pub static mut TIME_OFFSET: i64 = 0i64;
+const ALIGNMENT_SAMPLES: usize = 1_000;
+const INTERRUPT_WINDOW_NANOS: i64 = 10_000;
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+const CAP_IPC_LOCK: u32 = 14;
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+static REOPEN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static INTERRUPTS_DISABLED: Cell<bool> = const { Cell::new(false) };
+}
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const TSC_CALIBRATION_MILLIS: u64 = 200;
 
 
 pub type TimeFunc = fn() -> i64;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    ClockRealtime,
+    ClockMonotonic,
+    ClockMonotonicRaw,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Rdtsc,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    RdtscLfence,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Rdtscp,
+    #[cfg(target_arch = "aarch64")]
+    Cntvct,
+    #[cfg(target_arch = "riscv64")]
+    Rdtime,
+    #[cfg(target_arch = "riscv64")]
+    Rdcycle,
+    Instant,
+}
+
+impl TimeSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeSource::ClockRealtime => "clock_realtime",
+            TimeSource::ClockMonotonic => "clock_monotonic",
+            TimeSource::ClockMonotonicRaw => "clock_monotonic_raw",
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtsc => "rdtsc",
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::RdtscLfence => "rdtsc_lfence",
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtscp => "rdtscp",
+            #[cfg(target_arch = "aarch64")]
+            TimeSource::Cntvct => "cntvct",
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdtime => "rdtime",
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdcycle => "rdcycle",
+            TimeSource::Instant => "instant",
+        }
+    }
+
+    /// Raw reading of the source: nanoseconds for the clocks, ticks for the cycle counters.
+    pub fn read_func(self) -> TimeFunc {
+        match self {
+            TimeSource::ClockRealtime => clock_realtime,
+            TimeSource::ClockMonotonic => clock_monotonic,
+            TimeSource::ClockMonotonicRaw => clock_monotonic_raw,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtsc => rdtsc,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::RdtscLfence => rdtsc_lfence,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtscp => rdtscp,
+            #[cfg(target_arch = "aarch64")]
+            TimeSource::Cntvct => cntvct,
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdtime => rdtime,
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdcycle => rdcycle,
+            TimeSource::Instant => clock_instant,
+        }
+    }
+
+    /// Same as `read_func()`, but hands the reading function over as its own type so that `consumer` gets
+    /// monomorphized for this source and can inline the read instead of calling it through a pointer.
+    pub fn with_read_func<C: ReadFuncConsumer>(self, consumer: C) -> C::Output {
+        match self {
+            TimeSource::ClockRealtime => consumer.consume(clock_realtime),
+            TimeSource::ClockMonotonic => consumer.consume(clock_monotonic),
+            TimeSource::ClockMonotonicRaw => consumer.consume(clock_monotonic_raw),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtsc => consumer.consume(rdtsc),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::RdtscLfence => consumer.consume(rdtsc_lfence),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            TimeSource::Rdtscp => consumer.consume(rdtscp),
+            #[cfg(target_arch = "aarch64")]
+            TimeSource::Cntvct => consumer.consume(cntvct),
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdtime => consumer.consume(rdtime),
+            #[cfg(target_arch = "riscv64")]
+            TimeSource::Rdcycle => consumer.consume(rdcycle),
+            TimeSource::Instant => consumer.consume(clock_instant),
+        }
+    }
+
+    /// Whether the source reads a raw hardware counter that has to be scaled by its frequency.
+    pub fn is_cycle_counter(self) -> bool {
+        !matches!(self, TimeSource::ClockRealtime | TimeSource::ClockMonotonic | TimeSource::ClockMonotonicRaw | TimeSource::Instant)
+    }
+}
+
+
+/// Code generic over the reading function of a time source, see `TimeSource::with_read_func()`.
+pub trait ReadFuncConsumer {
+    type Output;
+
+    fn consume<R: Fn() -> i64>(self, read: R) -> Self::Output;
+}
+
+
+/// A time source together with its calibration: the counter frequency and the offset aligning it with realtime.
+/// Every sampler thread works on its own copy, so threads may carry differently calibrated clocks.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    source: TimeSource,
+    read: TimeFunc,
+    frequency: Option<f64>,
+    offset: i64,
+}
+
+impl Clock {
+    /// `frequency` (GHz) only applies to cycle counters and is detected when not given.
+    pub fn new(source: TimeSource, frequency: Option<f64>) -> error::Result<Clock> {
+        let frequency = match frequency {
+            _ if !source.is_cycle_counter() => None,
+            Some(frequency) => Some(frequency),
+            None => {
+                let detected = detect_counter_frequency()?;
+                info!("No counter frequency given for {}, detected {:.6} GHz", source.name(), detected);
+                Some(detected)
+            },
+        };
+
+        let mut clock = Clock { source, read: source.read_func(), frequency, offset: 0 };
+        clock.align_with_realtime();
+        Ok(clock)
+    }
+
+    #[inline(always)]
+    pub fn now(&self) -> i64 {
+        self.timestamp(self.ticks())
+    }
+
+    /// Raw reading of the time source: ticks for cycle counters, nanoseconds for everything else.
+    /// Cheaper than `now()`, which has to convert it.
+    #[inline(always)]
+    pub fn ticks(&self) -> i64 {
+        (self.read)()
+    }
+
+    /// Realtime timestamp of a raw reading.
+    #[inline(always)]
+    pub fn timestamp(&self, ticks: i64) -> i64 {
+        self.ticks_to_nanos(ticks) + self.offset
+    }
+
+    #[inline(always)]
+    pub fn ticks_to_nanos(&self, ticks: i64) -> i64 {
+        match self.frequency {
+            Some(frequency) => (ticks as f64 / frequency) as i64,
+            None => ticks,
+        }
+    }
+
+    pub fn nanos_to_ticks(&self, nanos: i64) -> i64 {
+        match self.frequency {
+            Some(frequency) => (nanos as f64 * frequency) as i64,
+            None => nanos,
+        }
+    }
+
+    /// Offset to realtime as seen from the calling thread's cpu, taken from the reading bracketed by the
+    /// tightest pair of realtime reads. Counters of different packages may be skewed, so every sampler
+    /// thread re-aligns its copy after being affinitized.
+    pub fn align_with_realtime(&mut self) {
+        if self.source == TimeSource::ClockRealtime {
+            return;
+        }
+
+        self.offset = 0;
+        let mut offset = 0;
+        let mut tightest_window = i64::MAX;
+        for _ in 0..ALIGNMENT_SAMPLES {
+            let before = clock_realtime();
+            let reading = self.now();
+            let after = clock_realtime();
+            if after - before < tightest_window {
+                tightest_window = after - before;
+                offset = before + tightest_window / 2 - reading;
+            }
+        }
+        self.offset = offset;
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Counter frequency in GHz, 0 for sources that read nanoseconds directly.
+    pub fn frequency(&self) -> f64 {
+        self.frequency.unwrap_or(0.0)
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new(TimeSource::ClockRealtime, None).expect("CLOCK_REALTIME needs no counter frequency")
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Busy,
+    Wakeup,
+    PingPong,
+    Memory,
+    Tick,
+}
+
+
+/// Whether sampler threads keep local interrupts enabled as usual, or disable them with `cli` for the run,
+/// bar the brief windows that keep the hard-lockup watchdog quiet (see `InterruptGuard`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    Normal,
+    Cli,
+}
+
+
+/// What gets locked in RAM: nothing, only what the sampler threads touch while measuring (their buffers and the
+/// executable code), or the whole address space including whatever the publishing threads allocate later on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MlockMode {
+    Off,
+    Targeted,
+    All,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Influx,
+    Csv,
+    JsonLines,
+    Graphite,
+    Statsd,
+    Kafka,
+    Mqtt,
+    LineProtocolSocket,
+    Postgres,
+    Clickhouse,
+    Sqlite,
+    Grpc,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Binary,
+    Csv,
+    Parquet,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaFormat {
+    Json,
+    Avro,
+}
+
+
+/// How `--kafka-user` authenticates with the brokers when producing to them directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaSaslMechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl KafkaSaslMechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            KafkaSaslMechanism::Plain => "PLAIN",
+            KafkaSaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            KafkaSaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickhouseFormat {
+    JsonEachRow,
+    RowBinary,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    Table,
+    Json,
+    Off,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+
+#[derive(Clone, Default)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+
+/// Settings of one cpu that differ from those of the rest of the run, see `--cpu-config`.
+#[derive(Debug, Clone, Default)]
+pub struct CpuOverride {
+    pub cpu: u32,
+    pub mode: Option<Mode>,
+    pub rt_priority: Option<i32>,
+    pub wakeup_interval_micros: Option<i64>,
+    pub workload: Option<Workload>,
+    pub working_set_kib: Option<usize>,
+}
+
+
+#[derive(Debug, Clone)]
 pub struct ProgramArgs {
+    /// 0 keeps sampling until stopped
     pub duration_seconds: i64,
-    pub report_interval_millis: i64,
+    pub report_interval_micros: i64,
+    pub mode: Mode,
+    pub wakeup_interval_micros: i64,
+    pub workload: Workload,
+    pub working_set_kib: usize,
     pub cpus: Vec<u32>,
-    pub time_func: TimeFunc,
-    pub mlock_enabled: bool,
-    pub lapic_disabled: bool,
+    pub cpu_overrides: Vec<CpuOverride>,
+    pub clock: Clock,
+    pub rt_priority: Option<i32>,
+    pub mlock: MlockMode,
+    pub numa_strict: bool,
+    pub require_idle_siblings: bool,
+    pub offline_siblings: bool,
+    pub performance_governor: bool,
+    pub disable_turbo: bool,
+    pub move_irqs: bool,
+    pub run_as: Option<String>,
+    pub huge_pages_enabled: bool,
+    pub interrupt_mode: InterruptMode,
+    pub lapic_max_off_millis: i64,
+    pub subtract_overhead: bool,
+    pub histogram_enabled: bool,
+    pub histogram_significant_digits: u8,
+    /// Latencies above this are recorded as this, the larger it is the more memory every histogram takes.
+    pub histogram_max_nanos: i64,
+    pub histogram_buckets_enabled: bool,
+    pub interrupts_enabled: bool,
+    pub context_switches_enabled: bool,
+    pub iterations_enabled: bool,
+    pub cpu_frequency_enabled: bool,
+    pub cstates_enabled: bool,
+    pub steal_time_enabled: bool,
+    pub cgroup_throttling_enabled: bool,
+    pub perf_counters: Vec<PerfCounter>,
+    pub stall_attribution_enabled: bool,
+    pub percentiles: Vec<f64>,
+    pub raw_output: Option<String>,
+    pub raw_format: RawFormat,
+    pub outlier_threshold_nanos: Option<i64>,
+    /// Deltas above this (or below zero) are taken for clock steps and recorded as discontinuities instead of latencies.
+    pub discontinuity_threshold_nanos: i64,
+    pub trace_on_outlier: bool,
+    pub top_latencies: usize,
+    pub outputs: Vec<Output>,
+    pub output_path: Option<String>,
+    pub flush_intervals: usize,
     pub influx_url: String,
     pub influx_db: String,
+    pub influx_org: String,
+    pub influx_bucket: Option<String>,
+    pub influx_token: Option<Secret>,
+    pub influx_user: Option<String>,
+    pub influx_password: Option<Secret>,
+    pub influx_ca_cert: Option<String>,
+    pub influx_insecure_skip_verify: bool,
+    pub influx_timeout_seconds: u64,
+    pub influx_gzip_enabled: bool,
+    /// Line protocol is posted in batches of at most this many bytes (give or take one point) and, if set, points.
+    pub influx_batch_max_bytes: usize,
+    pub influx_batch_max_points: Option<usize>,
+    pub influx_retries: u32,
+    pub influx_retry_backoff_millis: u64,
+    pub influx_spill_path: Option<String>,
+    pub influx_measurement: String,
+    /// Extra `key=value` tags attached to every published point, next to host and cpu.
+    pub influx_tags: Vec<(String, String)>,
+    pub graphite_address: Option<String>,
+    pub statsd_address: Option<String>,
+    pub kafka_rest_url: Option<String>,
+    /// Bootstrap brokers to produce to directly, instead of through a REST proxy.
+    pub kafka_brokers: Vec<String>,
+    pub kafka_topic: String,
+    pub kafka_format: KafkaFormat,
+    pub kafka_user: Option<String>,
+    pub kafka_password: Option<Secret>,
+    pub kafka_sasl_mechanism: KafkaSaslMechanism,
+    pub kafka_tls_enabled: bool,
+    pub kafka_ca_cert: Option<String>,
+    pub line_protocol_socket: Option<String>,
+    pub postgres_url: Option<String>,
+    pub postgres_table: String,
+    pub postgres_create_table: bool,
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_table: String,
+    pub clickhouse_format: ClickhouseFormat,
+    pub clickhouse_create_table: bool,
+    pub clickhouse_user: Option<String>,
+    pub clickhouse_password: Option<Secret>,
+    pub sqlite_path: Option<String>,
+    pub grpc_url: Option<String>,
+    pub mqtt_url: Option<String>,
+    pub mqtt_topic: String,
+    pub mqtt_qos: u8,
+    pub mqtt_user: Option<String>,
+    pub mqtt_password: Option<Secret>,
     pub local_hostname: String,
+    /// Tags every published sample, so that the samples of one run can be matched with its metadata.
+    pub run_id: String,
+    pub prometheus_listen: Option<String>,
+    pub tui_enabled: bool,
+    pub summary_format: SummaryFormat,
+    pub fail_if_max_above_nanos: Option<i64>,
+    pub fail_if_p99_above_nanos: Option<i64>,
+    pub daemon: bool,
+    pub pid_file: String,
+    /// Wall clock instant (CLOCK_REALTIME nanoseconds) every sampler thread starts measuring at, to line runs up across hosts.
+    pub start_at_nanos: Option<i64>,
 }
 
 impl Default for ProgramArgs {
     fn default() -> ProgramArgs {
         ProgramArgs {
             duration_seconds: 0,
-            report_interval_millis: 0,
+            report_interval_micros: 0,
+            mode: Mode::Busy,
+            wakeup_interval_micros: 1000,
+            workload: Workload::Empty,
+            working_set_kib: 32 * 1024,
             cpus: Vec::default(),
-            time_func: clock_realtime,
-            mlock_enabled: false,
-            lapic_disabled: false,
+            cpu_overrides: Vec::default(),
+            clock: Clock::default(),
+            rt_priority: None,
+            mlock: MlockMode::Off,
+            numa_strict: false,
+            require_idle_siblings: false,
+            offline_siblings: false,
+            performance_governor: false,
+            disable_turbo: false,
+            move_irqs: false,
+            run_as: None,
+            huge_pages_enabled: false,
+            interrupt_mode: InterruptMode::Normal,
+            lapic_max_off_millis: 1000,
+            subtract_overhead: false,
+            histogram_enabled: false,
+            histogram_significant_digits: 3,
+            histogram_max_nanos: 60 * NANOS_IN_SEC,
+            histogram_buckets_enabled: false,
+            interrupts_enabled: false,
+            context_switches_enabled: false,
+            iterations_enabled: false,
+            cpu_frequency_enabled: false,
+            cstates_enabled: false,
+            steal_time_enabled: false,
+            cgroup_throttling_enabled: false,
+            perf_counters: Vec::default(),
+            stall_attribution_enabled: false,
+            percentiles: vec![50.0, 90.0, 99.0, 99.99],
+            raw_output: None,
+            raw_format: RawFormat::Binary,
+            outlier_threshold_nanos: None,
+            discontinuity_threshold_nanos: 10 * NANOS_IN_SEC,
+            trace_on_outlier: false,
+            top_latencies: 0,
+            outputs: vec![Output::Influx],
+            output_path: None,
+            flush_intervals: 0,
             influx_url: String::default(),
             influx_db: String::default(),
+            influx_org: String::default(),
+            influx_bucket: None,
+            influx_token: None,
+            influx_user: None,
+            influx_password: None,
+            influx_ca_cert: None,
+            influx_insecure_skip_verify: false,
+            influx_timeout_seconds: 30,
+            influx_gzip_enabled: true,
+            influx_batch_max_bytes: 768 * 1024,
+            influx_batch_max_points: None,
+            influx_retries: 3,
+            influx_retry_backoff_millis: 500,
+            influx_spill_path: None,
+            influx_measurement: String::from("jitter"),
+            influx_tags: Vec::default(),
+            graphite_address: None,
+            statsd_address: None,
+            kafka_rest_url: None,
+            kafka_brokers: Vec::default(),
+            kafka_topic: String::from("jitter"),
+            kafka_format: KafkaFormat::Json,
+            kafka_user: None,
+            kafka_password: None,
+            kafka_sasl_mechanism: KafkaSaslMechanism::Plain,
+            kafka_tls_enabled: false,
+            kafka_ca_cert: None,
+            line_protocol_socket: None,
+            postgres_url: None,
+            postgres_table: String::from("jitter"),
+            postgres_create_table: false,
+            clickhouse_url: None,
+            clickhouse_table: String::from("jitter"),
+            clickhouse_format: ClickhouseFormat::RowBinary,
+            clickhouse_create_table: false,
+            clickhouse_user: None,
+            clickhouse_password: None,
+            sqlite_path: None,
+            grpc_url: None,
+            mqtt_url: None,
+            mqtt_topic: String::from("jitter"),
+            mqtt_qos: 0,
+            mqtt_user: None,
+            mqtt_password: None,
             local_hostname: String::default(),
+            run_id: String::default(),
+            prometheus_listen: None,
+            tui_enabled: false,
+            summary_format: SummaryFormat::Table,
+            fail_if_max_above_nanos: None,
+            fail_if_p99_above_nanos: None,
+            daemon: false,
+            pid_file: String::default(),
+            start_at_nanos: None,
         }
     }
 }
 
+impl ProgramArgs {
+    /// Settings a sampler thread of `cpu` runs with, any of its overrides applied.
+    pub fn for_cpu(&self, cpu: u32) -> ProgramArgs {
+        let mut program_args = self.clone();
+        for cpu_override in self.cpu_overrides.iter().filter(|cpu_override| cpu_override.cpu == cpu) {
+            program_args.mode = cpu_override.mode.unwrap_or(program_args.mode);
+            program_args.rt_priority = cpu_override.rt_priority.or(program_args.rt_priority);
+            program_args.wakeup_interval_micros = cpu_override.wakeup_interval_micros.unwrap_or(program_args.wakeup_interval_micros);
+            program_args.workload = cpu_override.workload.unwrap_or(program_args.workload);
+            program_args.working_set_kib = cpu_override.working_set_kib.unwrap_or(program_args.working_set_kib);
+        }
+        program_args
+    }
+
+    /// Copy safe to log or publish: secrets are already hidden by `Secret`, which leaves the credentials some urls carry.
+    pub fn redacted(&self) -> ProgramArgs {
+        let mut program_args = self.clone();
+        program_args.influx_url = redact_url(&program_args.influx_url);
+        for url in [&mut program_args.kafka_rest_url, &mut program_args.postgres_url, &mut program_args.clickhouse_url, &mut program_args.grpc_url, &mut program_args.mqtt_url] {
+            *url = url.as_deref().map(redact_url);
+        }
+        program_args
+    }
+}
+
+
+/// `url` without the `user:password@` of its authority.
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme), rest),
+        None => (String::new(), url),
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    match rest[..authority_end].rfind('@') {
+        Some(at) => format!("{}{}", scheme, &rest[at + 1..]),
+        None => url.to_string(),
+    }
+}
+
 pub fn clock_realtime() -> i64 {
     let time_spec = clock_gettime(ClockId::CLOCK_REALTIME).unwrap();
-    return time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec();
+    time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec()
+}
+
+
+/// Sleeps until CLOCK_REALTIME reaches `ts`, spinning through the last millisecond so that the wakeup latency of the
+/// sleep does not delay the start. Returns early once a stop has been requested.
+pub fn wait_until_realtime(ts: i64) {
+    const SPIN_NANOS: i64 = 1_000_000;
+    const CHECK_STOP_NANOS: i64 = 100_000_000;
+
+    loop {
+        let remaining = ts - clock_realtime();
+        if remaining <= SPIN_NANOS || stop_requested() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_nanos((remaining - SPIN_NANOS).min(CHECK_STOP_NANOS) as u64));
+    }
+    while clock_realtime() < ts && !stop_requested() {
+        std::hint::spin_loop();
+    }
+}
+
+
+/// Estimated error of CLOCK_REALTIME in microseconds as maintained by NTP or PTP (through phc2sys) daemons,
+/// None when the kernel considers the clock unsynchronized.
+pub fn realtime_sync_error_micros() -> std::io::Result<Option<i64>> {
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(if state == libc::TIME_ERROR || timex.status & libc::STA_UNSYNC != 0 { None } else { Some(timex.esterror) })
 }
 
 
 pub fn clock_monotonic() -> i64 {
     let time_spec = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap();
-    unsafe {
-        return time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec() + TIME_OFFSET;
-    }
+    time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec()
 }
 
 
-pub fn clock_rdtsc() -> i64 {
-    unsafe {
-        (rdtsc() as f64 / TSC_FREQUENCY) as i64 + TIME_OFFSET
+/// Hardware based monotonic time that is not subject to NTP slewing.
+pub fn clock_monotonic_raw() -> i64 {
+    let time_spec = clock_gettime(ClockId::CLOCK_MONOTONIC_RAW).unwrap();
+    time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec()
+}
+
+
+/// Portable fallback based on `std::time::Instant`; precision depends on what the platform backs it with.
+pub fn clock_instant() -> i64 {
+    static BASE: OnceLock<Instant> = OnceLock::new();
+
+    BASE.get_or_init(Instant::now).elapsed().as_nanos() as i64
+}
+
+
+/// Median cost of reading the clock, measured as the delta between back to back calls.
+pub fn measure_clock_overhead(clock: &Clock, samples: usize) -> i64 {
+    let mut deltas: Vec<i64> = Vec::with_capacity(samples);
+    let mut previous = clock.now();
+    for _ in 0..samples {
+        let now = clock.now();
+        deltas.push(now - previous);
+        previous = now;
     }
+
+    deltas.sort_unstable();
+    deltas[deltas.len() / 2]
 }
 
 
@@ -80,18 +709,549 @@ pub fn rdtsc() -> i64 {
 }
 
 
-pub fn affinitize_to_cpu(cpu: u32) {
+/// rdtsc preceded by lfence, so that it does not execute before all earlier instructions have completed.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn rdtsc_lfence() -> i64 {
+    let upper: i64;
+    let lower: i64;
+
+    unsafe {
+        asm!(
+        "lfence",
+        "rdtsc",
+        out("rax") lower,
+        out("rdx") upper,
+        options(nostack)
+        )
+    }
+
+    upper << 32 | lower
+}
+
+
+/// rdtscp waits for all earlier instructions to execute before reading the counter.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn rdtscp() -> i64 {
+    let upper: i64;
+    let lower: i64;
+
+    unsafe {
+        asm!(
+        "rdtscp",
+        out("rax") lower,
+        out("rdx") upper,
+        out("rcx") _,
+        options(nostack)
+        )
+    }
+
+    upper << 32 | lower
+}
+
+
+/// Virtual counter of the ARM generic timer, preceded by isb so that it is not read ahead of earlier instructions.
+#[cfg(target_arch = "aarch64")]
+pub fn cntvct() -> i64 {
+    let ticks: u64;
+
+    unsafe {
+        asm!(
+        "isb",
+        "mrs {ticks}, cntvct_el0",
+        ticks = out(reg) ticks,
+        options(nomem, nostack)
+        )
+    }
+
+    ticks as i64
+}
+
+
+#[cfg(target_arch = "aarch64")]
+fn cntfrq() -> u64 {
+    let frequency: u64;
+
+    unsafe {
+        asm!(
+        "mrs {frequency}, cntfrq_el0",
+        frequency = out(reg) frequency,
+        options(nomem, nostack)
+        )
+    }
+
+    frequency
+}
+
+
+/// RISC-V real-time counter, ticking at the platform timebase frequency.
+#[cfg(target_arch = "riscv64")]
+pub fn rdtime() -> i64 {
+    let ticks: u64;
+
+    unsafe {
+        asm!(
+        "rdtime {ticks}",
+        ticks = out(reg) ticks,
+        options(nomem, nostack)
+        )
+    }
+
+    ticks as i64
+}
+
+
+/// RISC-V cycle counter; ticks at the core clock, so its frequency has to be given with --tsc-frequency.
+/// Recent kernels only expose it to user space when perf_user_access is enabled.
+#[cfg(target_arch = "riscv64")]
+pub fn rdcycle() -> i64 {
+    let cycles: u64;
+
+    unsafe {
+        asm!(
+        "rdcycle {cycles}",
+        cycles = out(reg) cycles,
+        options(nomem, nostack)
+        )
+    }
+
+    cycles as i64
+}
+
+
+/// Frequency in GHz of the hardware counter read by the cycle counter time sources.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn detect_counter_frequency() -> error::Result<f64> {
+    Ok(detect_tsc_frequency())
+}
+
+
+#[cfg(target_arch = "aarch64")]
+pub fn detect_counter_frequency() -> error::Result<f64> {
+    Ok(cntfrq() as f64 / NANOS_IN_SEC as f64)
+}
+
+
+/// Timebase frequency of rdtime, as published in the device tree (a big-endian cell of 32 or 64 bits).
+#[cfg(target_arch = "riscv64")]
+pub fn detect_counter_frequency() -> error::Result<f64> {
+    use std::convert::TryInto;
+
+    const TIMEBASE_FREQUENCY: &str = "/proc/device-tree/cpus/timebase-frequency";
+    // ACPI and other boards without a device tree have no such node
+    let cell = std::fs::read(TIMEBASE_FREQUENCY)
+        .map_err(|err| JitterError::CounterFrequency(format!("unable to read {}: {}", TIMEBASE_FREQUENCY, err)))?;
+    let frequency = match cell.len() {
+        4 => u32::from_be_bytes(cell[..].try_into().unwrap()) as u64,
+        8 => u64::from_be_bytes(cell[..].try_into().unwrap()),
+        len => return Err(JitterError::CounterFrequency(format!("unexpected cell length {} of {}", len, TIMEBASE_FREQUENCY))),
+    };
+
+    Ok(frequency as f64 / NANOS_IN_SEC as f64)
+}
+
+
+/// TSC frequency in GHz, taken from CPUID leaf 0x15 when the cpu enumerates it or otherwise calibrated against CLOCK_MONOTONIC.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn detect_tsc_frequency() -> f64 {
+    match tsc_frequency_from_cpuid() {
+        Some(frequency) => frequency,
+        None => calibrate_tsc_frequency(),
+    }
+}
+
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn tsc_frequency_from_cpuid() -> Option<f64> {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::__cpuid;
+
+    #[allow(unused_unsafe)]
+    let (max_leaf, tsc_leaf) = unsafe { (__cpuid(0).eax, __cpuid(0x15)) };
+    if max_leaf < 0x15 || tsc_leaf.eax == 0 || tsc_leaf.ebx == 0 || tsc_leaf.ecx == 0 {
+        return None;
+    }
+
+    Some(tsc_leaf.ecx as f64 * tsc_leaf.ebx as f64 / tsc_leaf.eax as f64 / NANOS_IN_SEC as f64)
+}
+
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn calibrate_tsc_frequency() -> f64 {
+    let monotonic = || {
+        let time_spec = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap();
+        time_spec.tv_sec() * NANOS_IN_SEC + time_spec.tv_nsec()
+    };
+
+    let start_ns = monotonic();
+    let start_ticks = rdtsc();
+    std::thread::sleep(std::time::Duration::from_millis(TSC_CALIBRATION_MILLIS));
+    let end_ticks = rdtsc();
+    let end_ns = monotonic();
+
+    (end_ticks - start_ticks) as f64 / (end_ns - start_ns) as f64
+}
+
+
+/// Whether the TSC ticks at a constant rate regardless of P/C-states (CPUID 0x80000007 EDX bit 8),
+/// also accepting the constant_tsc + nonstop_tsc pair the kernel reports in /proc/cpuinfo.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn tsc_is_invariant() -> bool {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::__cpuid;
+
+    #[allow(unused_unsafe)]
+    let invariant = unsafe { __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0 };
+    if invariant {
+        return true;
+    }
+
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    cpuinfo.lines()
+        .find(|line| line.starts_with("flags"))
+        .map(|flags| {
+            let flags: Vec<&str> = flags.split_whitespace().collect();
+            flags.contains(&"constant_tsc") && flags.contains(&"nonstop_tsc")
+        })
+        .unwrap_or(false)
+}
+
+
+pub fn affinitize_to_cpu(cpu: u32) -> error::Result<()> {
     let mut cpus = CpuSet::new();
-    cpus.set(cpu as usize).expect("Unable to set target CPU in cpuset");
-    sched_setaffinity(Pid::from_raw(0), &cpus).expect(&format!("Unable to set CPU affinity to cpu: {}", cpu));
+    cpus.set(cpu as usize)
+        .and_then(|()| sched_setaffinity(Pid::from_raw(0), &cpus))
+        .map_err(|source| JitterError::Affinity { cpu, source })
+}
+
+
+/// Switches the calling thread to SCHED_FIFO so that it is not preempted by regular CFS tasks.
+pub fn set_realtime_priority(priority: i32) -> error::Result<()> {
+    let param = libc::sched_param { sched_priority: priority };
+    let result = unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) };
+    if result != 0 {
+        let source = std::io::Error::last_os_error();
+        if source.raw_os_error() == Some(libc::EPERM) {
+            return Err(JitterError::PriorityDenied { priority });
+        }
+        return Err(JitterError::Priority { priority, source });
+    }
+
+    Ok(())
+}
+
+
+/// Switches every thread of the process to `user` and its groups, for good.
+pub fn drop_privileges(user: &str) -> error::Result<()> {
+    let failure = |source: std::io::Error| JitterError::Privileges { user: user.to_string(), source };
+    let account = unistd::User::from_name(user)
+        .map_err(|err| failure(err.into()))?
+        .ok_or_else(|| failure(std::io::Error::new(std::io::ErrorKind::NotFound, "no such user")))?;
+    let name = std::ffi::CString::new(user).map_err(|err| failure(err.into()))?;
+
+    // glibc applies set*id calls to every thread, including the publisher and the sampler ones
+    unistd::initgroups(&name, account.gid)
+        .and_then(|()| unistd::setresgid(account.gid, account.gid, account.gid))
+        .and_then(|()| unistd::setresuid(account.uid, account.uid, account.uid))
+        .map_err(|err| failure(err.into()))?;
+    info!("Dropped privileges to user: {} (uid: {}, gid: {})", user, account.uid, account.gid);
+
+    Ok(())
+}
+
+
+/// Makes SIGINT and SIGTERM end the run early rather than kill the process, so that interrupts get re-enabled
+/// and the intervals sampled so far are still published. A second signal terminates the process as usual.
+pub fn install_stop_handler() -> error::Result<()> {
+    let action = SigAction::new(SigHandler::Handler(handle_stop_signal), SaFlags::SA_RESETHAND, SigSet::empty());
+    for stop_signal in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { signal::sigaction(stop_signal, &action) }
+            .map_err(|source| JitterError::SignalHandler { signal: stop_signal, source })?;
+    }
+
+    Ok(())
+}
+
+
+extern "C" fn handle_stop_signal(_: libc::c_int) {
+    request_stop();
+}
+
+
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+
+#[inline(always)]
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+
+/// Makes SIGHUP ask the streaming publisher to reopen its outputs before its next flush.
+pub fn install_reopen_handler() -> error::Result<()> {
+    let action = SigAction::new(SigHandler::Handler(handle_reopen_signal), SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { signal::sigaction(Signal::SIGHUP, &action) }
+        .map(|_| ())
+        .map_err(|source| JitterError::SignalHandler { signal: Signal::SIGHUP, source })
+}
+
+
+extern "C" fn handle_reopen_signal(_: libc::c_int) {
+    REOPEN_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+
+pub fn take_reopen_request() -> bool {
+    REOPEN_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+
+/// Detaches from the terminal into a new session in the background, keeping the working directory and stderr
+/// (so that logs can still be redirected), and records the pid of the detached process in `pid_file`.
+pub fn daemonize(pid_file: &str) -> std::io::Result<()> {
+    nix::unistd::daemon(true, true)?;
+    let dev_null = std::fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO] {
+        nix::unistd::dup2(std::os::unix::io::AsRawFd::as_raw_fd(&dev_null), fd)?;
+    }
+
+    std::fs::write(pid_file, format!("{}\n", std::process::id()))
+}
+
+
+/// Canonical name the resolver (`/etc/hosts`, DNS, ...) reports for `hostname`.
+pub fn fully_qualified_hostname(hostname: &str) -> Option<String> {
+    let node = std::ffi::CString::new(hostname).ok()?;
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_flags = libc::AI_CANONNAME;
+    hints.ai_family = libc::AF_UNSPEC;
+    let mut addresses: *mut libc::addrinfo = std::ptr::null_mut();
+    if unsafe { libc::getaddrinfo(node.as_ptr(), std::ptr::null(), &hints, &mut addresses) } != 0 {
+        return None;
+    }
+
+    let canonical_name = unsafe { (*addresses).ai_canonname.as_ref() }
+        .map(|name| unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned());
+    unsafe { libc::freeaddrinfo(addresses) };
+    canonical_name.filter(|name| !name.is_empty())
+}
+
+
+pub fn mlock_all() -> error::Result<()> {
+    info!("Mlocking all pages to RAM");
+    mman::mlockall(mman::MlockAllFlags::MCL_CURRENT | mman::MlockAllFlags::MCL_FUTURE)
+        .map_err(|err| JitterError::Mlock { what: "program pages", source: err.into() })
+}
+
+
+/// Locks the code of the binary and of libc in RAM, so that the sampling loop never waits on its code being paged
+/// back in. Other libraries only serve publishing and stay pageable. Returns the number of bytes locked.
+pub fn mlock_code() -> error::Result<usize> {
+    let mut locked = 0;
+    for (start, end) in executable_file_mappings().map_err(|source| JitterError::Mlock { what: "code pages", source })? {
+        if unsafe { libc::mlock(start as *const libc::c_void, end - start) } != 0 {
+            return Err(JitterError::Mlock { what: "code pages", source: std::io::Error::last_os_error() });
+        }
+        locked += end - start;
+    }
+
+    Ok(locked)
+}
+
+
+/// Bytes `mlock_code` would lock.
+pub fn code_bytes() -> std::io::Result<usize> {
+    Ok(executable_file_mappings()?.iter().map(|(start, end)| end - start).sum())
+}
+
+
+fn executable_file_mappings() -> std::io::Result<Vec<(usize, usize)>> {
+    let executable = std::fs::read_link("/proc/self/exe")?;
+    let is_hot = |path: &str| std::path::Path::new(path) == executable
+        || path.rsplit('/').next().is_some_and(|name| name.starts_with("libc.so") || name.starts_with("libc-"));
+
+    Ok(std::fs::read_to_string("/proc/self/maps")?.lines().filter_map(|line| executable_file_mapping(line, is_hot)).collect())
+}
+
+
+/// Address range of a `/proc/self/maps` line mapping a file accepted by `filter` with execute permission.
+fn executable_file_mapping(line: &str, filter: impl Fn(&str) -> bool) -> Option<(usize, usize)> {
+    let mut columns = line.split_whitespace();
+    let (range, permissions) = (columns.next()?, columns.next()?);
+    let path = columns.nth(3)?;
+    if !permissions.contains('x') || !filter(path) {
+        return None;
+    }
+
+    let (start, end) = range.split_once('-')?;
+    Some((usize::from_str_radix(start, 16).ok()?, usize::from_str_radix(end, 16).ok()?))
+}
+
+
+/// Makes sure that `bytes` more can be mlocked on top of what already is, raising the soft RLIMIT_MEMLOCK (and the
+/// hard one, given CAP_SYS_RESOURCE) when it is too low. Processes with CAP_IPC_LOCK are not bound by the limit.
+pub fn ensure_memlock_limit(bytes: u64) -> error::Result<()> {
+    if has_capability(CAP_IPC_LOCK) {
+        return Ok(());
+    }
+
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return Err(JitterError::Mlock { what: "memory, unable to read RLIMIT_MEMLOCK", source: std::io::Error::last_os_error() });
+    }
+    let needed = process_status_kib("VmLck").unwrap_or(0) * 1024 + bytes;
+    if limit.rlim_cur == libc::RLIM_INFINITY || limit.rlim_cur >= needed {
+        return Ok(());
+    }
+
+    let raised = libc::rlimit { rlim_cur: needed, rlim_max: if limit.rlim_max == libc::RLIM_INFINITY { limit.rlim_max } else { limit.rlim_max.max(needed) } };
+    if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &raised) } != 0 {
+        return Err(JitterError::MemlockLimit { needed_kib: needed.div_ceil(1024), limit_kib: limit.rlim_cur / 1024, hard_limit_kib: limit.rlim_max / 1024 });
+    }
+    info!("Raised RLIMIT_MEMLOCK from {}KiB to {}KiB", limit.rlim_cur / 1024, needed.div_ceil(1024));
+
+    Ok(())
+}
+
+
+/// Bytes currently mapped by the process, all of which `mlock_all` locks.
+pub fn mapped_bytes() -> u64 {
+    process_status_kib("VmSize").unwrap_or(0) * 1024
+}
+
+
+fn process_status_kib(field: &str) -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status").ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+
+fn has_capability(capability: u32) -> bool {
+    std::fs::read_to_string("/proc/self/status").ok()
+        .and_then(|status| status.lines().find_map(|line| line.strip_prefix("CapEff:")).and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok()))
+        .is_some_and(|mask| mask & (1 << capability) != 0)
 }
 
 
-pub fn mlock() {
-    info!("Mlocking pages to RAM");
-    let result = mman::mlockall(mman::MlockAllFlags::MCL_CURRENT | mman::MlockAllFlags::MCL_FUTURE);
-    if result.is_err() {
-        panic!("Unable to mlock program pages: {}", result.unwrap_err());
+/// Writes to every page of the allocated capacity of `buffer`, so that filling it up later does not page fault.
+pub fn prefault<T>(buffer: &mut Vec<T>) {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let start = buffer.as_mut_ptr() as *mut u8;
+    let length = buffer.capacity() * std::mem::size_of::<T>();
+    for offset in (0..length).step_by(page_size) {
+        // spare capacity is allocated but uninitialized, so it is fine to scribble over
+        unsafe { std::ptr::write_volatile(start.add(offset), 0) };
+    }
+}
+
+
+/// Locks the allocated capacity of `buffer` in RAM, as a narrower alternative to mlocking the whole address space.
+pub fn mlock_buffer<T>(buffer: &Vec<T>) -> error::Result<()> {
+    let length = buffer.capacity() * std::mem::size_of::<T>();
+    if length > 0 && unsafe { libc::mlock(buffer.as_ptr() as *const libc::c_void, length) } != 0 {
+        return Err(JitterError::Mlock { what: "sample buffer pages", source: std::io::Error::last_os_error() });
+    }
+
+    Ok(())
+}
+
+
+/// Asks for the allocated capacity of `buffer` to be backed by transparent huge pages, so that walking it does not
+/// miss the dTLB every 4KiB. Only the 2MiB aligned part of the buffer qualifies, and only pages faulted in after the
+/// call get backed by huge pages right away. Returns how many bytes the advice covers.
+pub fn advise_huge_pages<T>(buffer: &Vec<T>) -> std::io::Result<usize> {
+    let mode = std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")?;
+    if mode.contains("[never]") {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "transparent huge pages are disabled"));
+    }
+
+    let start = buffer.as_ptr() as usize;
+    let end = start + buffer.capacity() * std::mem::size_of::<T>();
+    let aligned_start = (start + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1);
+    let aligned_end = end & !(HUGE_PAGE_SIZE - 1);
+    if aligned_end <= aligned_start {
+        return Ok(0);
+    }
+
+    if unsafe { libc::madvise(aligned_start as *mut libc::c_void, aligned_end - aligned_start, libc::MADV_HUGEPAGE) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(aligned_end - aligned_start)
+}
+
+
+/// Time after which the kernel's hard-lockup detector fires on a cpu with interrupts disabled, if it is enabled.
+pub fn hard_lockup_threshold_millis() -> Option<i64> {
+    let read = |path| std::fs::read_to_string(path).ok().and_then(|value| value.trim().parse::<i64>().ok());
+    match (read("/proc/sys/kernel/nmi_watchdog"), read("/proc/sys/kernel/watchdog_thresh")) {
+        (Some(enabled), Some(threshold_seconds)) if enabled != 0 && threshold_seconds > 0 => Some(threshold_seconds * 1000),
+        _ => None,
+    }
+}
+
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn raise_io_privilege_level() -> error::Result<()> {
+    if unsafe { libc::iopl(3) } != 0 {
+        return Err(JitterError::IoPrivilege(std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+
+/// Keeps local interrupts of the current cpu disabled for as long as it lives. Interrupts get re-enabled when
+/// it is dropped, including while unwinding, and by a panic hook in case the panic is not unwound at all;
+/// otherwise a panic on a sampler thread would leave its core dead until reboot.
+pub struct InterruptGuard {
+    // interrupts are disabled on the cpu the creating thread is pinned to, so the guard must not leave it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    pub fn disable() -> InterruptGuard {
+        static PANIC_HOOK: Once = Once::new();
+        PANIC_HOOK.call_once(|| {
+            let previous_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if INTERRUPTS_DISABLED.with(|disabled| disabled.replace(false)) {
+                    enable_lapic();
+                }
+                previous_hook(info);
+            }));
+        });
+
+        INTERRUPTS_DISABLED.with(|disabled| disabled.set(true));
+        disable_lapic();
+        InterruptGuard { _not_send: PhantomData }
+    }
+
+    /// Briefly re-enables interrupts so that the pending ones get serviced and the hard-lockup watchdog stays quiet.
+    pub fn open_window(&self) {
+        enable_lapic();
+        let window_end = clock_monotonic() + INTERRUPT_WINDOW_NANOS;
+        while clock_monotonic() < window_end {}
+        disable_lapic();
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if INTERRUPTS_DISABLED.with(|disabled| disabled.replace(false)) {
+            enable_lapic();
+        }
     }
 }
 
@@ -107,3 +1267,18 @@ pub fn enable_lapic() {
     unsafe { asm!("sti", options(nomem)) }
 }
 
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn raise_io_privilege_level() -> error::Result<()> {
+    Err(JitterError::Unsupported("Disabling local interrupts"))
+}
+
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn disable_lapic() {
+    panic!("Disabling local interrupts is only supported on x86");
+}
+
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn enable_lapic() {}
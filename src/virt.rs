@@ -0,0 +1,74 @@
+use std::{fs, io, sync::Arc};
+
+use nix::libc;
+
+use crate::{jitter::Field, probe::IntervalProbe};
+
+const PROC_STAT: &str = "/proc/stat";
+// position of steal among the times following the cpuN label
+const STEAL_COLUMN: usize = 7;
+
+
+/// Name of the hypervisor the process runs under, if any: the vendor signature of CPUID leaf 0x40000000 when
+/// CPUID leaf 1 sets the hypervisor bit (ECX bit 31) on x86, /sys/hypervisor/type elsewhere.
+pub fn hypervisor() -> Option<String> {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::__cpuid;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::__cpuid;
+
+        #[allow(unused_unsafe)]
+        let (features, vendor) = unsafe { (__cpuid(1), __cpuid(0x4000_0000)) };
+        if features.ecx & (1 << 31) == 0 {
+            return None;
+        }
+        let signature: Vec<u8> = [vendor.ebx, vendor.ecx, vendor.edx].iter().flat_map(|register| register.to_le_bytes()).collect();
+        let signature = String::from_utf8_lossy(&signature).trim_end_matches('\0').trim().to_string();
+        Some(if signature.is_empty() { "unknown".to_string() } else { signature })
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    fs::read_to_string("/sys/hypervisor/type").ok().map(|hypervisor| hypervisor.trim().to_string()).filter(|hypervisor| !hypervisor.is_empty())
+}
+
+
+/// Publishes the time the hypervisor kept the sampled cpu from running the guest during every interval as `steal_time_us`,
+/// taken from the steal column of /proc/stat. Only ever non-zero in a guest whose hypervisor reports it (e.g. KVM steal time).
+pub struct StealProbe {
+    label: String,
+    micros_per_tick: i64,
+    name: Arc<str>,
+    previous: i64,
+}
+
+impl StealProbe {
+    pub fn new(cpu: u32) -> io::Result<StealProbe> {
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks_per_sec <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut probe = StealProbe { label: format!("cpu{}", cpu), micros_per_tick: 1_000_000 / ticks_per_sec as i64, name: Arc::from("steal_time_us"), previous: 0 };
+        probe.previous = probe.read()?;
+        Ok(probe)
+    }
+
+    fn read(&self) -> io::Result<i64> {
+        let stat = fs::read_to_string(PROC_STAT)?;
+        stat.lines()
+            .map(|line| line.split_whitespace())
+            .find_map(|mut columns| if columns.next() == Some(self.label.as_str()) { columns.nth(STEAL_COLUMN) } else { None })
+            .and_then(|steal| steal.parse::<i64>().ok())
+            .map(|steal| steal * self.micros_per_tick)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no steal time of {} in {}", self.label, PROC_STAT)))
+    }
+}
+
+impl IntervalProbe for StealProbe {
+    fn report(&mut self, fields: &mut Vec<Field>) {
+        let current = self.read().unwrap_or(self.previous);
+        fields.push(Field { name: self.name.clone(), value: current - self.previous });
+        self.previous = current;
+    }
+}
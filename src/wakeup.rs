@@ -0,0 +1,51 @@
+use nix::libc;
+
+use crate::{recorder::IntervalRecorder, utils::{self, ProgramArgs, NANOS_IN_SEC}};
+
+
+/// Cyclictest-like measurement: sleeps until an absolute CLOCK_MONOTONIC deadline every wakeup interval
+/// and records how late the thread actually got to run.
+pub fn wakeup_loop(program_args: &ProgramArgs, recorder: &mut IntervalRecorder) {
+    let wakeup_interval = program_args.wakeup_interval_micros * 1_000;
+    let realtime_offset = utils::clock_realtime() - monotonic_now();
+
+    let start = monotonic_now();
+    let deadline = if program_args.duration_seconds == 0 { i64::MAX } else { start + program_args.duration_seconds * NANOS_IN_SEC };
+    let report_interval = program_args.report_interval_micros * 1_000;
+    let mut next_report = start + report_interval;
+    let mut next_wakeup = start + wakeup_interval;
+
+    while next_wakeup <= deadline && !utils::stop_requested() {
+        sleep_until(next_wakeup);
+        let now = monotonic_now();
+        recorder.record(now - next_wakeup, now + realtime_offset);
+
+        if now > next_report {
+            next_report += report_interval * ((now - next_report) / report_interval + 1);
+            recorder.report(now + realtime_offset);
+        }
+
+        next_wakeup += wakeup_interval;
+        if next_wakeup < now {
+            // overran by more than a whole interval; skip the missed wakeups rather than firing them back to back
+            next_wakeup = now + wakeup_interval - (now - next_wakeup) % wakeup_interval;
+        }
+    }
+}
+
+
+fn monotonic_now() -> i64 {
+    let mut time_spec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time_spec);
+    }
+    time_spec.tv_sec * NANOS_IN_SEC + time_spec.tv_nsec
+}
+
+
+fn sleep_until(target: i64) {
+    let time_spec = libc::timespec { tv_sec: target / NANOS_IN_SEC, tv_nsec: target % NANOS_IN_SEC };
+    unsafe {
+        while libc::clock_nanosleep(libc::CLOCK_MONOTONIC, libc::TIMER_ABSTIME, &time_spec, std::ptr::null_mut()) == libc::EINTR {}
+    }
+}
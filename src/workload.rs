@@ -0,0 +1,93 @@
+use std::hint::black_box;
+
+const CACHE_LINE_BYTES: usize = 64;
+const SLOTS_PER_CACHE_LINE: usize = CACHE_LINE_BYTES / std::mem::size_of::<usize>();
+const INT_CHAIN_LENGTH: usize = 16;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    Empty,
+    IntChain,
+    PointerChase,
+    CacheLine,
+}
+
+
+#[repr(align(64))]
+struct CacheLine([u64; 8]);
+
+
+/// Work executed by the busy loop between two consecutive reads of the clock.
+/// Each workload makes a different class of stalls (SMIs, cache/memory, scheduler) stand out.
+pub struct WorkloadKernel {
+    workload: Workload,
+    chain: Vec<usize>,
+    position: usize,
+    accumulator: u64,
+    line: Box<CacheLine>,
+}
+
+impl WorkloadKernel {
+    pub fn new(workload: Workload, working_set_kib: usize) -> WorkloadKernel {
+        let chain = if workload == Workload::PointerChase {
+            random_cycle((working_set_kib * 1024 / CACHE_LINE_BYTES).max(2))
+        } else {
+            Vec::new()
+        };
+
+        WorkloadKernel { workload, chain, position: 0, accumulator: 1, line: Box::new(CacheLine([0; 8])) }
+    }
+
+    #[inline(always)]
+    pub fn step(&mut self) {
+        match self.workload {
+            Workload::Empty => {},
+            Workload::IntChain => {
+                let mut value = self.accumulator;
+                for _ in 0..INT_CHAIN_LENGTH {
+                    value = black_box(value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407));
+                }
+                self.accumulator = value;
+            },
+            Workload::PointerChase => {
+                self.position = black_box(self.chain[self.position]);
+            },
+            Workload::CacheLine => {
+                flush_cache_line(&self.line.0[0]);
+                self.accumulator = self.accumulator.wrapping_add(unsafe { std::ptr::read_volatile(&self.line.0[0]) });
+            },
+        }
+    }
+}
+
+
+// Sattolo's algorithm: a single random cycle through all cache lines of the working set, so the hardware prefetcher
+// cannot guess the next access and every hop has to wait for the previous one.
+fn random_cycle(cache_lines: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..cache_lines).collect();
+    for i in (1..cache_lines).rev() {
+        order.swap(i, fastrand::usize(0..i));
+    }
+
+    let mut chain = vec![0usize; cache_lines * SLOTS_PER_CACHE_LINE];
+    for i in 0..cache_lines {
+        chain[order[i] * SLOTS_PER_CACHE_LINE] = order[(i + 1) % cache_lines] * SLOTS_PER_CACHE_LINE;
+    }
+
+    chain
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn flush_cache_line(ptr: &u64) {
+    unsafe { std::arch::x86_64::_mm_clflush(ptr as *const u64 as *const u8) }
+}
+
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+fn flush_cache_line(ptr: &u64) {
+    black_box(ptr);
+}